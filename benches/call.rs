@@ -0,0 +1,59 @@
+//! Times a CALL round trip over an in-memory peer, so payload copies on the
+//! send path show up in the numbers. Run with `cargo bench --bench call`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::thread;
+use std::time::Instant;
+
+use wampproto::messages::call::Call;
+use wampproto::messages::result::Result_;
+use wampproto::serializers::json::JSONSerializer;
+use wampproto::serializers::serializer::Serializer;
+use xconn::sync::memory::MemoryPeer;
+use xconn::sync::peer::Peer;
+use xconn::sync::session::Session;
+use xconn::sync::types::{CallRequest, SessionDetails, Value};
+
+const ITERATIONS: u32 = 2_000;
+
+fn main() {
+    let (client, router) = MemoryPeer::pair();
+    let details = SessionDetails::new(1, "realm1".to_string(), "bench".to_string(), "anonymous".to_string());
+    let session = Session::new(details, client, Box::new(JSONSerializer {}));
+
+    // answers every CALL with an empty RESULT so the client side dominates
+    thread::spawn(move || {
+        let serializer = JSONSerializer {};
+        while let Ok(payload) = router.read() {
+            let Ok(msg) = serializer.deserialize(payload) else {
+                continue;
+            };
+            let Some(call) = msg.as_any().downcast_ref::<Call>() else {
+                continue;
+            };
+            let result = Result_ {
+                request_id: call.request_id,
+                details: Default::default(),
+                args: None,
+                kwargs: None,
+            };
+            if router.write(serializer.serialize(&result).unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+
+    for size in [16, 1024, 64 * 1024] {
+        let payload = "x".repeat(size);
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            let request = CallRequest::new("io.xconn.bench")
+                .args(vec![Value::Str(payload.clone())])
+                .kwargs(HashMap::from([("blob".to_string(), Value::Str(payload.clone()))]));
+            black_box(session.call(request).unwrap());
+        }
+        let per_call = started.elapsed() / ITERATIONS;
+        println!("call with {size} byte payload: {per_call:?}/iter");
+    }
+}
@@ -8,6 +8,10 @@ async fn main() {
         .unwrap_or_else(|e| panic!("{e}"));
 
     async fn registration_handler(inv: Invocation) -> Yield {
+        if inv.args.is_empty() {
+            return Yield::error("io.xconn.error.invalid_argument");
+        }
+
         Yield::new(inv.args, inv.kwargs)
     }
 
@@ -22,6 +26,26 @@ async fn main() {
     let response = session.call(call_request).await.unwrap();
     println!("args={:?}, kwargs={:?}", response.args, response.kwargs);
 
+    // calling without arguments makes the handler return an error
+    let response = session.call(CallRequest::new("io.xconn.echo")).await.unwrap();
+    println!("error={:?}", response.error);
+
+    // the session can be shared between tasks to issue calls concurrently
+    let mut tasks = Vec::new();
+    for i in 0..5 {
+        let session = session.clone();
+        tasks.push(tokio::spawn(async move {
+            session.call(CallRequest::new("io.xconn.echo").arg(i)).await
+        }));
+    }
+
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(response) => println!("args={:?}", response.args),
+            Err(e) => println!("{e}"),
+        }
+    }
+
     async fn event_handler(event: Event) {
         println!("received event {event:?}")
     }
@@ -1,16 +1,39 @@
 use crate::async_::joiner::{RawSocketJoiner, WebSocketJoiner};
-use crate::async_::session::Session;
-use crate::common::types::{CBORSerializerSpec, Error, SerializerSpec};
+use crate::async_::peer::Peer;
+use crate::async_::session::{JoinFuture, Reconnect, Session, SessionOptions};
+use crate::common::types::{
+    CBORSerializerSpec, DEFAULT_CONNECT_TIMEOUT, Error, ErrorFn, JoinRetry, KeepAlive, MessageFn,
+    MsgPackSerializerSpec, ReconnectEvent, ReconnectFn, ReconnectPolicy, Revocation, RevokedFn, SerializerSpec,
+    SessionDetails, TlsConfig,
+};
+use std::sync::Arc;
+use std::time::Duration;
 
 use wampproto::authenticators::anonymous::AnonymousAuthenticator;
 use wampproto::authenticators::authenticator::ClientAuthenticator;
 use wampproto::authenticators::cryptosign::CryptoSignAuthenticator;
+use wampproto::authenticators::scram::SCRAMAuthenticator;
 use wampproto::authenticators::ticket::TicketAuthenticator;
 use wampproto::authenticators::wampcra::WAMPCRAAuthenticator;
+use wampproto::messages::message::Message;
 
 pub struct Client {
     serializer: Box<dyn SerializerSpec>,
     authenticator: Box<dyn ClientAuthenticator>,
+    connect_timeout: Duration,
+    tls: Option<TlsConfig>,
+    keepalive: Option<KeepAlive>,
+    reconnect: Option<ReconnectPolicy>,
+    reconnect_listener: Option<ReconnectFn>,
+    max_concurrent_invocations: Option<usize>,
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: Option<usize>,
+    call_buffer_size: Option<usize>,
+    strict_uris: bool,
+    manual_dispatch: bool,
+    join_retry: Option<JoinRetry>,
 }
 
 impl Client {
@@ -18,44 +41,319 @@ impl Client {
         Self {
             serializer,
             authenticator,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            tls: None,
+            keepalive: None,
+            reconnect: None,
+            reconnect_listener: None,
+            max_concurrent_invocations: None,
+            error_handler: None,
+            message_handler: None,
+            revoked_handler: None,
+            event_buffer_size: None,
+            call_buffer_size: None,
+            strict_uris: false,
+            manual_dispatch: false,
+            join_retry: None,
         }
     }
 
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
     pub async fn connect(self, uri: &str, realm: &str) -> Result<Session, Error> {
+        let (peer, details) = self.join(uri, realm).await?;
+        let serializer = self.serializer.serializer();
+
+        let mut options = SessionOptions {
+            reconnect: None,
+            max_concurrent_invocations: self.max_concurrent_invocations,
+            error_handler: self.error_handler.clone(),
+            message_handler: self.message_handler.clone(),
+            revoked_handler: self.revoked_handler.clone(),
+            event_buffer_size: self.event_buffer_size,
+            call_buffer_size: self.call_buffer_size,
+            subprotocol: Some(self.serializer.subprotocol()),
+            strict_uris: self.strict_uris,
+            manual_dispatch: self.manual_dispatch,
+        };
+
+        let Some(policy) = self.reconnect else {
+            return Ok(Session::with_options(details, peer, serializer, options));
+        };
+
+        let listener = self.reconnect_listener.clone();
+        let client = Arc::new(self);
+        let (uri, realm) = (uri.to_string(), realm.to_string());
+        options.reconnect = Some(Reconnect {
+            policy,
+            join: Arc::new(move || -> JoinFuture {
+                let (client, uri, realm) = (client.clone(), uri.clone(), realm.clone());
+                Box::pin(async move { client.join(&uri, &realm).await })
+            }),
+            listener,
+        });
+
+        Ok(Session::with_options(details, peer, serializer, options))
+    }
+
+    async fn join(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
+        let serializer = self.serializer.clone();
+        let authenticator = self.authenticator.clone();
+
         if uri.starts_with("ws://") || uri.starts_with("wss://") {
-            let serializer = self.serializer.serializer();
-            let joiner = WebSocketJoiner::new(self.serializer, self.authenticator);
-            let (peer, details) = joiner.join(uri, realm).await.map_err(|e| Error::new(e.to_string()))?;
-            Ok(Session::new(details, peer, serializer))
+            let mut joiner = WebSocketJoiner::new(serializer, authenticator).with_connect_timeout(self.connect_timeout);
+            if let Some(tls) = self.tls.clone() {
+                joiner = joiner.with_tls_config(tls)?;
+            }
+            if let Some(retry) = self.join_retry {
+                joiner = joiner.with_join_retry(retry);
+            }
+            if let Some(keepalive) = self.keepalive {
+                joiner = joiner
+                    .with_keepalive(keepalive.interval)
+                    .with_keepalive_max_missed(keepalive.max_missed_pongs);
+            }
+            joiner.join(uri, realm).await.map_err(|e| Error::new(e.to_string()))
         } else if uri.starts_with("rs://")
             || uri.starts_with("rss://")
             || uri.starts_with("tcp://")
             || uri.starts_with("tcps://")
         {
-            let serializer = self.serializer.serializer();
-            let joiner = RawSocketJoiner::new(self.serializer, self.authenticator);
-            let (peer, details) = joiner.join(uri, realm).await.map_err(|e| Error::new(e.to_string()))?;
-            Ok(Session::new(details, peer, serializer))
+            let mut joiner = RawSocketJoiner::new(serializer, authenticator).with_connect_timeout(self.connect_timeout);
+            if let Some(tls) = self.tls.clone() {
+                joiner = joiner.with_tls_config(tls);
+            }
+            if let Some(retry) = self.join_retry {
+                joiner = joiner.with_join_retry(retry);
+            }
+            joiner.join(uri, realm).await.map_err(|e| Error::new(e.to_string()))
         } else {
             Err(Error::new("Invalid URI scheme".to_string()))
         }
     }
 }
 
+/// Anonymous authentication over CBOR, like the authenticated `connect_*`
+/// helpers. The sync client defaults to JSON instead.
 impl Default for Client {
     fn default() -> Self {
-        Self {
-            serializer: Box::new(CBORSerializerSpec {}),
-            authenticator: Box::new(AnonymousAuthenticator::new("", Default::default())),
+        Self::new(
+            Box::new(CBORSerializerSpec {}),
+            Box::new(AnonymousAuthenticator::new("", Default::default())),
+        )
+    }
+}
+
+/// Fluent configuration for a `Client`. Anything left unset falls back to the
+/// same defaults as `Client::default()`.
+#[derive(Default)]
+pub struct ClientBuilder {
+    serializer: Option<Box<dyn SerializerSpec>>,
+    authenticator: Option<Box<dyn ClientAuthenticator>>,
+    connect_timeout: Option<Duration>,
+    tls: Option<TlsConfig>,
+    keepalive: Option<KeepAlive>,
+    reconnect: Option<ReconnectPolicy>,
+    reconnect_listener: Option<ReconnectFn>,
+    max_concurrent_invocations: Option<usize>,
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: Option<usize>,
+    call_buffer_size: Option<usize>,
+    strict_uris: bool,
+    manual_dispatch: bool,
+    join_retry: Option<JoinRetry>,
+}
+
+impl ClientBuilder {
+    pub fn serializer(mut self, serializer: Box<dyn SerializerSpec>) -> Self {
+        self.serializer = Some(serializer);
+        self
+    }
+
+    pub fn authenticator(mut self, authenticator: Box<dyn ClientAuthenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Upper bound for the TCP connect and the transport handshake, defaults
+    /// to `DEFAULT_CONNECT_TIMEOUT`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// TLS settings for `wss://`, `rss://` and `tcps://` URIs.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// WebSocket keepalive; ignored for rawsocket transports.
+    pub fn keepalive(mut self, keepalive: KeepAlive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Reconnects automatically after the connection dropped and restores
+    /// all registrations and subscriptions. Calls that are in flight when the
+    /// connection drops fail and are not retried.
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Gets notified about reconnect attempts and their outcome.
+    pub fn on_reconnect<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(ReconnectEvent) + Send + Sync + 'static,
+    {
+        self.reconnect_listener = Some(ReconnectFn(Arc::new(callback)));
+        self
+    }
+
+    /// Limits how many invocation and event handlers run at the same time.
+    /// Further invocations and events wait until a handler finished, and the
+    /// session stops reading meanwhile. A handler that waits for the answer
+    /// to a call of its own therefore needs a free slot left for others. A
+    /// limit of 0 is treated as 1.
+    pub fn max_concurrent_invocations(mut self, limit: usize) -> Self {
+        self.max_concurrent_invocations = Some(limit);
+        self
+    }
+
+    /// Receives errors from the session's background work that have no caller
+    /// to be returned to, like failing to send an invocation result. Without a
+    /// handler these are dropped.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(ErrorFn(Arc::new(callback)));
+        self
+    }
+
+    /// Sees every message the router sends before the session handles it. The
+    /// hook runs on the reader, so it should return quickly.
+    pub fn on_message<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&dyn Message) + Send + Sync + 'static,
+    {
+        self.message_handler = Some(MessageFn(Arc::new(callback)));
+        self
+    }
+
+    /// Told when the router revokes a registration or subscription on its own,
+    /// e.g. because an admin removed it. The session has already dropped the
+    /// handler by then. The hook runs on the reader, so it should return quickly.
+    pub fn on_revoked<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Revocation) + Send + Sync + 'static,
+    {
+        self.revoked_handler = Some(RevokedFn(Arc::new(callback)));
+        self
+    }
+
+    /// How many events an ordered subscription buffers while its handler is
+    /// busy, defaults to `DEFAULT_EVENT_BUFFER_SIZE`. When the buffer is full
+    /// the session stops reading from the connection until the handler caught
+    /// up, which also holds back call results and other events.
+    pub fn event_buffer_size(mut self, size: usize) -> Self {
+        self.event_buffer_size = Some(size);
+        self
+    }
+
+    /// How many progressive results a call buffers until the caller gets to
+    /// them, defaults to `DEFAULT_CALL_BUFFER_SIZE`. Progress callbacks run
+    /// on the caller's side while it waits for the final result, so a slow
+    /// callback only fills its own call's buffer. Once that is full the
+    /// session stops reading from the connection until the callback caught
+    /// up, which also holds back other results and events. A value of 0 is
+    /// treated as 1. Publishes and (un)registrations expect a single answer
+    /// and are never buffered beyond it.
+    pub fn call_buffer_size(mut self, size: usize) -> Self {
+        self.call_buffer_size = Some(size);
+        self
+    }
+
+    /// Checks outgoing URIs against the strict WAMP rules, i.e. only
+    /// `[0-9a-z_]` in components, instead of the loose ones. Either way an
+    /// invalid URI fails locally before anything is sent.
+    pub fn strict_uris(mut self, strict: bool) -> Self {
+        self.strict_uris = strict;
+        self
+    }
+
+    /// Queues invocations and events instead of spawning a task for each.
+    /// They run one after another on whichever task calls
+    /// `Session::poll_once` or `Session::run`, which makes handler execution
+    /// deterministic, e.g. in tests. Ordered subscriptions use the same queue
+    /// instead of a task of their own, stream subscriptions are not affected.
+    pub fn manual_dispatch(mut self) -> Self {
+        self.manual_dispatch = true;
+        self
+    }
+
+    /// Retries connecting when the connection drops before the router
+    /// welcomed the session. A rejected join is never retried.
+    pub fn join_retry(mut self, retry: JoinRetry) -> Self {
+        self.join_retry = Some(retry);
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let default = Client::default();
+        Client {
+            serializer: self.serializer.unwrap_or(default.serializer),
+            authenticator: self.authenticator.unwrap_or(default.authenticator),
+            connect_timeout: self.connect_timeout.unwrap_or(default.connect_timeout),
+            tls: self.tls,
+            keepalive: self.keepalive,
+            reconnect: self.reconnect,
+            reconnect_listener: self.reconnect_listener,
+            max_concurrent_invocations: self.max_concurrent_invocations,
+            error_handler: self.error_handler,
+            message_handler: self.message_handler,
+            revoked_handler: self.revoked_handler,
+            event_buffer_size: self.event_buffer_size,
+            call_buffer_size: self.call_buffer_size,
+            strict_uris: self.strict_uris,
+            manual_dispatch: self.manual_dispatch,
+            join_retry: self.join_retry,
         }
     }
 }
 
+/// Connects with the given serializer and authenticator, e.g. a custom one
+/// not covered by the `connect_*` helpers below.
+pub async fn connect_with(
+    uri: &str,
+    realm: &str,
+    serializer: Box<dyn SerializerSpec>,
+    authenticator: Box<dyn ClientAuthenticator>,
+) -> Result<Session, Error> {
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm).await
+}
+
+/// Joins anonymously using CBOR, see `Client::default`.
 pub async fn connect_anonymous(uri: &str, realm: &str) -> Result<Session, Error> {
     let client = Client::default();
     client.connect(uri, realm).await
 }
 
+/// Joins anonymously using MessagePack.
+pub async fn connect_anonymous_msgpack(uri: &str, realm: &str) -> Result<Session, Error> {
+    let serializer = Box::new(MsgPackSerializerSpec {});
+    let authenticator = Box::new(AnonymousAuthenticator::new("", Default::default()));
+
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm).await
+}
+
 pub async fn connect_ticket(uri: &str, realm: &str, authid: &str, ticket: &str) -> Result<Session, Error> {
     let serializer = Box::new(CBORSerializerSpec {});
     let authenticator = Box::new(TicketAuthenticator::new(authid, ticket, Default::default()));
@@ -72,6 +370,14 @@ pub async fn connect_wampcra(uri: &str, realm: &str, authid: &str, secret: &str)
     client.connect(uri, realm).await
 }
 
+pub async fn connect_scram(uri: &str, realm: &str, authid: &str, secret: &str) -> Result<Session, Error> {
+    let serializer = Box::new(CBORSerializerSpec {});
+    let authenticator = Box::new(SCRAMAuthenticator::new(authid, secret, Default::default()));
+
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm).await
+}
+
 pub async fn connect_cryptosign(uri: &str, realm: &str, authid: &str, private_key_hex: &str) -> Result<Session, Error> {
     let serializer = Box::new(CBORSerializerSpec {});
     let authenticator = CryptoSignAuthenticator::try_new(authid, private_key_hex, Default::default())
@@ -1,19 +1,41 @@
 use crate::async_::peer::Peer;
-use crate::async_::rawsocket::connect_rawsocket;
+use crate::async_::rawsocket;
+use crate::async_::rawsocket::dial;
 use crate::async_::websocket::WebSocketPeer;
-use crate::common::types::{Error, JSONSerializerSpec, SerializerSpec, SessionDetails};
-use futures_util::{StreamExt, TryFutureExt};
-use tokio_tungstenite::connect_async_with_config;
+use crate::common::joiner::{
+    JoinError, inspect_join_reply, select_serializer, upgrade_error, with_roles, with_welcome_details,
+};
+use crate::common::types::{
+    DEFAULT_CONNECT_TIMEOUT, Error, JSONSerializerSpec, JoinRetry, KeepAlive, SerializerSpec, SessionDetails,
+    TlsConfig, Value,
+};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{Connector, WebSocketStream, client_async_with_config, connect_async_tls_with_config};
 use tungstenite::ClientRequestBuilder;
+use tungstenite::handshake::client::Response;
+use tungstenite::http::Uri;
 use tungstenite::protocol::WebSocketConfig;
 use wampproto::authenticators::anonymous::AnonymousAuthenticator;
 use wampproto::authenticators::authenticator::ClientAuthenticator;
 use wampproto::joiner;
 use wampproto::serializers::serializer::Serializer;
 
+/// Joins a realm over WebSocket. permessage-deflate is not offered during the
+/// handshake because tungstenite does not implement it yet, so the size limits
+/// below always apply to uncompressed payloads.
 pub struct WebSocketJoiner {
-    serializer: Box<dyn SerializerSpec>,
+    serializers: Vec<Box<dyn SerializerSpec>>,
     authenticator: Box<dyn ClientAuthenticator>,
+    keepalive: Option<KeepAlive>,
+    tls: Option<Connector>,
+    connect_timeout: Duration,
+    config: WebSocketConfig,
+    retry: Option<JoinRetry>,
+    roles: Option<HashMap<String, Value>>,
 }
 
 impl Default for WebSocketJoiner {
@@ -28,23 +50,201 @@ impl Default for WebSocketJoiner {
 impl WebSocketJoiner {
     pub fn new(serializer: Box<dyn SerializerSpec>, authenticator: Box<dyn ClientAuthenticator>) -> Self {
         Self {
-            serializer,
+            serializers: vec![serializer],
             authenticator,
+            keepalive: None,
+            tls: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            config: WebSocketConfig::default(),
+            retry: None,
+            roles: None,
+        }
+    }
+
+    /// Offers all given serializers in order of preference and uses the one
+    /// the router selects. Replaces the serializer passed to `new`.
+    pub fn with_serializers(mut self, serializers: Vec<Box<dyn SerializerSpec>>) -> Self {
+        if !serializers.is_empty() {
+            self.serializers = serializers;
+        }
+        self
+    }
+
+    /// Upper bound for the TCP connect and the transport handshake.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Announces `roles` in HELLO instead of the default client roles, e.g.
+    /// only `subscriber` for a session that never publishes, or without a
+    /// feature the router rejects. Keys are role names, values the role
+    /// details, like `{"subscriber": {"features": {}}}`.
+    pub fn with_roles(mut self, roles: HashMap<String, Value>) -> Self {
+        self.roles = Some(roles);
+        self
+    }
+
+    /// Retries the connect and join when the connection fails before the
+    /// session was welcomed. Every attempt starts with a fresh copy of the
+    /// authenticator, so challenge-response state is never reused.
+    pub fn with_join_retry(mut self, retry: JoinRetry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Largest message accepted from the router. Defaults to 64 MiB.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.config = self.config.max_message_size(Some(max_message_size));
+        self
+    }
+
+    /// Largest single frame accepted from the router. Defaults to 16 MiB.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.config = self.config.max_frame_size(Some(max_frame_size));
+        self
+    }
+
+    /// Uses the given connector for `wss://` connections instead of the
+    /// system defaults.
+    pub fn with_tls(mut self, connector: Connector) -> Self {
+        self.tls = Some(connector);
+        self
+    }
+
+    /// Convenience over `with_tls` that builds the connector from a `TlsConfig`,
+    /// e.g. to trust a private CA loaded from PEM.
+    pub fn with_tls_config(self, tls: TlsConfig) -> Result<Self, Error> {
+        Ok(self.with_tls(Connector::NativeTls(tls.connector()?)))
+    }
+
+    /// Sends a websocket ping every `interval` to keep idle connections alive.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(KeepAlive::new(interval));
+        self
+    }
+
+    /// Number of unanswered pings after which the connection is closed. Only
+    /// has an effect together with `with_keepalive`.
+    pub fn with_keepalive_max_missed(mut self, max_missed_pongs: u32) -> Self {
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.max_missed_pongs = max_missed_pongs;
         }
+        self
     }
 
     pub async fn join(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
-        let uri = uri.parse().unwrap();
-        let request = ClientRequestBuilder::new(uri).with_sub_protocol(self.serializer.subprotocol());
-        let config = Some(WebSocketConfig::default());
+        let (peer, details, _) = self.join_negotiated(uri, realm).await?;
+        Ok((peer, details))
+    }
+
+    /// Like `join`, but also returns the serializer the router selected, which
+    /// is needed to create the `Session` when several were offered.
+    pub async fn join_negotiated(
+        &self,
+        uri: &str,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), Error> {
+        let uri: Uri = uri.parse().map_err(|e| Error::new(format!("invalid uri: {e}")))?;
+        retry(self.retry, || self.join_negotiated_once(uri.clone(), realm)).await
+    }
+
+    /// Like `join_negotiated`, but runs the WebSocket upgrade and the WAMP
+    /// handshake over an already established `stream`, e.g. a tunnel or an
+    /// in-memory pipe, instead of dialing `uri`. The uri only fills the
+    /// upgrade request. TLS and join retries do not apply, the caller owns
+    /// the stream and it cannot be reopened.
+    pub async fn join_over<S>(
+        &self,
+        stream: S,
+        uri: &str,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+    {
+        let uri: Uri = uri.parse().map_err(|e| Error::new(format!("invalid uri: {e}")))?;
+        let upgrade = client_async_with_config(self.request(uri), stream, Some(self.config));
+        let (ws, response) = tokio::time::timeout(self.connect_timeout, upgrade)
+            .await
+            .map_err(|_| Error::new("upgrade timed out"))?
+            .map_err(|e| self.upgrade_error(e))?;
+
+        Ok(self.join_upgraded(ws, &response, realm).await?)
+    }
+
+    fn request(&self, uri: Uri) -> ClientRequestBuilder {
+        let mut request = ClientRequestBuilder::new(uri);
+        for serializer in &self.serializers {
+            request = request.with_sub_protocol(serializer.subprotocol());
+        }
+        request
+    }
+
+    fn upgrade_error(&self, e: tungstenite::Error) -> JoinError {
+        let requested: Vec<String> = self.serializers.iter().map(|spec| spec.subprotocol()).collect();
+        upgrade_error(e, &requested)
+    }
+
+    async fn join_negotiated_once(
+        &self,
+        uri: Uri,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), JoinError> {
+        let config = Some(self.config);
 
-        let (ws, _) = connect_async_with_config(request, config, false)
+        let connect = connect_async_tls_with_config(self.request(uri), config, false, self.tls.clone());
+        let (ws, response) = tokio::time::timeout(self.connect_timeout, connect)
             .await
-            .map_err(|e| Error::new(format!("failed to connect: {e}")))?;
+            .map_err(|_| JoinError::Transient(Error::new("connect timed out")))?
+            .map_err(|e| self.upgrade_error(e))?;
+
+        self.join_upgraded(ws, &response, realm).await
+    }
+
+    /// Selects the serializer from the upgrade response and joins `realm`.
+    async fn join_upgraded<S>(
+        &self,
+        ws: WebSocketStream<S>,
+        response: &Response,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), JoinError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+    {
+        let selected = response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|protocol| protocol.to_str().ok());
+        let serializer = select_serializer(&self.serializers, selected).map_err(JoinError::Rejected)?;
+
         let (writer, reader) = ws.split();
-        let peer = WebSocketPeer::new(reader, writer, self.serializer.is_binary());
+        let peer = WebSocketPeer::new(reader, writer, serializer.is_binary(), self.keepalive);
         let auth = self.authenticator.clone();
-        join(peer, realm, self.serializer.serializer(), auth).await
+        let (peer, details) = join_once(peer, realm, serializer.serializer(), auth, self.roles.as_ref()).await?;
+        Ok((peer, details, serializer))
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails for good or the retries are used up.
+async fn retry<T, F, Fut>(retry: Option<JoinRetry>, attempt: F) -> Result<T, Error>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, JoinError>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Ok(joined) => return Ok(joined),
+            Err(JoinError::Transient(e)) => match retry {
+                Some(retry) if retries < retry.attempts => {
+                    retries += 1;
+                    tokio::time::sleep(retry.delay(retries)).await;
+                }
+                _ => return Err(e),
+            },
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
@@ -54,22 +254,50 @@ pub async fn join(
     serializer: Box<dyn Serializer>,
     authenticator: Box<dyn ClientAuthenticator>,
 ) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
+    Ok(join_once(peer, realm, serializer, authenticator, None).await?)
+}
+
+async fn join_once(
+    peer: Box<dyn Peer>,
+    realm: &str,
+    serializer: Box<dyn Serializer>,
+    authenticator: Box<dyn ClientAuthenticator>,
+    roles: Option<&HashMap<String, Value>>,
+) -> Result<(Box<dyn Peer>, SessionDetails), JoinError> {
     let mut proto = joiner::Joiner::new(realm, serializer.clone(), authenticator);
 
     let hello_raw = proto
         .send_hello()
-        .map_err(|e| Error::new(format!("failed to send hello: {e}")))?;
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to send hello: {e}"))))?;
+    let hello_raw = match roles {
+        Some(roles) => with_roles(serializer.as_ref(), hello_raw, roles).map_err(JoinError::Rejected)?,
+        None => hello_raw,
+    };
 
-    peer.write(hello_raw).await?;
+    peer.write(hello_raw).await.map_err(JoinError::Transient)?;
 
+    let mut welcome = None;
     loop {
         let reply = peer
             .read()
             .await
-            .map_err(|e| Error::new(format!("failed to read: {e}")))?;
+            .map_err(|e| JoinError::Transient(Error::new(format!("failed to read: {e}"))))?;
+
+        // parsed once, then inspected and handed to the protocol joiner
+        let msg = serializer
+            .deserialize(reply)
+            .map_err(|e| JoinError::Rejected(Error::new(format!("failed to join: {e}"))))?;
+        if let Some(details) = inspect_join_reply(msg.as_ref()).map_err(JoinError::Rejected)? {
+            welcome = Some(details);
+        }
 
-        match proto.receive(reply) {
-            Ok(Some(to_send)) => peer.write(to_send).await?,
+        match proto.receive_message(msg) {
+            Ok(Some(to_send)) => {
+                let to_send = serializer
+                    .serialize(to_send.as_ref())
+                    .map_err(|e| JoinError::Rejected(Error::new(format!("failed to join: {e}"))))?;
+                peer.write(to_send).await.map_err(JoinError::Transient)?
+            }
             Ok(None) => {
                 if let Ok(Some(details)) = proto.session_details() {
                     let details = SessionDetails::new(
@@ -78,11 +306,12 @@ pub async fn join(
                         details.authid.to_string(),
                         details.auth_role.to_string(),
                     );
+                    let details = with_welcome_details(details, welcome.take());
 
                     return Ok((peer, details));
                 }
             }
-            Err(e) => return Err(Error::new(format!("failed to join: {e}"))),
+            Err(e) => return Err(JoinError::Rejected(Error::new(format!("failed to join: {e}")))),
         }
     }
 }
@@ -90,6 +319,10 @@ pub async fn join(
 pub struct RawSocketJoiner {
     serializer: Box<dyn SerializerSpec>,
     authenticator: Box<dyn ClientAuthenticator>,
+    tls: Option<TlsConfig>,
+    connect_timeout: Duration,
+    retry: Option<JoinRetry>,
+    roles: Option<HashMap<String, Value>>,
 }
 
 impl Default for RawSocketJoiner {
@@ -106,14 +339,97 @@ impl RawSocketJoiner {
         Self {
             serializer,
             authenticator,
+            tls: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            retry: None,
+            roles: None,
         }
     }
 
+    /// Upper bound for the TCP connect and the transport handshake.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Announces `roles` in HELLO instead of the default client roles, see
+    /// `WebSocketJoiner::with_roles`.
+    pub fn with_roles(mut self, roles: HashMap<String, Value>) -> Self {
+        self.roles = Some(roles);
+        self
+    }
+
+    /// Retries the connect and join when the connection fails before the
+    /// session was welcomed, with a fresh copy of the authenticator each time.
+    pub fn with_join_retry(mut self, retry: JoinRetry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Uses the given TLS settings for `rss://` and `tcps://` connections
+    /// instead of the system defaults.
+    pub fn with_tls_config(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     pub async fn join(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
-        let peer = connect_rawsocket(uri, self.serializer.clone())
-            .map_err(|e| Error::new(format!("failed to connect: {e}")))
-            .await?;
+        retry(self.retry, || self.join_once(uri, realm)).await
+    }
+
+    /// Like `join`, but runs the rawsocket and WAMP handshakes over an
+    /// already established `stream`, e.g. a tunnel or an in-memory pipe,
+    /// instead of dialing. TLS and join retries do not apply, the caller owns
+    /// the stream and it cannot be reopened.
+    pub async fn join_over<S>(&self, stream: S, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+    {
+        let handshake = rawsocket::handshake(stream, self.serializer.serializer_id());
+        let peer = tokio::time::timeout(self.connect_timeout, handshake)
+            .await
+            .map_err(|_| Error::new("handshake timed out"))??;
+
+        Ok(join_once(
+            peer,
+            realm,
+            self.serializer.serializer(),
+            self.authenticator.clone(),
+            self.roles.as_ref(),
+        )
+        .await?)
+    }
+
+    async fn join_once(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), JoinError> {
+        let connect = dial(uri, self.serializer.as_ref(), self.tls.clone());
+        let peer = tokio::time::timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| JoinError::Transient(Error::new("connect timed out")))??;
+
+        join_once(
+            peer,
+            realm,
+            self.serializer.serializer(),
+            self.authenticator.clone(),
+            self.roles.as_ref(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn websocket_join_rejects_a_malformed_uri() {
+        assert!(WebSocketJoiner::default().join("not a url", "realm1").await.is_err());
+    }
 
-        join(peer, realm, self.serializer.serializer(), self.authenticator.clone()).await
+    #[tokio::test]
+    async fn rawsocket_join_rejects_a_malformed_uri() {
+        let joiner = RawSocketJoiner::default();
+        assert!(joiner.join("not a url", "realm1").await.is_err());
+        assert!(joiner.join("rs://localhost", "realm1").await.is_err());
     }
 }
@@ -0,0 +1,222 @@
+use crate::async_::peer::Peer;
+use crate::common::types::{Error, TRANSPORT_MEMORY, TransportType};
+use async_trait::async_trait;
+use tokio::sync::{Mutex, mpsc};
+
+/// One end of an in-memory connection, mainly useful to drive a `Session`
+/// from tests without a router. Whatever is written to one end of a `pair`
+/// is read from the other.
+#[derive(Debug)]
+pub struct MemoryPeer {
+    reader: Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    writer: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+#[async_trait]
+impl Peer for MemoryPeer {
+    fn kind(&self) -> TransportType {
+        TRANSPORT_MEMORY
+    }
+
+    async fn read(&self) -> Result<Vec<u8>, Error> {
+        self.reader
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| Error::new("connection closed"))
+    }
+
+    async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.writer
+            .send(data)
+            .map_err(|e| Error::new(format!("write error: {e}")))
+    }
+}
+
+impl MemoryPeer {
+    /// Creates two connected peers. Dropping one end closes the other.
+    pub fn pair() -> (Box<dyn Peer>, Box<dyn Peer>) {
+        let (left_writer, right_reader) = mpsc::unbounded_channel();
+        let (right_writer, left_reader) = mpsc::unbounded_channel();
+
+        let left = MemoryPeer {
+            reader: Mutex::new(left_reader),
+            writer: left_writer,
+        };
+        let right = MemoryPeer {
+            reader: Mutex::new(right_reader),
+            writer: right_writer,
+        };
+
+        (Box::new(left), Box::new(right))
+    }
+}
+
+/// A router for tests that serves any number of sessions over memory peers,
+/// the routing itself is done by `common::router::Realm`.
+#[cfg(test)]
+pub(crate) mod router {
+    use super::MemoryPeer;
+    use crate::async_::peer::Peer;
+    use crate::async_::session::{Session, SessionOptions};
+    use crate::common::router::Realm;
+    use crate::common::types::SessionDetails;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::Notify;
+    use wampproto::serializers::json::JSONSerializer;
+
+    type Connection = (Arc<dyn Peer>, Arc<Notify>);
+
+    #[derive(Clone, Default)]
+    pub(crate) struct RouterStub {
+        realm: Arc<Mutex<Realm>>,
+        // the router end of every session and what ends its task
+        peers: Arc<Mutex<HashMap<i64, Connection>>>,
+    }
+
+    impl RouterStub {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Attaches a new session with the default options.
+        pub(crate) fn session(&self) -> Session {
+            self.session_with(Default::default())
+        }
+
+        pub(crate) fn session_with(&self, options: SessionOptions) -> Session {
+            let (details, peer) = self.connect();
+            Session::with_options(details, peer, Box::new(JSONSerializer {}), options)
+        }
+
+        /// Attaches a new session and returns its details and the client end
+        /// of its connection, for tests that wrap the peer before creating
+        /// the session. The session has to use JSON.
+        pub(crate) fn connect(&self) -> (SessionDetails, Box<dyn Peer>) {
+            let (client, router) = MemoryPeer::pair();
+            let router: Arc<dyn Peer> = router.into();
+            let closed = Arc::new(Notify::new());
+            let id = self.realm.lock().unwrap().join();
+            self.peers.lock().unwrap().insert(id, (router.clone(), closed.clone()));
+
+            let stub = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    let payload = tokio::select! {
+                        payload = router.read() => payload,
+                        _ = closed.notified() => break,
+                    };
+                    let Ok(payload) = payload else {
+                        break;
+                    };
+
+                    let replies = stub.realm.lock().unwrap().receive(id, &payload);
+                    for (to, data) in replies {
+                        let peer = stub.peers.lock().unwrap().get(&to).map(|(peer, _)| peer.clone());
+                        if let Some(peer) = peer {
+                            _ = peer.write(data).await;
+                        }
+                    }
+                }
+                stub.realm.lock().unwrap().leave(id);
+                stub.peers.lock().unwrap().remove(&id);
+            });
+
+            let details = SessionDetails::new(id, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+            (details, client)
+        }
+
+        /// Drops the connection of session `id` without a GOODBYE.
+        pub(crate) fn disconnect(&self, id: i64) {
+            if let Some((_, closed)) = self.peers.lock().unwrap().remove(&id) {
+                closed.notify_one();
+            }
+        }
+
+        /// Revokes a registration or subscription as if the router removed it
+        /// on its own.
+        pub(crate) async fn revoke(&self, id: i64, reason: &str) {
+            let revoked = self.realm.lock().unwrap().revoke(id, reason);
+            if let Some((to, data)) = revoked {
+                let peer = self.peers.lock().unwrap().get(&to).map(|(peer, _)| peer.clone());
+                if let Some(peer) = peer {
+                    _ = peer.write(data).await;
+                }
+            }
+        }
+
+        /// Messages received so far, with the id of the session that sent them.
+        pub(crate) fn received(&self) -> Vec<(i64, serde_json::Value)> {
+            self.realm.lock().unwrap().received()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::router::RouterStub;
+    use super::*;
+    use crate::async_::types::{CallRequest, Event, Invocation, PublishRequest, RegisterRequest, SubscribeRequest};
+    use crate::async_::types::{Value, Yield};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn pair_carries_frames_both_ways_until_dropped() {
+        let (left, right) = MemoryPeer::pair();
+        left.write(b"ping".to_vec()).await.unwrap();
+        assert_eq!(right.read().await.unwrap(), b"ping");
+        right.write(b"pong".to_vec()).await.unwrap();
+        assert_eq!(left.read().await.unwrap(), b"pong");
+
+        drop(right);
+        assert!(left.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stub_routes_a_call_to_the_callee() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+
+        let registered = callee
+            .register(RegisterRequest::new("io.xconn.echo", |inv: Invocation| async move {
+                Yield::new(inv.args, inv.kwargs)
+            }))
+            .await
+            .unwrap();
+        assert!(registered.error.is_none());
+
+        let response = caller
+            .call(CallRequest::new("io.xconn.echo").arg("hello"))
+            .await
+            .unwrap();
+        assert_eq!(response.args, Some(vec![Value::Str("hello".to_string())]));
+    }
+
+    #[tokio::test]
+    async fn stub_delivers_published_events() {
+        let router = RouterStub::new();
+        let subscriber = router.session();
+        let publisher = router.session();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        subscriber
+            .subscribe(SubscribeRequest::new("io.xconn.topic", move |event: Event| {
+                _ = sender.send(event.args);
+                async {}
+            }))
+            .await
+            .unwrap();
+        publisher
+            .publish(PublishRequest::new("io.xconn.topic").arg("hello").acknowledge())
+            .await
+            .unwrap();
+
+        let args = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap();
+        assert_eq!(args, Some(vec![Value::Str("hello".to_string())]));
+    }
+}
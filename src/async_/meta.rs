@@ -0,0 +1,95 @@
+use crate::async_::session::Session;
+use crate::common::meta::{
+    REGISTRATION_LIST, REGISTRATION_LOOKUP, REGISTRATION_MATCH, SESSION_COUNT, SESSION_LIST, SUBSCRIPTION_LIST,
+    SUBSCRIPTION_LOOKUP, SUBSCRIPTION_MATCH, parse_id, parse_id_list, parse_lists, parse_optional_id,
+};
+pub use crate::common::meta::{RegistrationLists, SubscriptionLists};
+use crate::common::types::{CallRequest, Error};
+
+/// Queries about the sessions joined to the realm.
+#[derive(Debug, Clone, Copy)]
+pub struct Sessions<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Sessions<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub async fn count(&self) -> Result<i64, Error> {
+        parse_id(self.session.call(CallRequest::new(SESSION_COUNT)).await?)
+    }
+
+    pub async fn list(&self) -> Result<Vec<i64>, Error> {
+        parse_id_list(self.session.call(CallRequest::new(SESSION_LIST)).await?)
+    }
+}
+
+/// Queries about the procedures registered on the realm.
+#[derive(Debug, Clone, Copy)]
+pub struct Registrations<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Registrations<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub async fn list(&self) -> Result<RegistrationLists, Error> {
+        parse_lists(self.session.call(CallRequest::new(REGISTRATION_LIST)).await?)
+    }
+
+    /// Returns the ID of the exact-match registration for `procedure`, if any.
+    pub async fn lookup(&self, procedure: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(
+            self.session
+                .call(CallRequest::new(REGISTRATION_LOOKUP).arg(procedure))
+                .await?,
+        )
+    }
+
+    /// Returns the ID of the registration a call to `procedure` would be routed to.
+    pub async fn matching(&self, procedure: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(
+            self.session
+                .call(CallRequest::new(REGISTRATION_MATCH).arg(procedure))
+                .await?,
+        )
+    }
+}
+
+/// Queries about the topics subscribed to on the realm.
+#[derive(Debug, Clone, Copy)]
+pub struct Subscriptions<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Subscriptions<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub async fn list(&self) -> Result<SubscriptionLists, Error> {
+        parse_lists(self.session.call(CallRequest::new(SUBSCRIPTION_LIST)).await?)
+    }
+
+    /// Returns the ID of the exact-match subscription for `topic`, if any.
+    pub async fn lookup(&self, topic: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(
+            self.session
+                .call(CallRequest::new(SUBSCRIPTION_LOOKUP).arg(topic))
+                .await?,
+        )
+    }
+
+    /// Returns the IDs of all subscriptions an event published to `topic` would reach.
+    pub async fn matching(&self, topic: &str) -> Result<Vec<i64>, Error> {
+        parse_id_list(
+            self.session
+                .call(CallRequest::new(SUBSCRIPTION_MATCH).arg(topic))
+                .await?,
+        )
+    }
+}
@@ -1,5 +1,7 @@
 pub mod client;
 pub mod joiner;
+pub mod memory;
+pub mod meta;
 pub mod peer;
 pub mod rawsocket;
 pub mod session;
@@ -7,4 +7,8 @@ pub trait Peer: Debug + Send + Sync {
     fn kind(&self) -> TransportType;
     async fn read(&self) -> Result<Vec<u8>, Error>;
     async fn write(&self, data: Vec<u8>) -> Result<(), Error>;
+
+    /// Closes the connection so that later reads and writes fail. The default
+    /// does nothing and leaves closing to dropping the peer.
+    async fn close(&self) {}
 }
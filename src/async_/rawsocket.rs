@@ -1,25 +1,48 @@
 use crate::async_::peer::Peer;
-use crate::common::types::{Error, SerializerSpec, TRANSPORT_RAW_SOCKET, TransportType};
+use crate::common::joiner::{JoinError, host_and_port};
+use crate::common::rawsocket::{check_handshake_response, check_message_size, max_message_size};
+use crate::common::types::{Error, SerializerSpec, TRANSPORT_RAW_SOCKET, TlsConfig, TransportType};
 use async_trait::async_trait;
+use std::fmt::Debug;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
+use tokio_native_tls::TlsConnector;
+use tokio_tungstenite::MaybeTlsStream;
 
 use url::Url;
 use wampproto::transports::rawsocket::{
-    DEFAULT_MAX_MSG_SIZE, Handshake, Message as RSMessage, MessageHeader, receive_handshake, receive_message_header,
-    send_handshake, send_message_header,
+    DEFAULT_MAX_MSG_SIZE, Handshake, Message as RSMessage, MessageHeader, SerializerID, receive_handshake,
+    receive_message_header, send_handshake, send_message_header,
 };
 
-#[derive(Debug, Clone)]
-pub struct RawSocketPeer {
-    reader: Arc<Mutex<ReadHalf<TcpStream>>>,
-    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+/// A rawsocket connection, by default over TCP with optional TLS. Any other
+/// stream works too, see `RawSocketJoiner::join_over`.
+#[derive(Debug)]
+pub struct RawSocketPeer<S = MaybeTlsStream<TcpStream>> {
+    reader: Arc<Mutex<ReadHalf<S>>>,
+    writer: Arc<Mutex<WriteHalf<S>>>,
+    // as negotiated in the handshake, larger messages are refused by write
+    max_message_size: usize,
+}
+
+// not derived, that would require the stream to be Clone as well
+impl<S> Clone for RawSocketPeer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+            max_message_size: self.max_message_size,
+        }
+    }
 }
 
 #[async_trait]
-impl Peer for RawSocketPeer {
+impl<S> Peer for RawSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+{
     fn kind(&self) -> TransportType {
         TRANSPORT_RAW_SOCKET
     }
@@ -29,23 +52,24 @@ impl Peer for RawSocketPeer {
 
         let mut buf = [0u8; 4];
         reader
-            .read(&mut buf)
+            .read_exact(&mut buf)
             .await
-            .map_err(|e| Error::new(format!("failed to read handshake response: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to read message header: {e}")))?;
 
         let header =
-            receive_message_header(&buf).map_err(|e| Error::new(format!("failed to read handshake response: {e}")))?;
+            receive_message_header(&buf).map_err(|e| Error::new(format!("failed to parse message header: {e}")))?;
 
         let mut buf = vec![0u8; header.length()];
         reader
-            .read(&mut buf)
+            .read_exact(&mut buf)
             .await
-            .map_err(|e| Error::new(format!("failed to read header response: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to read message: {e}")))?;
 
         Ok(buf)
     }
 
     async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        check_message_size(data.len(), self.max_message_size)?;
         let header = MessageHeader::new(RSMessage::Wamp, data.len());
         let header_raw = send_message_header(&header);
 
@@ -62,46 +86,165 @@ impl Peer for RawSocketPeer {
 
         Ok(())
     }
+
+    async fn close(&self) {
+        _ = self.writer.lock().await.shutdown().await;
+    }
 }
 
 #[allow(clippy::new_ret_no_self)]
-impl RawSocketPeer {
-    pub fn new(reader: ReadHalf<TcpStream>, writer: WriteHalf<TcpStream>) -> Box<dyn Peer> {
+impl<S> RawSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+{
+    pub fn new(reader: ReadHalf<S>, writer: WriteHalf<S>) -> Box<dyn Peer> {
+        Self::with_max_message_size(reader, writer, DEFAULT_MAX_MSG_SIZE)
+    }
+
+    /// Like `new` for a router that accepts messages up to `max_message_size`
+    /// bytes, as announced in its handshake reply.
+    pub fn with_max_message_size(reader: ReadHalf<S>, writer: WriteHalf<S>, max_message_size: usize) -> Box<dyn Peer> {
         Box::new(RawSocketPeer {
             reader: Arc::new(Mutex::new(reader)),
             writer: Arc::new(Mutex::new(writer)),
+            max_message_size,
         })
     }
 }
 
-pub async fn connect_rawsocket(uri: &str, serializer: Box<dyn SerializerSpec>) -> Result<Box<dyn Peer>, Error> {
-    let parsed = Url::parse(uri).map_err(|e| Error::new(format!("invalid uri: {e}")))?;
-    let host = parsed.host_str().unwrap();
-    let port = parsed.port_or_known_default().unwrap();
+pub async fn connect_rawsocket(
+    uri: &str,
+    serializer: Box<dyn SerializerSpec>,
+    tls: Option<TlsConfig>,
+) -> Result<Box<dyn Peer>, Error> {
+    Ok(dial(uri, serializer.as_ref(), tls).await?)
+}
 
-    let addr = format!("{host}:{port}");
-    let mut stream = TcpStream::connect(addr)
+/// Like `connect_rawsocket`, but tells the joiner whether a failure is worth
+/// retrying: only the TCP connect and I/O during the handshake are.
+pub(crate) async fn dial(
+    uri: &str,
+    serializer: &dyn SerializerSpec,
+    tls: Option<TlsConfig>,
+) -> Result<Box<dyn Peer>, JoinError> {
+    let parsed = Url::parse(uri).map_err(|e| JoinError::Rejected(Error::new(format!("invalid uri: {e}"))))?;
+    let (host, port) = host_and_port(&parsed).map_err(JoinError::Rejected)?;
+
+    // tokio tries every address the host resolves to until one connects
+    let tcp_stream = TcpStream::connect((host.as_str(), port))
         .await
-        .map_err(|e| Error::new(format!("connect error: {e}")))?;
+        .map_err(|e| JoinError::Transient(Error::new(format!("connect error: {e}"))))?;
+
+    // the TLS handshake has to complete before the rawsocket handshake
+    let stream = match parsed.scheme() {
+        "rss" | "tcps" => {
+            let connector = tls.unwrap_or_default().connector().map_err(JoinError::Rejected)?;
+            // most likely a certificate that failed verification
+            let tls_stream = TlsConnector::from(connector)
+                .connect(&host, tcp_stream)
+                .await
+                .map_err(|e| JoinError::Rejected(Error::new(format!("tls handshake failed: {e}"))))?;
+
+            MaybeTlsStream::NativeTls(tls_stream)
+        }
+        _ => MaybeTlsStream::Plain(tcp_stream),
+    };
+
+    handshake(stream, serializer.serializer_id()).await
+}
 
-    let handshake = Handshake::new(serializer.serializer_id(), DEFAULT_MAX_MSG_SIZE);
+/// Runs the rawsocket handshake over an established stream and wraps it in a
+/// peer that respects the message size limit the router announced.
+pub(crate) async fn handshake<S>(mut stream: S, serializer_id: SerializerID) -> Result<Box<dyn Peer>, JoinError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+{
+    let handshake = Handshake::new(serializer_id, DEFAULT_MAX_MSG_SIZE);
 
-    let handshake_raw =
-        send_handshake(&handshake).map_err(|e| Error::new(format!("failed to serialize handshake: {e}")))?;
+    let handshake_raw = send_handshake(&handshake)
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to serialize handshake: {e}"))))?;
 
     stream
         .write_all(&handshake_raw)
         .await
-        .map_err(|e| Error::new(format!("failed to send handshake: {e}")))?;
+        .map_err(|e| JoinError::Transient(Error::new(format!("failed to send handshake: {e}"))))?;
 
     let mut buf = [0u8; 4];
     stream
-        .read(&mut buf)
+        .read_exact(&mut buf)
         .await
-        .map_err(|e| Error::new(format!("failed to read handshake response: {e}")))?;
+        .map_err(|e| JoinError::Transient(Error::new(format!("failed to read handshake response: {e}"))))?;
 
-    _ = receive_handshake(&buf).map_err(|e| Error::new(format!("failed to parse handshake response: {e}")))?;
+    // the router refused the handshake, e.g. for an unsupported serializer
+    check_handshake_response(&buf).map_err(JoinError::Rejected)?;
+    _ = receive_handshake(&buf)
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to parse handshake response: {e}"))))?;
 
     let (reader, writer) = tokio::io::split(stream);
-    Ok(RawSocketPeer::new(reader, writer))
+    Ok(RawSocketPeer::with_max_message_size(
+        reader,
+        writer,
+        max_message_size(&buf),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::types::JSONSerializerSpec;
+
+    #[tokio::test]
+    async fn handshake_refused_by_the_router_is_not_retried() {
+        let (client, mut router) = tokio::io::duplex(64);
+        let refuse = tokio::spawn(async move {
+            let mut hello = [0u8; 4];
+            router.read_exact(&mut hello).await.unwrap();
+            // error code 1, serializer unsupported
+            router.write_all(&[0x7F, 0x10, 0, 0]).await.unwrap();
+            router
+        });
+
+        let result = handshake(client, SerializerID::JSON).await;
+        let _router = refuse.await.unwrap();
+        assert!(matches!(result, Err(JoinError::Rejected(_))));
+    }
+
+    #[tokio::test]
+    async fn handshake_dropped_by_the_router_is_retried() {
+        let (client, router) = tokio::io::duplex(64);
+        drop(router);
+
+        let result = handshake(client, SerializerID::JSON).await;
+        assert!(matches!(result, Err(JoinError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn dial_retries_a_refused_connection_but_not_a_bad_uri() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let serializer = JSONSerializerSpec {};
+        let refused = dial(&format!("rs://127.0.0.1:{port}"), &serializer, None).await;
+        assert!(matches!(refused, Err(JoinError::Transient(_))));
+        let invalid = dial("not a url", &serializer, None).await;
+        assert!(matches!(invalid, Err(JoinError::Rejected(_))));
+    }
+
+    #[tokio::test]
+    async fn read_reassembles_a_payload_split_across_short_reads() {
+        // a buffer far smaller than the message forces many partial reads
+        let (client, router) = tokio::io::duplex(64);
+        let (reader, writer) = tokio::io::split(client);
+        let client = RawSocketPeer::new(reader, writer);
+        let (reader, writer) = tokio::io::split(router);
+        let router = RawSocketPeer::new(reader, writer);
+
+        let payload: Vec<u8> = (0..16 * 1024).map(|i| (i % 251) as u8).collect();
+        let sent = payload.clone();
+        let writer = tokio::spawn(async move { client.write(sent).await });
+
+        assert_eq!(router.read().await.unwrap(), payload);
+        writer.await.unwrap().unwrap();
+    }
 }
@@ -1,18 +1,37 @@
 use crate::async_::peer::Peer;
 use crate::common::types::{
-    CallRequest, CallResponse, Error, Event as XEvent, Invocation as XInvocation, PublishRequest, PublishResponse,
-    RegisterResponse, SessionDetails, SubscribeResponse, WampError,
+    CallRequest, CallResponse, CancelMode, DEFAULT_ACKNOWLEDGE_TIMEOUT, DEFAULT_CALL_BUFFER_SIZE,
+    DEFAULT_EVENT_BUFFER_SIZE, Error, ErrorFn, Event as XEvent, EventFilterFn, GoodbyeInfo, Heartbeat, InterruptToken,
+    Invocation as XInvocation, MessageFn, ProgressFn, PublishRequest, PublishResponse, ReconnectEvent, ReconnectFn,
+    ReconnectPolicy, RegisterResponse, Revocation, RevokedFn, SessionDetails, SubscribeResponse, SubscriptionSpec,
+    TransportType, WampError,
 };
+use futures_util::Stream;
+use futures_util::future::{BoxFuture, join_all};
+use futures_util::stream;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, RwLock, Weak};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::task::JoinHandle;
 
-use crate::async_::types::{EventFn, RegisterFn, RegisterRequest, SubscribeRequest};
+use crate::async_::types::{
+    EventFn, ProgressSender, RegisterFn, RegisterRequest, SubscribeRequest, SubscriptionRegistry,
+};
+use crate::common::ppt::{payload, validate_ppt, validate_ppt_options};
+use crate::common::trace::{Outcome, Span};
+use crate::common::uri::{match_policy, validate_uri};
 use wampproto::idgen::SessionScopeIDGenerator;
 use wampproto::messages::call::MESSAGE_TYPE_CALL;
+use wampproto::messages::cancel::Cancel;
 use wampproto::messages::error::{Error as ErrorMsg, MESSAGE_TYPE_ERROR};
 use wampproto::messages::event::{Event, MESSAGE_TYPE_EVENT};
 use wampproto::messages::goodbye::{Goodbye, MESSAGE_TYPE_GOODBYE};
+use wampproto::messages::interrupt::{Interrupt, MESSAGE_TYPE_INTERRUPT};
 use wampproto::messages::invocation::{Invocation, MESSAGE_TYPE_INVOCATION};
 use wampproto::messages::message::Message;
 use wampproto::messages::publish::MESSAGE_TYPE_PUBLISH;
@@ -30,34 +49,257 @@ use wampproto::messages::unsubscribed::{MESSAGE_TYPE_UNSUBSCRIBED, Unsubscribed}
 use wampproto::messages::yield_::Yield;
 use wampproto::serializers::serializer::Serializer;
 
-#[derive(Debug)]
+/// A joined WAMP session. All state is shared behind `Arc`s, so cloning is
+/// cheap and every clone talks to the same connection. Sessions are
+/// `Send + Sync` and all methods take `&self`, so calls, publishes and
+/// (un)registrations can be issued from many tasks concurrently. Only one
+/// clone will observe the disconnect in `wait_disconnect`.
+#[derive(Debug, Clone)]
 pub struct Session {
-    _details: SessionDetails,
+    // replaced by the details of the new WELCOME when the session reconnects
+    details: Arc<RwLock<SessionDetails>>,
     serializer: Arc<Box<dyn Serializer>>,
-    idgen: SessionScopeIDGenerator,
-    peer: Arc<Box<dyn Peer>>,
+    subprotocol: Option<String>,
+    // shared by every clone and the background tasks, next_id is atomic. It
+    // survives reconnects on purpose: ids keep counting up, so no request on
+    // the new connection reuses the id of one left over from the old one
+    idgen: Arc<SessionScopeIDGenerator>,
+    // swapped for a new connection when the session reconnects
+    peer: Arc<RwLock<Arc<Box<dyn Peer>>>>,
+
+    state: Arc<State>,
+    goodbye_sender: mpsc::Sender<()>,
+    exit_sender: mpsc::Sender<GoodbyeInfo>,
+    goodbye_receiver_channel: Arc<Mutex<mpsc::Receiver<()>>>,
+    exist_receiver_channel: Arc<Mutex<mpsc::Receiver<GoodbyeInfo>>>,
+    reconnect: Option<Arc<Reconnect>>,
+    // the running reader task, replaced when the session reconnects
+    reader: Arc<StdMutex<Option<JoinHandle<()>>>>,
+    // only held by the user's clones, not the reader's or the heartbeat's,
+    // so that it is dropped together with the last of them
+    closer: Option<Arc<Closer>>,
+}
 
+/// Stops the reader task and closes the connection once the last clone of
+/// the session is dropped.
+#[derive(Debug)]
+struct Closer {
     state: Arc<State>,
-    goodbye_receiver_channel: Mutex<mpsc::Receiver<()>>,
-    exist_receiver_channel: Mutex<mpsc::Receiver<()>>,
+    peer: Arc<RwLock<Arc<Box<dyn Peer>>>>,
+    reader: Arc<StdMutex<Option<JoinHandle<()>>>>,
+}
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.state.closing.store(true, Ordering::SeqCst);
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+
+        let peer = self.peer.read().unwrap().clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { peer.close().await });
+        }
+    }
+}
+
+pub(crate) type JoinFuture = Pin<Box<dyn Future<Output = Result<(Box<dyn Peer>, SessionDetails), Error>> + Send>>;
+
+/// Everything needed to rejoin after the connection dropped.
+pub(crate) struct Reconnect {
+    pub(crate) policy: ReconnectPolicy,
+    pub(crate) join: Arc<dyn Fn() -> JoinFuture + Send + Sync>,
+    pub(crate) listener: Option<ReconnectFn>,
+}
+
+impl fmt::Debug for Reconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnect")
+            .field("policy", &self.policy)
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl Reconnect {
+    fn notify(&self, event: ReconnectEvent) {
+        if let Some(listener) = &self.listener {
+            listener.invoke(event);
+        }
+    }
+}
+
+/// Optional behavior configured through the client builder.
+#[derive(Debug, Default)]
+pub(crate) struct SessionOptions {
+    /// Rejoin whenever the connection drops without the session having been left.
+    pub(crate) reconnect: Option<Reconnect>,
+    /// Queue invocations and events once this many handlers are running.
+    pub(crate) max_concurrent_invocations: Option<usize>,
+    /// Receives errors from the background reader and handler tasks.
+    pub(crate) error_handler: Option<ErrorFn>,
+    /// Sees every incoming message before it is dispatched.
+    pub(crate) message_handler: Option<MessageFn>,
+    /// Told about registrations and subscriptions the router revoked.
+    pub(crate) revoked_handler: Option<RevokedFn>,
+    /// Capacity of the queue feeding each ordered subscription's handler.
+    pub(crate) event_buffer_size: Option<usize>,
+    /// Capacity of each call's queue of progressive results.
+    pub(crate) call_buffer_size: Option<usize>,
+    /// The WAMP subprotocol of the serializer in use, e.g. `wamp.2.cbor`.
+    pub(crate) subprotocol: Option<String>,
+    /// Validate outgoing URIs against the strict rather than the loose rules.
+    pub(crate) strict_uris: bool,
+    /// Queue invocations and events for `poll_once`/`run` instead of spawning
+    /// a task for each.
+    pub(crate) manual_dispatch: bool,
+}
+
+/// A registration as requested by the user, kept to replay it on reconnect.
+#[derive(Debug)]
+struct Registration {
+    procedure: String,
+    options: HashMap<String, Value>,
+    // sent along with every YIELD, e.g. to mark results as passthrough
+    result_options: HashMap<String, Value>,
+    callback: RegisterFn,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    topic: String,
+    options: HashMap<String, Value>,
+    callback: EventFn,
+    // set for ordered subscriptions, feeds the task that runs the handler
+    queue: Option<mpsc::Sender<(XEvent, HandlerGuard)>>,
+    // set for subscriptions consumed as a stream, the callback is unused then
+    stream: Option<mpsc::Sender<XEvent>>,
+    filter: Option<EventFilterFn>,
+}
+
+// the handler travels with the request and is stored by the reader when the
+// router confirms, so it is in place before any INVOCATION or EVENT for it
+#[derive(Debug)]
+struct PendingRegister {
+    sender: mpsc::Sender<RegisterResponse>,
+    registration: Registration,
+}
+
+#[derive(Debug)]
+struct PendingSubscribe {
+    sender: mpsc::Sender<SubscribeResponse>,
+    subscription: Subscription,
+}
+
+// an acknowledged publish waiting for its PUBLISHED, and for how long at most
+type PendingPublish = (mpsc::Receiver<PublishResponse>, Duration);
+
+// what the reader passes on to a waiting call, the caller hands progressive results
+// to its progress callback itself so a slow callback doesn't hold up the reader
+#[derive(Debug)]
+enum CallUpdate {
+    Progress(CallResponse),
+    Done(CallResponse),
+}
+
+// the reader removes the entry before sending the final response, and sends without
+// holding the lock since a buffer full of progressive results blocks it
+#[derive(Debug)]
+struct PendingCall {
+    sender: mpsc::Sender<CallUpdate>,
+    // intermediate results are only expected if the call asked for them
+    receive_progress: bool,
+}
+
+type Task = BoxFuture<'static, ()>;
+
+/// Invocation and event handlers of a session with manual dispatch, waiting
+/// for the user's task to run them through `poll_once` or `run`.
+#[derive(Debug)]
+struct ManualQueue {
+    // taken once the connection is gone, so that run returns when drained
+    sender: StdMutex<Option<mpsc::UnboundedSender<Task>>>,
+    receiver: Mutex<mpsc::UnboundedReceiver<Task>>,
+}
+
+impl ManualQueue {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender: StdMutex::new(Some(sender)),
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    fn push(&self, task: Task) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            _ = sender.send(task);
+        }
+    }
+
+    async fn poll_once(&self) -> bool {
+        let task = self.receiver.lock().await.try_recv();
+        match task {
+            Ok(task) => {
+                task.await;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    async fn run(&self) {
+        loop {
+            // the lock is only held while waiting for the next task
+            let Some(task) = self.receiver.lock().await.recv().await else {
+                break;
+            };
+            task.await;
+        }
+    }
+
+    fn close(&self) {
+        self.sender.lock().unwrap().take();
+    }
 }
 
 #[derive(Debug)]
 struct State {
     // RPC states
-    call_requests: Mutex<HashMap<i64, mpsc::Sender<CallResponse>>>,
-    register_requests: Mutex<HashMap<i64, mpsc::Sender<RegisterResponse>>>,
+    call_requests: Mutex<HashMap<i64, PendingCall>>,
+    register_requests: Mutex<HashMap<i64, PendingRegister>>,
     unregister_requests: Mutex<HashMap<i64, mpsc::Sender<Option<WampError>>>>,
-    registrations: Mutex<HashMap<i64, RegisterFn>>,
+    registrations: Mutex<HashMap<i64, Registration>>,
+    invocations: Mutex<HashMap<i64, InterruptToken>>,
 
     // PubSub states
     publish_requests: Mutex<HashMap<i64, mpsc::Sender<PublishResponse>>>,
-    subscribe_requests: Mutex<HashMap<i64, mpsc::Sender<SubscribeResponse>>>,
+    subscribe_requests: Mutex<HashMap<i64, PendingSubscribe>>,
     unsubscribe_requests: Mutex<HashMap<i64, mpsc::Sender<Option<WampError>>>>,
-    subscriptions: Mutex<HashMap<i64, EventFn>>,
+    subscriptions: Mutex<HashMap<i64, Subscription>>,
 
     // goodbye stuff
     goodbye_sent: Mutex<bool>,
+
+    // set by shutdown, new invocations and events are turned away
+    closing: AtomicBool,
+    // invocation and event handlers dispatched but not yet finished
+    in_flight: Arc<InFlight>,
+
+    // bounds the number of invocation and event handlers running at once
+    dispatch_limit: Option<Arc<Semaphore>>,
+    // set for manual dispatch, handlers then wait here instead of being spawned
+    manual: Option<ManualQueue>,
+
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: usize,
+    call_buffer_size: usize,
+    strict_uris: bool,
+
+    // why the reader stopped, set once the connection is gone
+    closed: RwLock<Option<String>>,
 }
 
 impl Default for State {
@@ -67,61 +309,308 @@ impl Default for State {
             register_requests: Default::default(),
             unregister_requests: Default::default(),
             registrations: Default::default(),
+            invocations: Default::default(),
             publish_requests: Default::default(),
             subscribe_requests: Default::default(),
             unsubscribe_requests: Default::default(),
             subscriptions: Default::default(),
 
             goodbye_sent: Mutex::new(false),
+            closing: AtomicBool::new(false),
+            in_flight: Default::default(),
+            dispatch_limit: None,
+            manual: None,
+            error_handler: None,
+            message_handler: None,
+            revoked_handler: None,
+            event_buffer_size: DEFAULT_EVENT_BUFFER_SIZE,
+            call_buffer_size: DEFAULT_CALL_BUFFER_SIZE,
+            strict_uris: false,
+            closed: RwLock::new(None),
         }
     }
 }
 
+/// Counts the handlers that were dispatched but did not finish yet, shared
+/// apart from `State` so ordered subscription workers don't keep it alive.
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    // notified when the last of them finished, for shutdown
+    idle: Notify,
+}
+
+/// Marks a handler as finished when dropped, which also happens when its
+/// task panicked or was aborted.
+struct HandlerGuard(Arc<InFlight>);
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl State {
+    /// Waits for a free handler slot if the session has a concurrency limit.
+    /// The reader waits here before spawning a handler, it doesn't read on
+    /// until one of the running handlers finished.
+    async fn acquire_dispatch(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.dispatch_limit {
+            Some(limit) => limit.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
+    /// Runs an invocation or event handler on a task of its own or, with
+    /// manual dispatch, queues it for `poll_once`/`run`.
+    fn dispatch<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match &self.manual {
+            Some(manual) => manual.push(Box::pin(task)),
+            None => {
+                tokio::spawn(task);
+            }
+        }
+    }
+
+    /// Lets `run` return once the queued handlers are done.
+    fn close_dispatch(&self) {
+        if let Some(manual) = &self.manual {
+            manual.close();
+        }
+    }
+
+    /// Hands an error that has no caller to return to over to the user's handler.
+    fn report(&self, error: Error) {
+        if let Some(handler) = &self.error_handler {
+            handler.invoke(error);
+        }
+    }
+
+    /// The error for a request whose response channel closed without an answer.
+    fn dropped(&self, action: &str) -> Error {
+        match self.closed.read().unwrap().as_ref() {
+            Some(reason) => Error::new(format!("{action} failed: {reason}")),
+            None => Error::new(format!("{action} failed")),
+        }
+    }
+
+    fn handler_started(&self) -> HandlerGuard {
+        self.in_flight.count.fetch_add(1, Ordering::SeqCst);
+        HandlerGuard(self.in_flight.clone())
+    }
+
+    /// Returns once no invocation or event handler is running anymore.
+    async fn wait_idle(&self) {
+        loop {
+            // registered before checking, so a handler finishing in between isn't missed
+            let notified = self.in_flight.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.in_flight.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    async fn clear_pending(&self) {
+        self.call_requests.lock().await.clear();
+        self.register_requests.lock().await.clear();
+        self.unregister_requests.lock().await.clear();
+        self.publish_requests.lock().await.clear();
+        self.subscribe_requests.lock().await.clear();
+        self.unsubscribe_requests.lock().await.clear();
+    }
+}
+
 impl Session {
     pub fn new(details: SessionDetails, peer: Box<dyn Peer>, serializer: Box<dyn Serializer>) -> Self {
-        let stored_serializer = Arc::new(serializer);
-        let task_serializer = stored_serializer.clone();
+        Self::with_options(details, peer, serializer, SessionOptions::default())
+    }
 
-        let stored_state = Arc::new(State::default());
-        let task_state = stored_state.clone();
+    pub(crate) fn with_options(
+        details: SessionDetails,
+        peer: Box<dyn Peer>,
+        serializer: Box<dyn Serializer>,
+        options: SessionOptions,
+    ) -> Self {
+        let (goodbye_sender, goodbye_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel(1);
+        let (exit_sender, exit_receiver): (mpsc::Sender<GoodbyeInfo>, mpsc::Receiver<GoodbyeInfo>) = mpsc::channel(1);
 
-        let stored_peer = Arc::new(peer);
-        let task_peer = stored_peer.clone();
+        let peer = Arc::new(peer);
+        let shared_peer = Arc::new(RwLock::new(peer.clone()));
+        let state = Arc::new(State {
+            dispatch_limit: options
+                .max_concurrent_invocations
+                // the reader waits for a permit, without any it would stop for good
+                .map(|limit| Arc::new(Semaphore::new(limit.max(1)))),
+            manual: options.manual_dispatch.then(ManualQueue::new),
+            error_handler: options.error_handler,
+            message_handler: options.message_handler,
+            revoked_handler: options.revoked_handler,
+            event_buffer_size: options.event_buffer_size.unwrap_or(DEFAULT_EVENT_BUFFER_SIZE),
+            call_buffer_size: options.call_buffer_size.unwrap_or(DEFAULT_CALL_BUFFER_SIZE),
+            strict_uris: options.strict_uris,
+            ..Default::default()
+        });
+        let reader = Arc::new(StdMutex::new(None));
 
-        let (goodbye_sender, goodbye_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel(1);
-        let (exit_sender, exit_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel(1);
+        let session = Self {
+            details: Arc::new(RwLock::new(details)),
+            subprotocol: options.subprotocol.clone(),
+            peer: shared_peer.clone(),
+            serializer: Arc::new(serializer),
+            idgen: Arc::new(SessionScopeIDGenerator::new()),
 
-        tokio::spawn(async move {
-            while let Ok(payload) = task_peer.read().await {
-                match task_serializer.deserialize(payload) {
+            state: state.clone(),
+            goodbye_sender,
+            exit_sender,
+            goodbye_receiver_channel: Arc::new(Mutex::new(goodbye_receiver)),
+            exist_receiver_channel: Arc::new(Mutex::new(exit_receiver)),
+            reconnect: options.reconnect.map(Arc::new),
+            reader: reader.clone(),
+            closer: Some(Arc::new(Closer {
+                state,
+                peer: shared_peer,
+                reader,
+            })),
+        };
+
+        session.spawn_reader(peer);
+        session
+    }
+
+    fn peer(&self) -> Arc<Box<dyn Peer>> {
+        self.peer.read().unwrap().clone()
+    }
+
+    fn spawn_reader(&self, peer: Arc<Box<dyn Peer>>) {
+        let mut session = self.clone();
+        session.closer = None;
+
+        let task = tokio::spawn(async move {
+            let mut reason = "session disconnected".to_string();
+            while let Ok(payload) = peer.read().await {
+                match session.serializer.deserialize(payload) {
                     Ok(msg) => {
                         Self::process_incoming_message(
                             msg,
-                            task_state.clone(),
-                            task_serializer.clone(),
-                            task_peer.clone(),
-                            goodbye_sender.clone(),
-                            exit_sender.clone(),
+                            session.state.clone(),
+                            session.serializer.clone(),
+                            peer.clone(),
+                            session.goodbye_sender.clone(),
+                            session.exit_sender.clone(),
                         )
                         .await;
                     }
                     Err(e) => {
-                        eprintln!("Error: {e}");
+                        reason = format!("session disconnected: protocol error: failed to parse message: {e}");
+                        session
+                            .state
+                            .report(Error::new(format!("failed to parse message: {e}")));
                         break;
                     }
                 }
             }
+
+            // the connection is gone, close every pending request so that
+            // waiting callers return an error instead of hanging forever.
+            *session.state.closed.write().unwrap() = Some(reason);
+            session.state.clear_pending().await;
+
+            let left = *session.state.goodbye_sent.lock().await;
+            if left {
+                // the router closed the connection without answering our GOODBYE,
+                // don't keep leave() waiting for it.
+                _ = session.goodbye_sender.try_send(());
+            } else if let Some(reconnect) = session.reconnect.clone() {
+                if session.reconnect(&reconnect).await {
+                    return;
+                }
+            }
+
+            session.state.close_dispatch();
+            _ = session.exit_sender.try_send(GoodbyeInfo {
+                reason: "wamp.error.connection_lost".to_string(),
+                details: Default::default(),
+            });
         });
+        *self.reader.lock().unwrap() = Some(task);
+    }
 
-        Self {
-            _details: details,
-            peer: stored_peer,
-            serializer: stored_serializer,
-            idgen: SessionScopeIDGenerator::new(),
+    /// Rejoins with backoff and restores registrations and subscriptions.
+    /// Returns false if the policy gave up.
+    async fn reconnect(&self, reconnect: &Reconnect) -> bool {
+        reconnect.notify(ReconnectEvent::Disconnected);
 
-            state: stored_state,
-            goodbye_receiver_channel: Mutex::new(goodbye_receiver),
-            exist_receiver_channel: Mutex::new(exit_receiver),
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if reconnect.policy.max_attempts.is_some_and(|max| attempt > max) {
+                reconnect.notify(ReconnectEvent::GaveUp);
+                return false;
+            }
+
+            let delay = reconnect.policy.backoff(attempt);
+            reconnect.notify(ReconnectEvent::Attempt { attempt, delay });
+            tokio::time::sleep(delay).await;
+
+            match (reconnect.join)().await {
+                Ok((peer, details)) => {
+                    // requests made while reconnecting went to the dead peer and
+                    // won't be answered, fail them before the new peer takes over
+                    self.state.clear_pending().await;
+                    let peer = Arc::new(peer);
+                    *self.peer.write().unwrap() = peer.clone();
+                    *self.details.write().unwrap() = details.clone();
+                    *self.state.closed.write().unwrap() = None;
+                    self.spawn_reader(peer);
+                    self.replay().await;
+
+                    reconnect.notify(ReconnectEvent::Reconnected(details));
+                    return true;
+                }
+                Err(e) => reconnect.notify(ReconnectEvent::Failed {
+                    attempt,
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    /// Sends REGISTER/SUBSCRIBE again for everything the previous connection had.
+    async fn replay(&self) {
+        let registrations: Vec<Registration> = self.state.registrations.lock().await.drain().map(|(_, r)| r).collect();
+        for registration in registrations {
+            let procedure = registration.procedure.clone();
+            match self.register_inner(registration).await {
+                Ok(RegisterResponse { error: Some(e), .. }) => self
+                    .state
+                    .report(Error::new(format!("failed to re-register {procedure}: {e}"))),
+                Ok(_) => {}
+                Err(e) => self
+                    .state
+                    .report(Error::new(format!("failed to re-register {procedure}: {e}"))),
+            }
+        }
+
+        let subscriptions: Vec<Subscription> = self.state.subscriptions.lock().await.drain().map(|(_, s)| s).collect();
+        for subscription in subscriptions {
+            let topic = subscription.topic.clone();
+            match self.subscribe_inner(subscription).await {
+                Ok(SubscribeResponse { error: Some(e), .. }) => self
+                    .state
+                    .report(Error::new(format!("failed to re-subscribe {topic}: {e}"))),
+                Ok(_) => {}
+                Err(e) => self
+                    .state
+                    .report(Error::new(format!("failed to re-subscribe {topic}: {e}"))),
+            }
         }
     }
 
@@ -131,14 +620,24 @@ impl Session {
         serializer: Arc<Box<dyn Serializer>>,
         peer: Arc<Box<dyn Peer>>,
         goodbye_sender: mpsc::Sender<()>,
-        exist_sender: mpsc::Sender<()>,
+        exist_sender: mpsc::Sender<GoodbyeInfo>,
     ) {
+        if let Some(handler) = &state.message_handler {
+            handler.invoke(msg.as_ref());
+        }
+
         match msg.message_type() {
             MESSAGE_TYPE_REGISTERED => {
                 let registered = msg.as_any().downcast_ref::<Registered>().unwrap();
                 let mut register_requests = state.register_requests.lock().await;
-                if let Some(callback) = register_requests.remove(&registered.request_id) {
-                    _ = callback
+                if let Some(pending) = register_requests.remove(&registered.request_id) {
+                    state
+                        .registrations
+                        .lock()
+                        .await
+                        .insert(registered.registration_id, pending.registration);
+                    _ = pending
+                        .sender
                         .send(RegisterResponse {
                             registration_id: registered.registration_id,
                             error: None,
@@ -151,66 +650,196 @@ impl Session {
                 let mut unregister_requests = state.unregister_requests.lock().await;
                 if let Some(callback) = unregister_requests.remove(&unregistered.request_id) {
                     _ = callback.send(None).await;
+                } else if let Some((registration_id, reason)) =
+                    Revocation::from_details(&unregistered.details, "registration")
+                {
+                    // no request of ours, the router revoked the registration
+                    drop(unregister_requests);
+                    let removed = state.registrations.lock().await.remove(&registration_id).is_some();
+                    if removed && let Some(handler) = &state.revoked_handler {
+                        handler.invoke(Revocation::Registration {
+                            registration_id,
+                            reason,
+                        });
+                    }
                 }
             }
             MESSAGE_TYPE_RESULT => {
                 let result = msg.as_any().downcast_ref::<Result_>().unwrap();
                 let mut call_requests = state.call_requests.lock().await;
-                if let Some(callback) = call_requests.remove(&result.request_id) {
-                    _ = callback
-                        .send(CallResponse {
-                            args: result.args.clone(),
-                            kwargs: result.kwargs.clone(),
-                            error: None,
-                        })
-                        .await;
+
+                let progress = matches!(result.details.get("progress"), Some(Value::Bool(true)))
+                    && call_requests
+                        .get(&result.request_id)
+                        .is_some_and(|call| call.receive_progress);
+                let sender = match progress {
+                    true => call_requests.get(&result.request_id).map(|call| call.sender.clone()),
+                    false => call_requests.remove(&result.request_id).map(|call| call.sender),
+                };
+                drop(call_requests);
+
+                if let Some(sender) = sender {
+                    let response = CallResponse {
+                        args: result.args.clone(),
+                        kwargs: result.kwargs.clone(),
+                        error: None,
+                    };
+                    let update = match progress {
+                        true => CallUpdate::Progress(response),
+                        false => CallUpdate::Done(response),
+                    };
+                    _ = sender.send(update).await;
                 }
             }
             MESSAGE_TYPE_INVOCATION => {
                 let invocation = msg.as_any().downcast_ref::<Invocation>().unwrap();
-                let registrations = state.registrations.lock().await;
-
-                let callback = registrations.get(&invocation.registration_id).cloned();
-                if callback.is_none() {
+                if state.closing.load(Ordering::SeqCst) {
+                    let error = ErrorMsg {
+                        message_type: MESSAGE_TYPE_INVOCATION,
+                        request_id: invocation.request_id,
+                        details: Default::default(),
+                        uri: "wamp.error.unavailable".to_string(),
+                        args: None,
+                        kwargs: None,
+                    };
+                    if let Ok(to_send) = serializer.serialize(&error) {
+                        _ = peer.write(to_send).await;
+                    }
                     return;
                 }
 
+                // the lock is released right after the lookup, not held while dispatching
+                let Some((callback, result_options)) = state
+                    .registrations
+                    .lock()
+                    .await
+                    .get(&invocation.registration_id)
+                    .map(|registration| (registration.callback.clone(), registration.result_options.clone()))
+                else {
+                    return;
+                };
+
                 let inv = XInvocation {
                     args: invocation.args.clone().map_or_else(Default::default, |args| args),
                     kwargs: invocation.kwargs.clone().map_or_else(Default::default, |kwargs| kwargs),
                     details: invocation.details.clone(),
+                    interrupt: InterruptToken::default(),
                 };
 
                 let request_id = invocation.request_id;
-                let callback = callback.unwrap();
-
-                tokio::spawn(async move {
-                    let response = callback.invoke(inv).await;
-                    let yield_ = Yield {
-                        request_id,
-                        options: Default::default(),
-                        args: Some(response.args),
-                        kwargs: Some(response.kwargs),
+                let interrupt = inv.interrupt_token();
+                state.invocations.lock().await.insert(request_id, interrupt.clone());
+                let finished = state.handler_started();
+                let task_state = state.clone();
+
+                let progress =
+                    ProgressSender::new(request_id, serializer.clone(), peer.clone(), result_options.clone());
+
+                let registration_id = invocation.registration_id;
+                // taken before spawning, so no more tasks exist than the limit allows
+                let permit = state.acquire_dispatch().await;
+                state.dispatch(async move {
+                    let _finished = finished;
+                    let _permit = permit;
+                    // started once the handler gets to run, not while it waited for a permit
+                    let span = Span::invocation(registration_id, request_id);
+                    let response = span.instrument(callback.invoke(inv, progress)).await;
+                    task_state.invocations.lock().await.remove(&request_id);
+
+                    // the dealer has already dropped an interrupted invocation, so
+                    // only let it know the cancellation went through.
+                    let error = if interrupt.is_cancelled() {
+                        Some(WampError {
+                            uri: "wamp.error.canceled".to_string(),
+                            args: None,
+                            kwargs: None,
+                        })
+                    } else if response.error.is_some() {
+                        response.error
+                    } else if let Err(e) = validate_ppt(&result_options, &response.args, &response.kwargs) {
+                        task_state.report(Error::new(format!("invalid invocation result: {e}")));
+                        Some(WampError {
+                            uri: "wamp.error.invalid_argument".to_string(),
+                            args: None,
+                            kwargs: None,
+                        })
+                    } else {
+                        None
+                    };
+
+                    let yielded = error.is_none();
+                    let serialized = match error {
+                        Some(error) => serializer.serialize(&ErrorMsg {
+                            message_type: MESSAGE_TYPE_INVOCATION,
+                            request_id,
+                            details: Default::default(),
+                            uri: error.uri,
+                            args: error.args,
+                            kwargs: error.kwargs,
+                        }),
+                        None => {
+                            let (args, kwargs) = payload(&result_options, response.args, response.kwargs);
+                            serializer.serialize(&Yield {
+                                request_id,
+                                options: result_options,
+                                args,
+                                kwargs,
+                            })
+                        }
+                    };
+
+                    let sent = match serialized {
+                        Ok(to_send) => peer
+                            .write(to_send)
+                            .await
+                            .map_err(|e| Error::new(format!("failed to send invocation result: {e}"))),
+                        Err(e) => Err(Error::new(format!("failed to serialize message: {e}"))),
                     };
+                    let mut outcome = if yielded { Outcome::Ok } else { Outcome::Error };
+                    if let Err(e) = sent {
+                        // e.g. a YIELD above the transport's size limit, answer with an
+                        // ERROR so the caller isn't left waiting for a result
+                        let fallback = yielded.then(|| ErrorMsg {
+                            message_type: MESSAGE_TYPE_INVOCATION,
+                            request_id,
+                            details: Default::default(),
+                            uri: "wamp.error.runtime_error".to_string(),
+                            args: Some(vec![Value::Str(e.to_string())]),
+                            kwargs: None,
+                        });
+                        task_state.report(e);
 
-                    match serializer.serialize(&yield_) {
-                        Ok(to_send) => match peer.write(to_send).await {
-                            Ok(()) => {}
-                            Err(e) => {
-                                eprintln!("Error sending message: {e}");
+                        if let Some(fallback) = fallback {
+                            outcome = Outcome::Error;
+                            let sent = match serializer.serialize(&fallback) {
+                                Ok(to_send) => peer.write(to_send).await,
+                                Err(e) => Err(Error::new(format!("failed to serialize message: {e}"))),
+                            };
+                            if let Err(e) = sent {
+                                task_state.report(Error::new(format!("failed to send invocation error: {e}")));
                             }
-                        },
-                        Err(e) => {
-                            eprintln!("Error sending message: {e}");
                         }
                     }
+                    span.finish(outcome);
                 });
             }
+            MESSAGE_TYPE_INTERRUPT => {
+                let interrupt = msg.as_any().downcast_ref::<Interrupt>().unwrap();
+                if let Some(token) = state.invocations.lock().await.remove(&interrupt.request_id) {
+                    token.cancel();
+                }
+            }
             MESSAGE_TYPE_SUBSCRIBED => {
                 let subscribed = msg.as_any().downcast_ref::<Subscribed>().unwrap();
                 let mut subscribe_requests = state.subscribe_requests.lock().await;
-                if let Some(callback) = subscribe_requests.remove(&subscribed.request_id) {
-                    _ = callback
+                if let Some(pending) = subscribe_requests.remove(&subscribed.request_id) {
+                    state
+                        .subscriptions
+                        .lock()
+                        .await
+                        .insert(subscribed.subscription_id, pending.subscription);
+                    _ = pending
+                        .sender
                         .send(SubscribeResponse {
                             subscription_id: subscribed.subscription_id,
                             error: None,
@@ -223,6 +852,18 @@ impl Session {
                 let mut unsubscribe_requests = state.unsubscribe_requests.lock().await;
                 if let Some(callback) = unsubscribe_requests.remove(&unsubscribed.request_id) {
                     _ = callback.send(None).await;
+                } else if let Some((subscription_id, reason)) =
+                    Revocation::from_details(&unsubscribed.details, "subscription")
+                {
+                    // no request of ours, the router revoked the subscription
+                    drop(unsubscribe_requests);
+                    let removed = state.subscriptions.lock().await.remove(&subscription_id).is_some();
+                    if removed && let Some(handler) = &state.revoked_handler {
+                        handler.invoke(Revocation::Subscription {
+                            subscription_id,
+                            reason,
+                        });
+                    }
                 }
             }
             MESSAGE_TYPE_PUBLISHED => {
@@ -234,17 +875,48 @@ impl Session {
             }
             MESSAGE_TYPE_EVENT => {
                 let event = msg.as_any().downcast_ref::<Event>().unwrap();
+                if state.closing.load(Ordering::SeqCst) {
+                    return;
+                }
+
                 let subscriptions = state.subscriptions.lock().await;
-                if let Some(callback) = subscriptions.get(&event.subscription_id) {
+                if let Some(subscription) = subscriptions.get(&event.subscription_id) {
                     let xevent = XEvent {
                         args: event.args.clone().map_or_else(Default::default, |args| args),
                         kwargs: event.kwargs.clone().map_or_else(Default::default, |kwargs| kwargs),
                         details: event.details.clone(),
+                        interrupt: Default::default(),
                     };
+                    if let Some(filter) = &subscription.filter
+                        && !filter.matches(&xevent)
+                    {
+                        return;
+                    }
 
-                    let callback = callback.clone();
-                    tokio::spawn(async move {
-                        callback.invoke(xevent).await;
+                    if let Some(stream) = subscription.stream.clone() {
+                        drop(subscriptions);
+                        _ = stream.send(xevent).await;
+                        return;
+                    }
+
+                    let finished = state.handler_started();
+                    if let Some(queue) = subscription.queue.clone() {
+                        // waits while the buffer is full, so (un)subscribing must not be blocked meanwhile
+                        drop(subscriptions);
+                        _ = queue.send((xevent, finished)).await;
+                        return;
+                    }
+
+                    let callback = subscription.callback.clone();
+                    drop(subscriptions);
+                    let task_state = state.clone();
+                    let permit = state.acquire_dispatch().await;
+                    state.dispatch(async move {
+                        let _finished = finished;
+                        let _permit = permit;
+                        if let Err(e) = callback.invoke(xevent).await {
+                            task_state.report(e);
+                        }
                     });
                 }
             }
@@ -252,10 +924,11 @@ impl Session {
                 let error = msg.as_any().downcast_ref::<ErrorMsg>().unwrap();
                 match error.message_type {
                     MESSAGE_TYPE_CALL => {
-                        let mut call_requests = state.call_requests.lock().await;
-                        if let Some(response) = call_requests.remove(&error.request_id) {
-                            let _ = response
-                                .send(CallResponse {
+                        let call = state.call_requests.lock().await.remove(&error.request_id);
+                        if let Some(call) = call {
+                            let _ = call
+                                .sender
+                                .send(CallUpdate::Done(CallResponse {
                                     args: None,
                                     kwargs: None,
                                     error: Some(WampError {
@@ -263,15 +936,16 @@ impl Session {
                                         args: error.args.clone(),
                                         kwargs: error.kwargs.clone(),
                                     }),
-                                })
+                                }))
                                 .await;
                         }
                     }
 
                     MESSAGE_TYPE_REGISTER => {
                         let mut register_requests = state.register_requests.lock().await;
-                        if let Some(response) = register_requests.remove(&error.request_id) {
-                            let _ = response
+                        if let Some(pending) = register_requests.remove(&error.request_id) {
+                            let _ = pending
+                                .sender
                                 .send(RegisterResponse {
                                     registration_id: 0,
                                     error: Some(WampError {
@@ -299,8 +973,9 @@ impl Session {
 
                     MESSAGE_TYPE_SUBSCRIBE => {
                         let mut subscribe_requests = state.subscribe_requests.lock().await;
-                        if let Some(response) = subscribe_requests.remove(&error.request_id) {
-                            let _ = response
+                        if let Some(pending) = subscribe_requests.remove(&error.request_id) {
+                            let _ = pending
+                                .sender
                                 .send(SubscribeResponse {
                                     subscription_id: 0,
                                     error: Some(WampError {
@@ -345,44 +1020,258 @@ impl Session {
                 }
             }
             MESSAGE_TYPE_GOODBYE => {
+                let goodbye = msg.as_any().downcast_ref::<Goodbye>().unwrap();
                 let goodbye_was_sent = { state.goodbye_sent.lock().await };
                 if *goodbye_was_sent {
-                    goodbye_sender.send(()).await.unwrap();
+                    _ = goodbye_sender.try_send(());
                 }
 
-                exist_sender.send(()).await.unwrap();
+                // nobody may be listening anymore if the session was dropped,
+                // so never block the reader on these.
+                _ = exist_sender.try_send(GoodbyeInfo {
+                    reason: goodbye.reason.clone(),
+                    details: goodbye.details.clone(),
+                });
             }
             _ => {}
         }
     }
 
+    /// The details the router sent on join, including the features it
+    /// supports. They change when the session reconnects.
+    pub fn details(&self) -> SessionDetails {
+        self.details.read().unwrap().clone()
+    }
+
+    /// The kind of transport the session runs over, one of the `TRANSPORT_*` constants.
+    pub fn transport(&self) -> TransportType {
+        self.peer().kind()
+    }
+
+    /// The serializer negotiated for the session, e.g. `cbor`. Unknown for
+    /// sessions created through `Session::new` rather than a client.
+    pub fn serializer_name(&self) -> Option<String> {
+        self.subprotocol
+            .as_ref()
+            .map(|subprotocol| subprotocol.trim_start_matches("wamp.2.").to_string())
+    }
+
+    /// Sends a message as is, for message types or options the session has no
+    /// API for. The session does not track it, so any answer is only visible
+    /// through the client's `on_message` hook.
+    pub async fn send_message(&self, msg: Box<dyn Message>) -> Result<(), Error> {
+        let to_send = self
+            .serializer
+            .serialize(msg.as_ref())
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+
+        self.peer()
+            .write(to_send)
+            .await
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
+
     pub async fn call(&self, request: CallRequest) -> Result<CallResponse, Error> {
+        let span = Span::call(request.uri());
+        let timeout = request.timeout();
+        let mut handle = match self.call_with_handle(request).await {
+            Ok(handle) => handle,
+            Err(e) => {
+                span.finish(Outcome::Error);
+                return Err(e);
+            }
+        };
+        let request_id = handle.request_id;
+        span.record_request_id(request_id);
+
+        let result = match timeout {
+            Some(timeout) => match span.instrument(tokio::time::timeout(timeout, handle.wait())).await {
+                Ok(response) => response.ok_or_else(|| self.state.dropped("call")),
+                Err(_) => {
+                    self.state.call_requests.lock().await.remove(&request_id);
+                    let cancelable = self.details.read().unwrap().supports("call_canceling");
+                    if cancelable {
+                        _ = self.send_cancel(request_id, CancelMode::KillNoWait).await;
+                    }
+
+                    span.finish(Outcome::Timeout);
+                    return Err(Error::new("call timed out"));
+                }
+            },
+            None => span.instrument(handle.response()).await,
+        };
+
+        span.finish(Outcome::of(&result));
+        result
+    }
+
+    /// Calls the procedure and returns its first positional result as `T`. An
+    /// error response, a result without arguments and one that doesn't
+    /// deserialize into `T` all return `Err`.
+    pub async fn call_typed<T: DeserializeOwned>(&self, request: CallRequest) -> Result<T, Error> {
+        self.call(request)
+            .await?
+            .into_result()
+            .map_err(|e| Error::new(format!("call failed: {e}")))?
+            .arg0()
+    }
+
+    /// Sends the call and returns as soon as it is written, without waiting
+    /// for a response. Only meant for procedures that are known not to reply;
+    /// this is not a publish, the dealer still routes it to a single callee.
+    /// Should a RESULT or ERROR arrive after all it is dropped, and with it
+    /// any error the call ran into. If the callee never answers, the dealer
+    /// may keep the request open on its side until the session ends.
+    pub async fn call_no_result(&self, request: CallRequest) -> Result<(), Error> {
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
+        let msg = request.into_call(self.idgen.next_id());
+        validate_uri(&msg.procedure, &msg.options, self.state.strict_uris)?;
+
+        let to_send = self
+            .serializer
+            .serialize(&msg)
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+        self.peer()
+            .write(to_send)
+            .await
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
+
+    /// Sends the call and returns a handle that exposes the request id and can
+    /// be used to cancel the call before awaiting its response.
+    pub async fn call_with_handle(&self, request: CallRequest) -> Result<CallHandle<'_>, Error> {
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
         let request_id = self.idgen.next_id();
-        let msg = request.to_call(request_id);
+        let progress = request.progress();
+        let receive_progress = request.wants_progress();
+        if receive_progress {
+            self.details.read().unwrap().require("progressive_call_results")?;
+        }
+        let msg = request.into_call(request_id);
+        validate_uri(&msg.procedure, &msg.options, self.state.strict_uris)?;
 
-        let (sender, mut receiver): (mpsc::Sender<CallResponse>, mpsc::Receiver<CallResponse>) = mpsc::channel(1);
+        let (sender, receiver) = mpsc::channel(self.state.call_buffer_size.max(1));
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         {
             let mut lock = self.state.call_requests.lock().await;
-            lock.insert(request_id, sender)
+            lock.insert(
+                request_id,
+                PendingCall {
+                    sender,
+                    receive_progress,
+                },
+            )
         };
 
-        self.peer
+        if let Err(e) = self.peer().write(to_send).await {
+            self.state.call_requests.lock().await.remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
+
+        Ok(CallHandle {
+            session: self,
+            request_id,
+            receiver,
+            progress,
+        })
+    }
+
+    /// Asks the dealer to cancel an in-flight call. With `CancelMode::Skip` the
+    /// pending call is resolved right away, otherwise it is resolved by the
+    /// ERROR the dealer sends once the cancellation went through. Fails without
+    /// touching the call if the dealer didn't advertise `call_canceling`.
+    pub async fn cancel(&self, request_id: i64, mode: CancelMode) -> Result<(), Error> {
+        self.details.read().unwrap().require("call_canceling")?;
+        if mode == CancelMode::Skip {
+            let call = self.state.call_requests.lock().await.remove(&request_id);
+            if let Some(call) = call {
+                // the caller may be the one to drain a full buffer, it then sees the call as dropped
+                _ = call.sender.try_send(CallUpdate::Done(CallResponse {
+                    args: None,
+                    kwargs: None,
+                    error: Some(WampError {
+                        uri: "wamp.error.canceled".to_string(),
+                        args: None,
+                        kwargs: None,
+                    }),
+                }));
+            }
+        }
+
+        self.send_cancel(request_id, mode).await
+    }
+
+    async fn send_cancel(&self, request_id: i64, mode: CancelMode) -> Result<(), Error> {
+        let msg = Cancel {
+            request_id,
+            options: HashMap::from([("mode".to_string(), mode.as_str().into())]),
+        };
+
+        let to_send = self
+            .serializer
+            .serialize(&msg)
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+
+        self.peer()
             .write(to_send)
             .await
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
 
-        let response = receiver.recv().await.ok_or_else(|| Error::new("call failed"))?;
+    pub async fn publish(&self, request: PublishRequest) -> Result<Option<PublishResponse>, Error> {
+        let (_, response) = self.publish_with_id(request).await?;
         Ok(response)
     }
 
-    pub async fn publish(&self, request: PublishRequest) -> Result<Option<PublishResponse>, Error> {
+    /// Like `publish`, but also returns the request id used for the PUBLISH,
+    /// e.g. for correlating it in logs. `call_with_handle` does the same for calls.
+    pub async fn publish_with_id(&self, request: PublishRequest) -> Result<(i64, Option<PublishResponse>), Error> {
+        let (request_id, pending) = self.send_publish(request).await?;
+        match pending {
+            Some(pending) => {
+                let response = self.wait_acknowledge(request_id, pending).await?;
+                Ok((request_id, Some(response)))
+            }
+            None => Ok((request_id, None)),
+        }
+    }
+
+    /// Publishes all requests back to back without waiting for acknowledgements
+    /// in between, then waits for all acknowledgements at once. The results are
+    /// in the same order as `requests`, so one failed publish doesn't hide the
+    /// outcome of the others.
+    pub async fn publish_batch(&self, requests: Vec<PublishRequest>) -> Vec<Result<Option<PublishResponse>, Error>> {
+        let mut pending = Vec::with_capacity(requests.len());
+        for request in requests {
+            pending.push(self.send_publish(request).await);
+        }
+
+        join_all(pending.into_iter().map(|sent| async move {
+            match sent {
+                Ok((request_id, Some(pending))) => self.wait_acknowledge(request_id, pending).await.map(Some),
+                Ok((_, None)) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }))
+        .await
+    }
+
+    /// Writes the PUBLISH and, if it asked for an acknowledgement, returns
+    /// the receiver the PUBLISHED (or ERROR) is delivered to along with how
+    /// long to wait for it.
+    async fn send_publish(&self, request: PublishRequest) -> Result<(i64, Option<PendingPublish>), Error> {
+        let timeout = request.timeout().unwrap_or(DEFAULT_ACKNOWLEDGE_TIMEOUT);
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
         let request_id = self.idgen.next_id();
-        let msg = request.to_publish(request_id);
+        let msg = request.into_publish(request_id);
+        validate_uri(&msg.topic, &msg.options, self.state.strict_uris)?;
 
         let acknowledge = {
             if let Some(Value::Bool(acknowledge)) = msg.options.get("acknowledge") {
@@ -395,18 +1284,17 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         if acknowledge {
-            let (sender, mut receiver): (mpsc::Sender<PublishResponse>, mpsc::Receiver<PublishResponse>) =
-                mpsc::channel(1);
+            let (sender, receiver): (mpsc::Sender<PublishResponse>, mpsc::Receiver<PublishResponse>) = mpsc::channel(1);
 
             {
                 let mut lock = self.state.publish_requests.lock().await;
                 lock.insert(request_id, sender)
             };
 
-            match self.peer.write(to_send).await {
+            match self.peer().write(to_send).await {
                 Ok(_) => (),
                 Err(e) => {
                     let mut lock = self.state.publish_requests.lock().await;
@@ -415,24 +1303,52 @@ impl Session {
                 }
             }
 
-            let response = receiver.recv().await.ok_or_else(|| Error::new("publish failed"))?;
-            Ok(Some(response))
+            Ok((request_id, Some((receiver, timeout))))
         } else {
-            self.peer
+            self.peer()
                 .write(to_send)
                 .await
                 .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
 
-            Ok(None)
+            Ok((request_id, None))
+        }
+    }
+
+    async fn wait_acknowledge(
+        &self,
+        request_id: i64,
+        (mut receiver, timeout): PendingPublish,
+    ) -> Result<PublishResponse, Error> {
+        match tokio::time::timeout(timeout, receiver.recv()).await {
+            Ok(response) => response.ok_or_else(|| self.state.dropped("publish")),
+            Err(_) => {
+                self.state.publish_requests.lock().await.remove(&request_id);
+                Err(Error::new("publish acknowledgement timed out"))
+            }
         }
     }
 
     pub async fn register(&self, request: RegisterRequest) -> Result<RegisterResponse, Error> {
+        validate_uri(&request.procedure(), request.options(), self.state.strict_uris)?;
+        if match_policy(request.options()) != "exact" {
+            self.details.read().unwrap().require("pattern_based_registration")?;
+        }
+        validate_ppt_options(request.result_options())?;
+        self.register_inner(Registration {
+            procedure: request.procedure(),
+            options: request.options().clone(),
+            result_options: request.result_options().clone(),
+            callback: request.callback(),
+        })
+        .await
+    }
+
+    async fn register_inner(&self, registration: Registration) -> Result<RegisterResponse, Error> {
         let request_id = self.idgen.next_id();
         let msg = Register {
             request_id,
-            options: request.options().clone(),
-            procedure: request.procedure(),
+            options: registration.options.clone(),
+            procedure: registration.procedure.clone(),
         };
 
         let (sender, mut receiver): (mpsc::Sender<RegisterResponse>, mpsc::Receiver<RegisterResponse>) =
@@ -441,34 +1357,97 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         {
             let mut lock = self.state.register_requests.lock().await;
-            lock.insert(request_id, sender)
+            lock.insert(request_id, PendingRegister { sender, registration })
         };
 
-        self.peer
-            .write(to_send)
-            .await
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
-
-        let response = receiver.recv().await.ok_or_else(|| Error::new("register failed"))?;
-        self.state
-            .registrations
-            .lock()
-            .await
-            .insert(response.registration_id, request.callback());
+        if let Err(e) = self.peer().write(to_send).await {
+            self.state.register_requests.lock().await.remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
 
-        Ok(response)
+        receiver.recv().await.ok_or_else(|| Error::new("register failed"))
     }
 
     pub async fn subscribe(&self, request: SubscribeRequest) -> Result<SubscribeResponse, Error> {
+        validate_uri(&request.topic(), request.options(), self.state.strict_uris)?;
+        if match_policy(request.options()) != "exact" {
+            self.details.read().unwrap().require("pattern_based_subscription")?;
+        }
+        // manually dispatched events already run one after another
+        let ordered = request.is_ordered() && self.state.manual.is_none();
+        let queue = ordered.then(|| {
+            let (sender, mut receiver) = mpsc::channel(self.state.event_buffer_size.max(1));
+            let callback = request.callback();
+            // a strong reference would keep the subscription, and with it the
+            // sender, alive forever, this way the task ends once both are dropped
+            let state: Weak<State> = Arc::downgrade(&self.state);
+            tokio::spawn(async move {
+                while let Some((event, _finished)) = receiver.recv().await {
+                    if let Err(e) = callback.invoke(event).await
+                        && let Some(state) = state.upgrade()
+                    {
+                        state.report(e);
+                    }
+                }
+            });
+            sender
+        });
+
+        self.subscribe_inner(Subscription {
+            topic: request.topic(),
+            options: request.options().clone(),
+            callback: request.callback(),
+            queue,
+            stream: None,
+            filter: request.event_filter(),
+        })
+        .await
+    }
+
+    /// Subscribes to `topic` and hands out its events as a stream instead of
+    /// passing them to a callback. Up to `DEFAULT_EVENT_BUFFER_SIZE` events,
+    /// or what the client configured, are buffered while the stream is not
+    /// polled; after that the session stops reading from the connection.
+    /// Dropping the stream does not unsubscribe, later events are discarded.
+    pub async fn subscribe_stream<S: Into<String>>(
+        &self,
+        topic: S,
+        options: HashMap<String, Value>,
+    ) -> Result<(SubscribeResponse, impl Stream<Item = XEvent> + use<S>), Error> {
+        let topic = topic.into();
+        validate_uri(&topic, &options, self.state.strict_uris)?;
+        if match_policy(&options) != "exact" {
+            self.details.read().unwrap().require("pattern_based_subscription")?;
+        }
+
+        let (sender, receiver) = mpsc::channel(self.state.event_buffer_size.max(1));
+        let response = self
+            .subscribe_inner(Subscription {
+                topic,
+                options,
+                callback: EventFn(Arc::new(|_| Box::pin(async { Ok(()) }))),
+                queue: None,
+                stream: Some(sender),
+                filter: None,
+            })
+            .await?;
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|event| (event, receiver))
+        });
+        Ok((response, events))
+    }
+
+    async fn subscribe_inner(&self, subscription: Subscription) -> Result<SubscribeResponse, Error> {
         let request_id = self.idgen.next_id();
         let msg = Subscribe {
             request_id,
-            options: request.options().clone(),
-            topic: request.topic(),
+            options: subscription.options.clone(),
+            topic: subscription.topic.clone(),
         };
 
         let (sender, mut receiver): (mpsc::Sender<SubscribeResponse>, mpsc::Receiver<SubscribeResponse>) =
@@ -477,26 +1456,74 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         {
             let mut lock = self.state.subscribe_requests.lock().await;
-            lock.insert(request_id, sender)
+            lock.insert(request_id, PendingSubscribe { sender, subscription })
         };
 
-        self.peer
-            .write(to_send)
-            .await
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
+        if let Err(e) = self.peer().write(to_send).await {
+            self.state.subscribe_requests.lock().await.remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
 
-        let response = receiver.recv().await.ok_or_else(|| Error::new("subscribe failed"))?;
-        self.state
-            .subscriptions
-            .lock()
-            .await
-            .insert(response.subscription_id, request.callback());
+        receiver.recv().await.ok_or_else(|| Error::new("subscribe failed"))
+    }
 
-        Ok(response)
+    /// The registrations the session currently holds as `(registration id,
+    /// procedure)`, ordered by id. Only reflects what the router confirmed,
+    /// e.g. for a health endpoint.
+    pub async fn registrations(&self) -> Vec<(i64, String)> {
+        let registrations = self.state.registrations.lock().await;
+        let mut listed: Vec<(i64, String)> = registrations
+            .iter()
+            .map(|(id, registration)| (*id, registration.procedure.clone()))
+            .collect();
+        listed.sort();
+        listed
+    }
+
+    /// The subscriptions the session currently holds as `(subscription id,
+    /// topic)`, ordered by id, including stream
+    /// subscriptions.
+    pub async fn subscriptions(&self) -> Vec<(i64, String)> {
+        let subscriptions = self.state.subscriptions.lock().await;
+        let mut listed: Vec<(i64, String)> = subscriptions
+            .iter()
+            .map(|(id, subscription)| (*id, subscription.topic.clone()))
+            .collect();
+        listed.sort();
+        listed
+    }
+
+    /// The current subscriptions of the session, to subscribe them again on a
+    /// new session with `restore`. Subscriptions consumed as a stream are left
+    /// out, their events have nowhere to go on another session.
+    pub async fn registry(&self) -> SubscriptionRegistry {
+        let mut registry = SubscriptionRegistry::new();
+        for subscription in self.state.subscriptions.lock().await.values() {
+            if subscription.stream.is_some() {
+                continue;
+            }
+            let spec = SubscriptionSpec {
+                topic: subscription.topic.clone(),
+                options: subscription.options.clone(),
+                ordered: subscription.queue.is_some(),
+            };
+            registry.insert(spec, subscription.callback.clone(), subscription.filter.clone());
+        }
+        registry
+    }
+
+    /// Subscribes everything in `registry`, returning one result per entry in
+    /// the registry's order.
+    pub async fn restore(&self, registry: &SubscriptionRegistry) -> Vec<Result<SubscribeResponse, Error>> {
+        let mut responses = Vec::with_capacity(registry.len());
+        for request in registry.requests() {
+            responses.push(self.subscribe(request).await);
+        }
+        responses
     }
 
     pub async fn leave(&self) -> Result<(), Error> {
@@ -508,9 +1535,12 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
-        self.peer
+        // also keeps the reader from reconnecting once the router closes the connection
+        *self.state.goodbye_sent.lock().await = true;
+
+        self.peer()
             .write(to_send)
             .await
             .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
@@ -523,7 +1553,681 @@ impl Session {
             .ok_or_else(|| Error::new("failed to send message"))
     }
 
-    pub async fn wait_disconnect(&self) {
-        self.exist_receiver_channel.lock().await.recv().await;
+    /// Leaves the session once the work already accepted is done. New
+    /// invocations are answered with `wamp.error.unavailable` and new events
+    /// are dropped, while the running handlers get up to `timeout` to finish.
+    /// Writes are sent in order, so everything the handlers wrote goes out
+    /// ahead of the GOODBYE.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.state.closing.store(true, Ordering::SeqCst);
+
+        _ = tokio::time::timeout(timeout, self.state.wait_idle()).await;
+
+        self.leave().await
+    }
+
+    /// Closes the connection without leaving the realm and returns once the
+    /// reader task has stopped. Requests still waiting for an answer fail.
+    /// Call `leave` first to end the session with a GOODBYE. Dropping the last
+    /// clone of the session does the same, but a handler that holds a clone
+    /// keeps the session alive.
+    pub async fn close(&self) {
+        self.state.closing.store(true, Ordering::SeqCst);
+        let reader = self.reader.lock().unwrap().take();
+        if let Some(reader) = reader {
+            reader.abort();
+            _ = reader.await;
+        }
+        self.peer().close().await;
+
+        // the reader didn't get to clean up after itself
+        *self.state.closed.write().unwrap() = Some("session closed".to_string());
+        self.state.clear_pending().await;
+        self.state.close_dispatch();
+        if *self.state.goodbye_sent.lock().await {
+            _ = self.goodbye_sender.try_send(());
+        }
+        _ = self.exit_sender.try_send(GoodbyeInfo {
+            reason: "wamp.close.normal".to_string(),
+            details: Default::default(),
+        });
+    }
+
+    /// Runs the next queued invocation or event handler to completion on the
+    /// calling task and returns true, or returns false right away if none is
+    /// waiting. Handlers are only queued if the client was built with
+    /// `manual_dispatch`, otherwise this always returns false.
+    pub async fn poll_once(&self) -> bool {
+        match &self.state.manual {
+            Some(manual) => manual.poll_once().await,
+            None => false,
+        }
+    }
+
+    /// Runs queued invocation and event handlers on the calling task as they
+    /// come in, until the session is gone for good. Keeps going across
+    /// reconnects. Returns right away unless the client was built with
+    /// `manual_dispatch`.
+    pub async fn run(&self) {
+        if let Some(manual) = &self.state.manual {
+            manual.run().await;
+        }
+    }
+
+    /// Calls `heartbeat.procedure` every `heartbeat.interval` on a background
+    /// task and closes the connection when a call goes unanswered, which ends
+    /// the session like a dropped connection would, or rejoins it if the
+    /// client reconnects. Complements the transport keepalive with a check
+    /// that RPCs make it through the router.
+    pub fn enable_heartbeat(&self, heartbeat: Heartbeat) {
+        let mut session = self.clone();
+        session.closer = None;
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(heartbeat.interval).await;
+                if session.state.closing.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                if let Err(e) = session.call(heartbeat.request()).await {
+                    if session.state.closing.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if session.state.closed.read().unwrap().is_none() {
+                        session
+                            .state
+                            .report(Error::new(format!("heartbeat failed, closing the connection: {e}")));
+                        session.peer().close().await;
+                    }
+                    if session.reconnect.is_none() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Waits until the router ends the session and returns the reason it gave.
+    pub async fn wait_disconnect(&self) -> GoodbyeInfo {
+        self.exist_receiver_channel
+            .lock()
+            .await
+            .recv()
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Like `wait_disconnect` but gives up after `timeout`, returns whether the
+    /// session disconnected in time.
+    pub async fn wait_disconnect_timeout(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.wait_disconnect()).await.is_ok()
+    }
+}
+
+/// An in-flight call returned by `Session::call_with_handle`.
+#[derive(Debug)]
+pub struct CallHandle<'a> {
+    session: &'a Session,
+    request_id: i64,
+    receiver: mpsc::Receiver<CallUpdate>,
+    progress: Option<ProgressFn>,
+}
+
+impl CallHandle<'_> {
+    pub fn request_id(&self) -> i64 {
+        self.request_id
+    }
+
+    pub async fn cancel(&self, mode: CancelMode) -> Result<(), Error> {
+        self.session.cancel(self.request_id, mode).await
+    }
+
+    /// Waits for the final response, invoking the progress callback for
+    /// every progressive result before it on the way.
+    pub async fn response(mut self) -> Result<CallResponse, Error> {
+        self.wait().await.ok_or_else(|| self.session.state.dropped("call"))
+    }
+
+    async fn wait(&mut self) -> Option<CallResponse> {
+        loop {
+            match self.receiver.recv().await? {
+                CallUpdate::Progress(response) => {
+                    if let Some(callback) = &self.progress {
+                        callback.invoke(response);
+                    }
+                }
+                CallUpdate::Done(response) => return Some(response),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_::memory::MemoryPeer;
+    use crate::async_::memory::router::RouterStub;
+    use crate::common::types::{InvokePolicy, PayloadSerializer, Yield as XYield};
+    use std::time::Instant;
+    use wampproto::messages::call::Call;
+    use wampproto::serializers::json::JSONSerializer;
+
+    async fn failing(_: XInvocation) -> XYield {
+        panic!("handler failed")
+    }
+
+    fn limited(limit: usize) -> SessionOptions {
+        SessionOptions {
+            max_concurrent_invocations: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_limit_of_zero_still_runs_handlers() {
+        let router = RouterStub::new();
+        let callee = router.session_with(limited(0));
+        let caller = router.session();
+        let echo = RegisterRequest::new("io.xconn.echo", |inv: XInvocation| async move {
+            XYield::new(inv.args, inv.kwargs)
+        });
+        callee.register(echo).await.unwrap();
+
+        let request = CallRequest::new("io.xconn.echo")
+            .arg("hello")
+            .with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).await.unwrap();
+        assert_eq!(response.args, Some(vec![Value::Str("hello".to_string())]));
+    }
+
+    #[tokio::test]
+    async fn dispatch_limit_of_one_runs_handlers_one_at_a_time() {
+        let router = RouterStub::new();
+        let callee = router.session_with(limited(1));
+        let caller = router.session();
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (handler_running, handler_most) = (running.clone(), most.clone());
+        let slow = RegisterRequest::new("io.xconn.slow", move |_: XInvocation| {
+            let (running, most) = (handler_running.clone(), handler_most.clone());
+            async move {
+                most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                XYield::default()
+            }
+        });
+        callee.register(slow).await.unwrap();
+
+        let (first, second) = tokio::join!(
+            caller.call(CallRequest::new("io.xconn.slow")),
+            caller.call(CallRequest::new("io.xconn.slow"))
+        );
+        assert!(first.unwrap().error.is_none());
+        assert!(second.unwrap().error.is_none());
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shutdown_does_not_wait_for_a_handler_that_panicked() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+        callee
+            .register(RegisterRequest::new("io.xconn.fail", failing))
+            .await
+            .unwrap();
+
+        // no result ever comes back, the timeout only gives the handler time to run
+        let request = CallRequest::new("io.xconn.fail").with_timeout(Duration::from_millis(200));
+        assert!(caller.call(request).await.is_err());
+
+        let started = Instant::now();
+        callee.shutdown(Duration::from_secs(5)).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    fn details() -> SessionDetails {
+        SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string())
+    }
+
+    /// Reads the next CALL the session wrote to `router` and returns its request id.
+    async fn call_id(router: &dyn Peer) -> i64 {
+        let payload = router.read().await.unwrap();
+        let msg = JSONSerializer {}.deserialize(payload).unwrap();
+        msg.as_any().downcast_ref::<Call>().unwrap().request_id
+    }
+
+    async fn wait_for(events: &mut mpsc::UnboundedReceiver<ReconnectEvent>, wanted: fn(&ReconnectEvent) -> bool) {
+        while let Some(event) = events.recv().await {
+            if wanted(&event) {
+                return;
+            }
+        }
+        panic!("reconnect listener dropped");
+    }
+
+    #[tokio::test]
+    async fn reconnect_fails_stale_requests_and_keeps_ids_unique() {
+        let (client, router) = MemoryPeer::pair();
+        let (next_client, next_router) = MemoryPeer::pair();
+
+        // the rejoin waits for the test, which keeps the reconnect window open
+        let release = Arc::new(Notify::new());
+        let join_release = release.clone();
+        let next_client = Arc::new(StdMutex::new(Some(next_client)));
+        let join: Arc<dyn Fn() -> JoinFuture + Send + Sync> = Arc::new(move || -> JoinFuture {
+            let release = join_release.clone();
+            let client = next_client.lock().unwrap().take();
+            Box::pin(async move {
+                release.notified().await;
+                client
+                    .map(|client| (client, details()))
+                    .ok_or_else(|| Error::new("no connection left"))
+            })
+        });
+
+        let (events, mut received) = mpsc::unbounded_channel();
+        let options = SessionOptions {
+            reconnect: Some(Reconnect {
+                policy: ReconnectPolicy::new()
+                    .with_backoff(Duration::ZERO, Duration::ZERO)
+                    .with_jitter(0.0),
+                join,
+                listener: Some(ReconnectFn(Arc::new(move |event| {
+                    _ = events.send(event);
+                }))),
+            }),
+            ..Default::default()
+        };
+        let session = Session::with_options(details(), client, Box::new(JSONSerializer {}), options);
+
+        let first = session
+            .call_with_handle(CallRequest::new("io.xconn.test"))
+            .await
+            .unwrap();
+        let first_id = call_id(router.as_ref()).await;
+
+        // a message the session can't parse ends its reader, which starts reconnecting
+        router.write(b"not wamp".to_vec()).await.unwrap();
+        wait_for(&mut received, |event| matches!(event, ReconnectEvent::Disconnected)).await;
+        assert!(first.response().await.is_err());
+
+        // still goes out over the old connection, which won't ever answer it
+        let stale = session
+            .call_with_handle(CallRequest::new("io.xconn.test"))
+            .await
+            .unwrap();
+        let stale_id = call_id(router.as_ref()).await;
+
+        release.notify_one();
+        wait_for(&mut received, |event| matches!(event, ReconnectEvent::Reconnected(_))).await;
+        assert!(stale.response().await.is_err());
+
+        let _fresh = session
+            .call_with_handle(CallRequest::new("io.xconn.test"))
+            .await
+            .unwrap();
+        let fresh_id = call_id(next_router.as_ref()).await;
+        assert_ne!(fresh_id, first_id);
+        assert_ne!(fresh_id, stale_id);
+    }
+
+    #[tokio::test]
+    async fn progressive_results_beyond_the_call_buffer_all_arrive() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session_with(SessionOptions {
+            call_buffer_size: Some(1),
+            ..Default::default()
+        });
+        let stream = RegisterRequest::progressive(
+            "io.xconn.stream",
+            |_: XInvocation, progress: ProgressSender| async move {
+                for chunk in 0..5 {
+                    progress
+                        .send(XYield::new(vec![Value::Int(chunk)], Default::default()))
+                        .await
+                        .unwrap();
+                }
+                XYield::new(vec![Value::Str("done".to_string())], Default::default())
+            },
+        );
+        callee.register(stream).await.unwrap();
+
+        let chunks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = chunks.clone();
+        let request = CallRequest::new("io.xconn.stream")
+            .on_progress(move |response| {
+                // a slow callback, the reader waits for it once the buffer is full
+                std::thread::sleep(Duration::from_millis(10));
+                received.lock().unwrap().push(response.args.unwrap());
+            })
+            .with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).await.unwrap();
+
+        assert_eq!(response.args, Some(vec![Value::Str("done".to_string())]));
+        let expected: Vec<_> = (0..5).map(|chunk| vec![Value::Int(chunk)]).collect();
+        assert_eq!(*chunks.lock().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn publish_sends_its_payload_with_the_payload_serializer() {
+        let router = RouterStub::new();
+        let publisher = router.session();
+        let request = PublishRequest::new("io.xconn.topic")
+            .arg("hello")
+            .kwarg("key", 1)
+            .payload_serializer(PayloadSerializer::Json)
+            .acknowledge();
+        publisher.publish(request).await.unwrap();
+
+        let received = router.received();
+        let (_, publish) = received.iter().find(|(_, msg)| msg[0] == 16).unwrap();
+        assert_eq!(publish[2]["ppt_serializer"], "json");
+        assert_eq!(publish[2]["ppt_scheme"], "wamp");
+        // the encoded payload is the only argument, without kwargs
+        assert_eq!(publish.as_array().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn manual_dispatch_runs_one_queued_handler_per_poll() {
+        let router = RouterStub::new();
+        let session = router.session_with(SessionOptions {
+            manual_dispatch: true,
+            ..Default::default()
+        });
+        let peer = Arc::new(router.session());
+
+        let handled = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = handled.clone();
+        let on_event = move |event: XEvent| {
+            seen.lock().unwrap().push(event.args);
+            async {}
+        };
+        session
+            .subscribe(SubscribeRequest::new("io.xconn.topic", on_event))
+            .await
+            .unwrap();
+        let seen = handled.clone();
+        let echo = RegisterRequest::new("io.xconn.echo", move |inv: XInvocation| {
+            seen.lock().unwrap().push(inv.args.clone());
+            async move { XYield::new(inv.args, inv.kwargs) }
+        });
+        session.register(echo).await.unwrap();
+
+        for n in 0..2 {
+            peer.publish(PublishRequest::new("io.xconn.topic").arg(n).acknowledge())
+                .await
+                .unwrap();
+        }
+        let caller = {
+            let peer = peer.clone();
+            tokio::spawn(async move {
+                let request = CallRequest::new("io.xconn.echo")
+                    .arg(2)
+                    .with_timeout(Duration::from_secs(5));
+                peer.call(request).await
+            })
+        };
+
+        for expected in 1..=3 {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !session.poll_once().await {
+                assert!(Instant::now() < deadline, "handler {expected} was never queued");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(handled.lock().unwrap().len(), expected);
+        }
+        assert!(!session.poll_once().await);
+
+        let response = caller.await.unwrap().unwrap();
+        assert_eq!(response.args, Some(vec![Value::Int(2)]));
+        assert_eq!(
+            *handled.lock().unwrap(),
+            vec![vec![Value::Int(0)], vec![Value::Int(1)], vec![Value::Int(2)]]
+        );
+    }
+
+    #[tokio::test]
+    async fn leave_returns_when_the_router_closes_without_goodbye() {
+        let (client, router) = MemoryPeer::pair();
+        let session = Arc::new(Session::new(details(), client, Box::new(JSONSerializer {})));
+
+        let waiter = {
+            let session = session.clone();
+            tokio::spawn(async move { session.wait_disconnect_timeout(Duration::from_secs(5)).await })
+        };
+        let closer = tokio::spawn(async move {
+            let msg = JSONSerializer {}.deserialize(router.read().await.unwrap()).unwrap();
+            assert_eq!(msg.message_type(), MESSAGE_TYPE_GOODBYE);
+            // closes the connection instead of answering
+            drop(router);
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), session.leave())
+            .await
+            .unwrap()
+            .unwrap();
+        closer.await.unwrap();
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn callee_sees_the_disclosed_caller() {
+        async fn reply_caller(inv: XInvocation) -> XYield {
+            XYield::new(inv.caller().into_iter().map(Value::Int).collect(), Default::default())
+        }
+
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+        let disclosed = RegisterRequest::new("io.xconn.disclosed", reply_caller).disclose_caller(true);
+        callee.register(disclosed).await.unwrap();
+        callee
+            .register(RegisterRequest::new("io.xconn.plain", reply_caller))
+            .await
+            .unwrap();
+
+        let caller_id = Some(vec![Value::Int(caller.details().id())]);
+        for (procedure, disclose_me) in [("io.xconn.disclosed", false), ("io.xconn.plain", true)] {
+            let request = CallRequest::new(procedure)
+                .disclose_me(disclose_me)
+                .with_timeout(Duration::from_secs(5));
+            assert_eq!(caller.call(request).await.unwrap().args, caller_id, "{procedure}");
+        }
+
+        let request = CallRequest::new("io.xconn.plain").with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).await.unwrap();
+        assert!(response.args.unwrap_or_default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn router_revocations_drop_the_handler_and_are_reported() {
+        let router = RouterStub::new();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let session = router.session_with(SessionOptions {
+            revoked_handler: Some(RevokedFn(Arc::new(move |revocation| {
+                _ = sender.send(revocation);
+            }))),
+            ..Default::default()
+        });
+        let echo = RegisterRequest::new("io.xconn.echo", |inv: XInvocation| async move {
+            XYield::new(inv.args, inv.kwargs)
+        });
+        let registered = session.register(echo).await.unwrap();
+        let subscribed = session
+            .subscribe(SubscribeRequest::new("io.xconn.topic", |_: XEvent| async {}))
+            .await
+            .unwrap();
+
+        router.revoke(registered.registration_id, "wamp.error.testing").await;
+        let revocation = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap();
+        match revocation.unwrap() {
+            Revocation::Registration {
+                registration_id,
+                reason,
+            } => {
+                assert_eq!(registration_id, registered.registration_id);
+                assert_eq!(reason.as_deref(), Some("wamp.error.testing"));
+            }
+            revocation => panic!("unexpected {revocation:?}"),
+        }
+        assert!(session.registrations().await.is_empty());
+
+        router.revoke(subscribed.subscription_id, "wamp.error.testing").await;
+        let revocation = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap();
+        match revocation.unwrap() {
+            Revocation::Subscription { subscription_id, .. } => assert_eq!(subscription_id, subscribed.subscription_id),
+            revocation => panic!("unexpected {revocation:?}"),
+        }
+        assert!(session.subscriptions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn calls_right_after_register_always_reach_the_handler() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+
+        for n in 0..100 {
+            let procedure = format!("io.xconn.stress.{n}");
+            let echo = RegisterRequest::new(procedure.clone(), |inv: XInvocation| async move {
+                XYield::new(inv.args, inv.kwargs)
+            });
+            callee.register(echo).await.unwrap();
+
+            let request = CallRequest::new(procedure).arg(n).with_timeout(Duration::from_secs(5));
+            let response = caller.call(request).await.unwrap();
+            assert_eq!(response.args, Some(vec![Value::Int(i64::from(n))]), "call {n}");
+        }
+    }
+
+    /// Fails to write the first YIELD, as if the connection hiccuped right then.
+    #[derive(Debug)]
+    struct FailingYield {
+        inner: Box<dyn Peer>,
+        failed: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl Peer for FailingYield {
+        fn kind(&self) -> TransportType {
+            self.inner.kind()
+        }
+
+        async fn read(&self) -> Result<Vec<u8>, Error> {
+            self.inner.read().await
+        }
+
+        async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+            if data.starts_with(b"[70,") && !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::new("simulated write failure"));
+            }
+            self.inner.write(data).await
+        }
+
+        async fn close(&self) {
+            self.inner.close().await
+        }
+    }
+
+    #[tokio::test]
+    async fn yield_that_fails_to_send_is_answered_with_an_error() {
+        let router = RouterStub::new();
+        let (details, peer) = router.connect();
+        let peer = Box::new(FailingYield {
+            inner: peer,
+            failed: AtomicBool::new(false),
+        });
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let options = SessionOptions {
+            error_handler: Some(ErrorFn(Arc::new(move |e: Error| {
+                _ = sender.send(e.to_string());
+            }))),
+            ..Default::default()
+        };
+        let callee = Session::with_options(details, peer, Box::new(JSONSerializer {}), options);
+        let caller = router.session();
+        let echo = RegisterRequest::new("io.xconn.echo", |inv: XInvocation| async move {
+            XYield::new(inv.args, inv.kwargs)
+        });
+        callee.register(echo).await.unwrap();
+
+        let request = CallRequest::new("io.xconn.echo")
+            .arg("hello")
+            .with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).await.unwrap();
+        assert_eq!(response.error.unwrap().uri, "wamp.error.runtime_error");
+        let reported = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(reported.contains("simulated write failure"), "{reported}");
+    }
+
+    #[tokio::test]
+    async fn prefix_subscription_receives_events_for_sub_topics() {
+        let router = RouterStub::new();
+        let subscriber = router.session();
+        let publisher = router.session();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let on_event = move |event: XEvent| {
+            _ = sender.send(event.topic());
+            async {}
+        };
+        subscriber
+            .subscribe(SubscribeRequest::new("io.xconn.", on_event).match_prefix())
+            .await
+            .unwrap();
+
+        for topic in ["io.other.topic", "io.xconn.a", "io.xconn.b.c"] {
+            publisher
+                .publish(PublishRequest::new(topic).acknowledge())
+                .await
+                .unwrap();
+        }
+
+        let mut topics = Vec::new();
+        for _ in 0..2 {
+            let topic = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+                .await
+                .unwrap();
+            topics.push(topic.unwrap());
+        }
+        assert_eq!(
+            topics,
+            vec![Some("io.xconn.a".to_string()), Some("io.xconn.b.c".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn roundrobin_calls_alternate_between_shared_callees() {
+        let router = RouterStub::new();
+        let callees = [router.session(), router.session()];
+        for callee in &callees {
+            let id = callee.details().id();
+            let request = RegisterRequest::new("io.xconn.shared", move |_: XInvocation| async move {
+                XYield::new(vec![Value::Int(id)], Default::default())
+            })
+            .invoke_policy(InvokePolicy::RoundRobin);
+            callee.register(request).await.unwrap();
+        }
+
+        let caller = router.session();
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            let request = CallRequest::new("io.xconn.shared").with_timeout(Duration::from_secs(5));
+            served.push(caller.call(request).await.unwrap().args.unwrap());
+        }
+        let [first, second] = callees.map(|callee| vec![Value::Int(callee.details().id())]);
+        assert_eq!(served, vec![first.clone(), second.clone(), first, second]);
     }
 }
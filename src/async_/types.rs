@@ -1,10 +1,17 @@
+use crate::async_::peer::Peer;
+use crate::common::ppt::{PPT_CIPHER, PPT_KEYID, PPT_SCHEME, PPT_SERIALIZER, payload, validate_ppt};
+use futures_util::FutureExt;
 use std::collections::HashMap;
 use std::fmt;
+use std::panic::AssertUnwindSafe;
 use std::pin::Pin;
 use std::sync::Arc;
+use wampproto::messages::yield_::Yield as YieldMsg;
+use wampproto::serializers::serializer::Serializer;
 
-type RegisterCallbackType = dyn Fn(Invocation) -> Pin<Box<dyn Future<Output = Yield> + Send>> + Send + Sync;
-type EventCallbackType = dyn Fn(Event) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync;
+type RegisterCallbackType =
+    dyn Fn(Invocation, ProgressSender) -> Pin<Box<dyn Future<Output = Yield> + Send>> + Send + Sync;
+type EventCallbackType = dyn Fn(Event) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send>> + Send + Sync;
 
 #[derive(Clone)]
 pub struct RegisterFn(pub Arc<RegisterCallbackType>);
@@ -16,8 +23,60 @@ impl fmt::Debug for RegisterFn {
 }
 
 impl RegisterFn {
-    pub async fn invoke(&self, inv: Invocation) -> Yield {
-        self.0(inv).await
+    pub async fn invoke(&self, inv: Invocation, progress: ProgressSender) -> Yield {
+        self.0(inv, progress).await
+    }
+}
+
+/// Sends progressive results for an in-flight invocation. Every progressive
+/// YIELD reuses the invocation's request id; the value returned by the handler
+/// is sent as the final YIELD. Only send progress if
+/// `Invocation::wants_progress` is true.
+#[derive(Clone, Debug)]
+pub struct ProgressSender {
+    request_id: i64,
+    serializer: Arc<Box<dyn Serializer>>,
+    peer: Arc<Box<dyn Peer>>,
+    options: HashMap<String, Value>,
+}
+
+impl ProgressSender {
+    pub(crate) fn new(
+        request_id: i64,
+        serializer: Arc<Box<dyn Serializer>>,
+        peer: Arc<Box<dyn Peer>>,
+        options: HashMap<String, Value>,
+    ) -> Self {
+        Self {
+            request_id,
+            serializer,
+            peer,
+            options,
+        }
+    }
+
+    pub async fn send(&self, progress: Yield) -> Result<(), Error> {
+        validate_ppt(&self.options, &progress.args, &progress.kwargs)?;
+
+        let mut options = self.options.clone();
+        options.insert("progress".to_string(), Value::Bool(true));
+        let (args, kwargs) = payload(&options, progress.args, progress.kwargs);
+        let msg = YieldMsg {
+            request_id: self.request_id,
+            options,
+            args,
+            kwargs,
+        };
+
+        let to_send = self
+            .serializer
+            .serialize(&msg)
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+
+        self.peer
+            .write(to_send)
+            .await
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
     }
 }
 
@@ -31,14 +90,20 @@ impl fmt::Debug for EventFn {
 }
 
 impl EventFn {
-    pub async fn invoke(&self, event: Event) {
-        self.0(event).await
+    /// Runs the handler. A panicking handler is reported as an error, so it
+    /// doesn't take the delivery of later events down with it.
+    pub async fn invoke(&self, event: Event) -> Result<(), Error> {
+        match AssertUnwindSafe(self.0(event)).catch_unwind().await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new("event handler panicked")),
+        }
     }
 }
 
 pub struct RegisterRequest {
     procedure: String,
     options: HashMap<String, Value>,
+    result_options: HashMap<String, Value>,
 
     callback: RegisterFn,
 }
@@ -53,7 +118,24 @@ impl RegisterRequest {
         Self {
             procedure: procedure.into(),
             options: HashMap::new(),
-            callback: RegisterFn(Arc::new(move |inv| Box::pin(callback(inv)))),
+            result_options: HashMap::new(),
+            callback: RegisterFn(Arc::new(move |inv, _| Box::pin(callback(inv)))),
+        }
+    }
+
+    /// Like `new`, but the handler also receives a `ProgressSender` that can be
+    /// used to stream progressive results before returning the final one.
+    pub fn progressive<S, F, Fut>(procedure: S, callback: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(Invocation, ProgressSender) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Yield> + Send + 'static,
+    {
+        Self {
+            procedure: procedure.into(),
+            options: HashMap::new(),
+            result_options: HashMap::new(),
+            callback: RegisterFn(Arc::new(move |inv, progress| Box::pin(callback(inv, progress)))),
         }
     }
 
@@ -67,10 +149,67 @@ impl RegisterRequest {
         self
     }
 
+    /// Asks the dealer to include the caller's identity in invocation details.
+    pub fn disclose_caller(self, disclose_caller: bool) -> Self {
+        self.with_option("disclose_caller", Value::Bool(disclose_caller))
+    }
+
+    /// Lets other sessions register the same procedure, with calls being
+    /// distributed among them according to `policy`.
+    pub fn invoke_policy(self, policy: InvokePolicy) -> Self {
+        self.with_option("invoke", policy.as_str())
+    }
+
+    /// Registers every procedure starting with the given procedure.
+    pub fn match_prefix(self) -> Self {
+        self.with_option("match", "prefix")
+    }
+
+    /// Treats empty URI components of the procedure as wildcards.
+    pub fn match_wildcard(self) -> Self {
+        self.with_option("match", "wildcard")
+    }
+
+    /// Marks the results of the procedure as passthrough (PPT) payloads, sent
+    /// to the caller untouched. Every result must then be exactly one argument
+    /// without kwargs; a result that isn't is answered with
+    /// `wamp.error.invalid_argument`. See `CallRequest::ppt_scheme` for the
+    /// accepted schemes. These options go on the YIELD, not the REGISTER.
+    pub fn ppt_scheme(mut self, scheme: &str) -> Self {
+        self.result_options.insert(PPT_SCHEME.to_string(), scheme.into());
+        self
+    }
+
+    /// The serializer the passthrough results are encoded with, e.g. `cbor`.
+    pub fn ppt_serializer(mut self, serializer: &str) -> Self {
+        self.result_options
+            .insert(PPT_SERIALIZER.to_string(), serializer.into());
+        self
+    }
+
+    /// The cipher the passthrough results are encrypted with. Requires
+    /// `ppt_serializer`.
+    pub fn ppt_cipher(mut self, cipher: &str) -> Self {
+        self.result_options.insert(PPT_CIPHER.to_string(), cipher.into());
+        self
+    }
+
+    /// Identifies the key the passthrough results are encrypted with.
+    /// Requires `ppt_serializer`.
+    pub fn ppt_keyid(mut self, keyid: &str) -> Self {
+        self.result_options.insert(PPT_KEYID.to_string(), keyid.into());
+        self
+    }
+
     pub fn options(&self) -> &HashMap<String, Value> {
         &self.options
     }
 
+    /// Options sent along with every result of the procedure.
+    pub fn result_options(&self) -> &HashMap<String, Value> {
+        &self.result_options
+    }
+
     pub fn procedure(&self) -> String {
         self.procedure.clone()
     }
@@ -85,6 +224,8 @@ pub struct SubscribeRequest {
     topic: String,
     options: HashMap<String, Value>,
     callback: EventFn,
+    ordered: bool,
+    filter: Option<EventFilterFn>,
 }
 
 impl SubscribeRequest {
@@ -97,7 +238,32 @@ impl SubscribeRequest {
         Self {
             topic: topic.into(),
             options: Default::default(),
-            callback: EventFn(Arc::new(move |inv| Box::pin(callback(inv)))),
+            callback: EventFn(Arc::new(move |event| {
+                let handled = callback(event);
+                Box::pin(async move {
+                    handled.await;
+                    Ok(())
+                })
+            })),
+            ordered: false,
+            filter: None,
+        }
+    }
+
+    /// Like `new`, but for handlers that can fail. Errors they return are
+    /// passed to the client's error handler, see `ClientBuilder::on_error`.
+    pub fn fallible<S, F, Fut>(topic: S, callback: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        Self {
+            topic: topic.into(),
+            options: Default::default(),
+            callback: EventFn(Arc::new(move |event| Box::pin(callback(event)))),
+            ordered: false,
+            filter: None,
         }
     }
 
@@ -111,6 +277,45 @@ impl SubscribeRequest {
         self
     }
 
+    /// Subscribes to every topic starting with the given topic.
+    pub fn match_prefix(self) -> Self {
+        self.with_option("match", "prefix")
+    }
+
+    /// Treats empty URI components of the topic as wildcards, e.g.
+    /// `com..update` matches `com.user.update`.
+    pub fn match_wildcard(self) -> Self {
+        self.with_option("match", "wildcard")
+    }
+
+    /// Delivers events one at a time in the order they arrive instead of
+    /// running a handler per event concurrently. A slow handler then delays
+    /// all later events of this subscription, so only use it when order matters.
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    /// Only hands events to the handler for which `filter` returns true, the
+    /// rest are dropped before a handler is spawned for them. The filter
+    /// runs on the session's reader task for every event of the subscription,
+    /// so it must be cheap and must not block.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Event) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(EventFilterFn(Arc::new(filter)));
+        self
+    }
+
+    pub fn event_filter(&self) -> Option<EventFilterFn> {
+        self.filter.clone()
+    }
+
     pub fn options(&self) -> &HashMap<String, Value> {
         &self.options
     }
@@ -124,5 +329,66 @@ impl SubscribeRequest {
     }
 }
 
+/// Subscriptions kept apart from any session, to subscribe them again on a
+/// new one after a manual rejoin. Take one from `Session::registry` and hand
+/// it to `Session::restore`; the router assigns fresh subscription ids, the
+/// handlers stay the same. Only the specs serialize, after loading them the
+/// handlers are supplied again through `add`.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionRegistry {
+    // the filter is only known for entries taken from a session
+    entries: Vec<(SubscriptionSpec, EventFn, Option<EventFilterFn>)>,
+}
+
+impl SubscriptionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<F, Fut>(&mut self, spec: SubscriptionSpec, callback: F)
+    where
+        F: Fn(Event) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handler = EventFn(Arc::new(move |event| {
+            let handled = callback(event);
+            Box::pin(async move {
+                handled.await;
+                Ok(())
+            })
+        }));
+        self.entries.push((spec, handler, None));
+    }
+
+    pub(crate) fn insert(&mut self, spec: SubscriptionSpec, callback: EventFn, filter: Option<EventFilterFn>) {
+        self.entries.push((spec, callback, filter));
+    }
+
+    pub fn specs(&self) -> Vec<SubscriptionSpec> {
+        self.entries.iter().map(|(spec, ..)| spec.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn requests(&self) -> Vec<SubscribeRequest> {
+        self.entries
+            .iter()
+            .map(|(spec, callback, filter)| SubscribeRequest {
+                topic: spec.topic.clone(),
+                options: spec.options.clone(),
+                callback: callback.clone(),
+                ordered: spec.ordered,
+                filter: filter.clone(),
+            })
+            .collect()
+    }
+}
+
 // Re-export
 pub use crate::common::types::*;
@@ -1,64 +1,187 @@
 use crate::async_::peer::Peer;
-use crate::common::types::{Error, TRANSPORT_WEB_SOCKET, TransportType};
+use crate::common::types::{Error, KeepAlive, TRANSPORT_WEB_SOCKET, TransportType};
 use async_trait::async_trait;
 use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
+use std::fmt::Debug;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
 use tungstenite::{Bytes, Message, Utf8Bytes};
 
-#[derive(Debug, Clone)]
-pub struct WebSocketPeer {
-    reader: Arc<Mutex<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
-    writer: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>,
+/// A WebSocket connection, by default over TCP with optional TLS. Any other
+/// stream works too, see `WebSocketJoiner::join_over`.
+#[derive(Debug)]
+pub struct WebSocketPeer<S = MaybeTlsStream<TcpStream>> {
+    reader: Arc<Mutex<SplitStream<WebSocketStream<S>>>>,
+    writer: Arc<Mutex<SplitSink<WebSocketStream<S>, Message>>>,
     binary: bool,
+    missed_pongs: Arc<AtomicU32>,
+    closed: Arc<Notify>,
+}
+
+// not derived, that would require the stream to be Clone as well
+impl<S> Clone for WebSocketPeer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+            binary: self.binary,
+            missed_pongs: self.missed_pongs.clone(),
+            closed: self.closed.clone(),
+        }
+    }
 }
 
 #[async_trait]
-impl Peer for WebSocketPeer {
+impl<S> Peer for WebSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+{
     fn kind(&self) -> TransportType {
         TRANSPORT_WEB_SOCKET
     }
 
     async fn read(&self) -> Result<Vec<u8>, Error> {
-        let mut reader = self.reader.clone().lock_owned().await;
-        let out = reader.next().await.unwrap().unwrap();
-        Ok(out.into_data().to_vec())
+        let mut reader = self.reader.lock().await;
+        loop {
+            let next = tokio::select! {
+                next = reader.next() => next,
+                _ = self.closed.notified() => return Err(Error::new("keepalive timed out")),
+            };
+
+            match next {
+                Some(Ok(Message::Text(text))) if !self.binary => return Ok(text.as_bytes().to_vec()),
+                Some(Ok(Message::Binary(data))) if self.binary => return Ok(data.to_vec()),
+                Some(Ok(Message::Text(_))) => return Err(Error::new("received a text frame on a binary session")),
+                Some(Ok(Message::Binary(_))) => return Err(Error::new("received a binary frame on a text session")),
+                Some(Ok(Message::Ping(data))) => {
+                    self.writer
+                        .lock()
+                        .await
+                        .send(Message::Pong(data))
+                        .await
+                        .map_err(|e| Error::new(format!("write error: {e}")))?;
+                }
+                Some(Ok(Message::Close(_))) => return Err(Error::new("connection closed")),
+                Some(Ok(Message::Pong(_))) => self.missed_pongs.store(0, Ordering::SeqCst),
+                Some(Ok(Message::Frame(_))) => {}
+                Some(Err(e)) => return Err(Error::new(format!("read error: {e}"))),
+                None => return Err(Error::new("connection closed")),
+            }
+        }
     }
 
     async fn write(&self, data: Vec<u8>) -> Result<(), Error> {
-        let mut writer = self.writer.clone().lock_owned().await;
-        if self.binary {
-            _ = writer
-                .send(Message::Binary(Bytes::copy_from_slice(&data)))
-                .await
-                .map_err(|e| Error::new(format!("write error: {e}")))?;
-            Ok(())
+        // build the frame before taking the lock so concurrent writers only
+        // wait on each other for the send itself, which keeps them in order
+        let message = if self.binary {
+            Message::Binary(Bytes::from(data))
         } else {
             let as_string = String::from_utf8(data).map_err(|e| Error::new(format!("Not valid UTF-8: {e}")))?;
-            _ = writer
-                .send(Message::Text(Utf8Bytes::from(as_string)))
-                .await
-                .map_err(|e| Error::new(format!("write error: {e}")))?;
-            Ok(())
-        }
+            Message::Text(Utf8Bytes::from(as_string))
+        };
+
+        self.writer
+            .lock()
+            .await
+            .send(message)
+            .await
+            .map_err(|e| Error::new(format!("write error: {e}")))
+    }
+
+    async fn close(&self) {
+        _ = self.writer.lock().await.close().await;
+        self.closed.notify_one();
     }
 }
 
 #[allow(clippy::new_ret_no_self)]
-impl WebSocketPeer {
+impl<S> WebSocketPeer<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Debug + 'static,
+{
     pub fn new(
-        reader: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-        writer: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+        reader: SplitStream<WebSocketStream<S>>,
+        writer: SplitSink<WebSocketStream<S>, Message>,
         binary: bool,
+        keepalive: Option<KeepAlive>,
     ) -> Box<dyn Peer> {
-        Box::new(WebSocketPeer {
+        let peer = WebSocketPeer {
             reader: Arc::new(Mutex::new(reader)),
             writer: Arc::new(Mutex::new(writer)),
             binary,
-        })
+            missed_pongs: Default::default(),
+            closed: Default::default(),
+        };
+
+        if let Some(keepalive) = keepalive {
+            tokio::spawn(peer.clone().keepalive(keepalive));
+        }
+
+        Box::new(peer)
+    }
+
+    async fn keepalive(self, keepalive: KeepAlive) {
+        let mut interval = tokio::time::interval(keepalive.interval);
+        // the first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            if self.missed_pongs.fetch_add(1, Ordering::SeqCst) >= keepalive.max_missed_pongs {
+                _ = self.writer.lock().await.close().await;
+                self.closed.notify_one();
+                return;
+            }
+
+            if self
+                .writer
+                .lock()
+                .await
+                .send(Message::Ping(Bytes::new()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tungstenite::protocol::Role;
+
+    async fn connected() -> (Box<dyn Peer>, WebSocketStream<DuplexStream>) {
+        let (client, router) = tokio::io::duplex(1024);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let router = WebSocketStream::from_raw_socket(router, Role::Server, None).await;
+
+        let (writer, reader) = client.split();
+        (WebSocketPeer::new(reader, writer, true, None), router)
+    }
+
+    #[tokio::test]
+    async fn read_fails_once_the_socket_is_gone() {
+        let (peer, router) = connected().await;
+        drop(router);
+
+        assert!(peer.read().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn read_ends_cleanly_on_a_close_frame() {
+        let (peer, mut router) = connected().await;
+        router.close(None).await.unwrap();
+
+        let error = peer.read().await.unwrap_err();
+        assert_eq!(error.message, "connection closed");
     }
 }
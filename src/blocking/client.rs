@@ -0,0 +1,46 @@
+use crate::async_::client::Client as AsyncClient;
+use crate::blocking::session::Session;
+use crate::common::types::Error;
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+
+/// Joins through an async `Client` configured as usual, e.g. with
+/// `async_::client::Client::builder()`, and hands out blocking sessions.
+pub struct Client {
+    client: AsyncClient,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    /// Wraps `client` together with a new multi-thread runtime. The runtime
+    /// keeps reading from the connection and running handlers while the
+    /// calling thread is not inside one of the session's methods.
+    pub fn new(client: AsyncClient) -> Result<Self, Error> {
+        let runtime = Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::new(format!("failed to start runtime: {e}")))?;
+
+        Ok(Self::with_runtime(client, runtime))
+    }
+
+    /// Like `new`, but runs the session on `runtime`. A current-thread runtime
+    /// only makes progress while a method blocks on it, so events and
+    /// invocations are handled late or not at all in between.
+    pub fn with_runtime(client: AsyncClient, runtime: Runtime) -> Self {
+        Self {
+            client,
+            runtime: Arc::new(runtime),
+        }
+    }
+
+    pub fn connect(self, uri: &str, realm: &str) -> Result<Session, Error> {
+        let session = self.runtime.block_on(self.client.connect(uri, realm))?;
+        Ok(Session::new(session, self.runtime))
+    }
+}
+
+/// Joins anonymously using CBOR, see `async_::client::Client::default`.
+pub fn connect_anonymous(uri: &str, realm: &str) -> Result<Session, Error> {
+    Client::new(AsyncClient::default())?.connect(uri, realm)
+}
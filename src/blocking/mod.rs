@@ -0,0 +1,8 @@
+//! The async client driven from synchronous code: every session runs on a
+//! tokio runtime owned by the wrapper and each method blocks the calling
+//! thread until the async one completes. Requests, responses and handlers are
+//! the async ones, handlers run on the runtime.
+pub mod client;
+pub mod session;
+
+pub use crate::async_::types::*;
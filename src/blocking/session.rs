@@ -0,0 +1,74 @@
+use crate::async_::session::Session as AsyncSession;
+use crate::async_::types::{RegisterRequest, SubscribeRequest};
+use crate::common::types::{
+    CallRequest, CallResponse, Error, GoodbyeInfo, PublishRequest, PublishResponse, RegisterResponse, SessionDetails,
+    SubscribeResponse,
+};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// An async `Session` whose methods block until the router answered. Clones
+/// share the session and its runtime, which shuts down with the last clone
+/// and closes the connection along with it.
+///
+/// Handlers run on the runtime, so they must not call the blocking methods,
+/// nor hold the last clone of the session; use `inner` from handlers instead.
+#[derive(Debug, Clone)]
+pub struct Session {
+    session: AsyncSession,
+    runtime: Arc<Runtime>,
+}
+
+impl Session {
+    pub fn new(session: AsyncSession, runtime: Arc<Runtime>) -> Self {
+        Self { session, runtime }
+    }
+
+    /// The wrapped async session.
+    pub fn inner(&self) -> &AsyncSession {
+        &self.session
+    }
+
+    pub fn details(&self) -> SessionDetails {
+        self.session.details()
+    }
+
+    pub fn call(&self, request: CallRequest) -> Result<CallResponse, Error> {
+        self.runtime.block_on(self.session.call(request))
+    }
+
+    /// Calls the procedure and returns its first positional result as `T`,
+    /// see the async `Session::call_typed`.
+    pub fn call_typed<T: DeserializeOwned>(&self, request: CallRequest) -> Result<T, Error> {
+        self.runtime.block_on(self.session.call_typed(request))
+    }
+
+    pub fn publish(&self, request: PublishRequest) -> Result<Option<PublishResponse>, Error> {
+        self.runtime.block_on(self.session.publish(request))
+    }
+
+    pub fn register(&self, request: RegisterRequest) -> Result<RegisterResponse, Error> {
+        self.runtime.block_on(self.session.register(request))
+    }
+
+    pub fn subscribe(&self, request: SubscribeRequest) -> Result<SubscribeResponse, Error> {
+        self.runtime.block_on(self.session.subscribe(request))
+    }
+
+    pub fn leave(&self) -> Result<(), Error> {
+        self.runtime.block_on(self.session.leave())
+    }
+
+    /// Leaves once the running handlers are done or `timeout` passed, see
+    /// the async `Session::shutdown`.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.runtime.block_on(self.session.shutdown(timeout))
+    }
+
+    /// Blocks until the router ends the session and returns the reason it gave.
+    pub fn wait_disconnect(&self) -> GoodbyeInfo {
+        self.runtime.block_on(self.session.wait_disconnect())
+    }
+}
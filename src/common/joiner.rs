@@ -0,0 +1,240 @@
+use crate::common::types::{Error, SerializerSpec, SessionDetails, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use tungstenite::error::{ProtocolError, SubProtocolError};
+use url::{Host, Url};
+use wampproto::messages::abort::{Abort, MESSAGE_TYPE_ABORT};
+use wampproto::messages::hello::Hello;
+use wampproto::messages::message::Message;
+use wampproto::messages::welcome::{MESSAGE_TYPE_WELCOME, Welcome};
+use wampproto::serializers::serializer::Serializer;
+
+/// Why a join attempt failed, which decides whether it is worth retrying.
+pub(crate) enum JoinError {
+    /// The connection failed or dropped before the router answered.
+    Transient(Error),
+    /// The router turned the join down, or answered with something unusable.
+    Rejected(Error),
+}
+
+impl From<JoinError> for Error {
+    fn from(error: JoinError) -> Self {
+        match error {
+            JoinError::Transient(e) | JoinError::Rejected(e) => e,
+        }
+    }
+}
+
+/// The host and port to connect to for `uri`. IPv6 literals come back without
+/// their brackets, the way address resolution and TLS expect them. Rawsocket
+/// schemes have no default port, so their URIs have to name one.
+pub(crate) fn host_and_port(uri: &Url) -> Result<(String, u16), Error> {
+    let host = match uri.host() {
+        Some(Host::Domain(domain)) => domain.to_string(),
+        Some(Host::Ipv4(addr)) => addr.to_string(),
+        Some(Host::Ipv6(addr)) => addr.to_string(),
+        None => return Err(Error::new(format!("missing host in uri {uri}"))),
+    };
+    let port = uri
+        .port_or_known_default()
+        .ok_or_else(|| Error::new(format!("missing port in uri {uri}")))?;
+
+    Ok((host, port))
+}
+
+/// Replaces the roles announced in the HELLO the protocol joiner produced,
+/// keeping realm and authentication as they are.
+pub(crate) fn with_roles(
+    serializer: &dyn Serializer,
+    hello: Vec<u8>,
+    roles: &HashMap<String, Value>,
+) -> Result<Vec<u8>, Error> {
+    let msg = serializer
+        .deserialize(hello)
+        .map_err(|e| Error::new(format!("failed to parse hello: {e}")))?;
+    let hello = msg
+        .as_any()
+        .downcast_ref::<Hello>()
+        .ok_or_else(|| Error::new("failed to parse hello: not a HELLO message"))?;
+
+    let hello = Hello {
+        realm: hello.realm.clone(),
+        authid: hello.authid.clone(),
+        auth_extra: hello.auth_extra.clone(),
+        roles: roles.clone(),
+        auth_methods: hello.auth_methods.clone(),
+    };
+    serializer
+        .serialize(&hello)
+        .map_err(|e| Error::new(format!("failed to send hello: {e}")))
+}
+
+/// Looks at a message received during the join handshake before it is handed
+/// to the protocol joiner. An ABORT is turned into an error carrying the
+/// router's reason and details (which hold the human-readable message), a
+/// WELCOME yields its details. Returns `Ok(None)` for any other message.
+pub(crate) fn inspect_join_reply(msg: &dyn Message) -> Result<Option<HashMap<String, Value>>, Error> {
+    match msg.message_type() {
+        MESSAGE_TYPE_ABORT => {
+            let abort = msg.as_any().downcast_ref::<Abort>().unwrap();
+            Err(Error::new(format!(
+                "join aborted by router: {} (details: {:?})",
+                abort.reason, abort.details
+            )))
+        }
+        MESSAGE_TYPE_WELCOME => {
+            let welcome = msg.as_any().downcast_ref::<Welcome>().unwrap();
+            Ok(Some(welcome.details.clone()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Adds the authentication details and the dealer and broker features
+/// announced in WELCOME to the session details.
+pub(crate) fn with_welcome_details(details: SessionDetails, welcome: Option<HashMap<String, Value>>) -> SessionDetails {
+    let mut welcome = welcome.unwrap_or_default();
+
+    let authmethod = match welcome.remove("authmethod") {
+        Some(Value::Str(authmethod)) => Some(authmethod),
+        _ => None,
+    };
+    let authprovider = match welcome.remove("authprovider") {
+        Some(Value::Str(authprovider)) => Some(authprovider),
+        _ => None,
+    };
+    let authextra = match welcome.remove("authextra") {
+        Some(Value::Dict(authextra)) => authextra,
+        _ => Default::default(),
+    };
+
+    let mut features = HashSet::new();
+    if let Some(Value::Dict(roles)) = welcome.remove("roles") {
+        for role in ["dealer", "broker"] {
+            if let Some(Value::Dict(role)) = roles.get(role)
+                && let Some(Value::Dict(announced)) = role.get("features")
+            {
+                let enabled = announced.iter().filter(|(_, v)| matches!(v, Value::Bool(true)));
+                features.extend(enabled.map(|(feature, _)| feature.clone()));
+            }
+        }
+    }
+
+    details
+        .with_auth_details(authmethod, authprovider, authextra)
+        .with_features(features)
+}
+
+/// The error for a router that answered the upgrade with a subprotocol other
+/// than the requested ones, or with none at all.
+pub(crate) fn subprotocol_mismatch(requested: &[String], received: Option<&str>) -> Error {
+    Error::new(format!(
+        "subprotocol mismatch: requested {}, received {}",
+        requested.join(", "),
+        received.unwrap_or("none")
+    ))
+}
+
+/// Turns tungstenite's own subprotocol check during the handshake into the
+/// same error `select_serializer` returns. tungstenite doesn't keep the
+/// value the router sent, so only a missing one can be named.
+pub(crate) fn subprotocol_error(requested: &[String], error: &SubProtocolError) -> Error {
+    match error {
+        SubProtocolError::NoSubProtocol => subprotocol_mismatch(requested, None),
+        _ => subprotocol_mismatch(requested, Some("one that was not requested")),
+    }
+}
+
+/// Sorts a failed WebSocket upgrade by whether another attempt may succeed.
+/// Only I/O errors, timeouts and connections dropped during the handshake are
+/// transient. A bad URI, a certificate that fails verification, an HTTP error
+/// response or a subprotocol the router doesn't speak fail again the same way.
+pub(crate) fn upgrade_error(error: tungstenite::Error, subprotocols: &[String]) -> JoinError {
+    match error {
+        tungstenite::Error::Io(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+            JoinError::Transient(Error::new("connect timed out"))
+        }
+        e @ (tungstenite::Error::Io(_)
+        | tungstenite::Error::ConnectionClosed
+        | tungstenite::Error::AlreadyClosed
+        | tungstenite::Error::Protocol(
+            ProtocolError::HandshakeIncomplete | ProtocolError::ResetWithoutClosingHandshake,
+        )) => JoinError::Transient(Error::new(format!("failed to connect: {e}"))),
+        tungstenite::Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(e)) => {
+            JoinError::Rejected(subprotocol_error(subprotocols, &e))
+        }
+        e => JoinError::Rejected(Error::new(format!("failed to connect: {e}"))),
+    }
+}
+
+/// Picks the serializer for the subprotocol the router selected from the ones
+/// that were offered. Anything else is a mismatch, since the router would
+/// talk a serializer we can't read.
+pub(crate) fn select_serializer(
+    offered: &[Box<dyn SerializerSpec>],
+    selected: Option<&str>,
+) -> Result<Box<dyn SerializerSpec>, Error> {
+    offered
+        .iter()
+        .find(|spec| selected == Some(spec.subprotocol().as_str()))
+        .cloned()
+        .ok_or_else(|| {
+            let requested: Vec<String> = offered.iter().map(|spec| spec.subprotocol()).collect();
+            subprotocol_mismatch(&requested, selected)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tungstenite::error::UrlError;
+
+    fn is_transient(error: tungstenite::Error) -> bool {
+        matches!(upgrade_error(error, &[]), JoinError::Transient(_))
+    }
+
+    #[test]
+    fn upgrade_error_retries_only_connection_failures() {
+        assert!(is_transient(tungstenite::Error::Io(io::Error::from(
+            ErrorKind::ConnectionReset
+        ))));
+        assert!(is_transient(tungstenite::Error::Io(io::Error::from(
+            ErrorKind::TimedOut
+        ))));
+        assert!(is_transient(tungstenite::Error::Protocol(
+            ProtocolError::ResetWithoutClosingHandshake
+        )));
+
+        assert!(!is_transient(tungstenite::Error::Url(UrlError::UnsupportedUrlScheme)));
+        assert!(!is_transient(tungstenite::Error::Protocol(
+            ProtocolError::SecWebSocketSubProtocolError(SubProtocolError::NoSubProtocol)
+        )));
+    }
+
+    fn parse(uri: &str) -> Result<(String, u16), Error> {
+        host_and_port(&Url::parse(uri).unwrap())
+    }
+
+    #[test]
+    fn host_and_port_unbrackets_ipv6() {
+        assert_eq!(parse("rs://[::1]:8080/").unwrap(), ("::1".to_string(), 8080));
+    }
+
+    #[test]
+    fn host_and_port_requires_a_rawsocket_port() {
+        let error = parse("rs://[::1]/").unwrap_err();
+        assert!(error.to_string().contains("missing port"), "{error}");
+    }
+
+    #[test]
+    fn host_and_port_falls_back_to_the_websocket_default() {
+        assert_eq!(parse("ws://[::1]/").unwrap(), ("::1".to_string(), 80));
+    }
+
+    #[test]
+    fn host_and_port_keeps_plain_hosts() {
+        assert_eq!(parse("rs://localhost:8080").unwrap(), ("localhost".to_string(), 8080));
+        assert_eq!(parse("tcp://127.0.0.1:9000").unwrap(), ("127.0.0.1".to_string(), 9000));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::common::types::{CallResponse, Error, Value};
+use std::collections::HashMap;
+
+pub(crate) const SESSION_COUNT: &str = "wamp.session.count";
+pub(crate) const SESSION_LIST: &str = "wamp.session.list";
+pub(crate) const REGISTRATION_LIST: &str = "wamp.registration.list";
+pub(crate) const REGISTRATION_LOOKUP: &str = "wamp.registration.lookup";
+pub(crate) const REGISTRATION_MATCH: &str = "wamp.registration.match";
+pub(crate) const SUBSCRIPTION_LIST: &str = "wamp.subscription.list";
+pub(crate) const SUBSCRIPTION_LOOKUP: &str = "wamp.subscription.lookup";
+pub(crate) const SUBSCRIPTION_MATCH: &str = "wamp.subscription.match";
+
+/// IDs returned by `wamp.registration.list`, grouped by match policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegistrationLists {
+    pub exact: Vec<i64>,
+    pub prefix: Vec<i64>,
+    pub wildcard: Vec<i64>,
+}
+
+/// `wamp.subscription.list` answers in the same shape as `wamp.registration.list`.
+pub type SubscriptionLists = RegistrationLists;
+
+fn first_arg(response: CallResponse) -> Result<Option<Value>, Error> {
+    let response = response
+        .into_result()
+        .map_err(|e| Error::new(format!("meta call failed: {e}")))?;
+
+    Ok(response.args.and_then(|args| args.into_iter().next()))
+}
+
+fn to_id(value: &Value) -> Result<i64, Error> {
+    match value {
+        Value::Int(id) => Ok(*id),
+        other => Err(Error::new(format!("expected an integer, got {other:?}"))),
+    }
+}
+
+fn to_id_list(value: &Value) -> Result<Vec<i64>, Error> {
+    match value {
+        Value::List(values) => values.iter().map(to_id).collect(),
+        other => Err(Error::new(format!("expected a list of integers, got {other:?}"))),
+    }
+}
+
+pub(crate) fn parse_id(response: CallResponse) -> Result<i64, Error> {
+    match first_arg(response)? {
+        Some(value) => to_id(&value),
+        None => Err(Error::new("meta call returned no result")),
+    }
+}
+
+/// Parses the result of procedures that answer with an ID or null.
+pub(crate) fn parse_optional_id(response: CallResponse) -> Result<Option<i64>, Error> {
+    match first_arg(response)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => to_id(&value).map(Some),
+    }
+}
+
+/// Parses the result of procedures that answer with a list of IDs or null.
+pub(crate) fn parse_id_list(response: CallResponse) -> Result<Vec<i64>, Error> {
+    match first_arg(response)? {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(value) => to_id_list(&value),
+    }
+}
+
+pub(crate) fn parse_lists(response: CallResponse) -> Result<RegistrationLists, Error> {
+    let lists: HashMap<String, Value> = match first_arg(response)? {
+        Some(Value::Dict(lists)) => lists,
+        other => return Err(Error::new(format!("expected a dict of lists, got {other:?}"))),
+    };
+
+    let list = |key: &str| lists.get(key).map_or_else(|| Ok(Vec::new()), to_id_list);
+    Ok(RegistrationLists {
+        exact: list("exact")?,
+        prefix: list("prefix")?,
+        wildcard: list("wildcard")?,
+    })
+}
@@ -1 +1,9 @@
+pub mod joiner;
+pub mod meta;
+pub mod ppt;
+pub mod rawsocket;
+#[cfg(test)]
+pub(crate) mod router;
+pub mod trace;
 pub mod types;
+pub mod uri;
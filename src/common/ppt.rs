@@ -0,0 +1,88 @@
+use crate::common::types::{Error, Value};
+use std::collections::HashMap;
+
+pub(crate) const PPT_SCHEME: &str = "ppt_scheme";
+pub(crate) const PPT_SERIALIZER: &str = "ppt_serializer";
+pub(crate) const PPT_CIPHER: &str = "ppt_cipher";
+pub(crate) const PPT_KEYID: &str = "ppt_keyid";
+
+/// Whether the options mark the payload as passthrough, i.e. as a single
+/// opaque argument the router forwards without looking into it.
+pub(crate) fn is_passthrough(options: &HashMap<String, Value>) -> bool {
+    options.contains_key(PPT_SCHEME)
+}
+
+/// Splits a payload into the `args` and `kwargs` of the outgoing message. A
+/// passthrough payload never carries `kwargs`.
+pub(crate) fn payload(
+    options: &HashMap<String, Value>,
+    args: Vec<Value>,
+    kwargs: HashMap<String, Value>,
+) -> (Option<Vec<Value>>, Option<HashMap<String, Value>>) {
+    if is_passthrough(options) {
+        (Some(args), None)
+    } else {
+        (Some(args), Some(kwargs))
+    }
+}
+
+/// Checks the payload passthrough options on their own. Only the shape is
+/// validated, the session never decodes a passthrough payload:
+///
+/// - `ppt_serializer`, `ppt_cipher` and `ppt_keyid` require `ppt_scheme`.
+/// - `ppt_scheme` is `wamp`, `mqtt` or a custom scheme starting with `x_`.
+/// - `ppt_cipher` and `ppt_keyid` are only valid with a `ppt_serializer`,
+///   since an encrypted payload has to say how it was encoded.
+pub(crate) fn validate_ppt_options(options: &HashMap<String, Value>) -> Result<(), Error> {
+    let scheme = match options.get(PPT_SCHEME) {
+        Some(Value::Str(scheme)) => scheme,
+        Some(other) => {
+            return Err(Error::new(format!(
+                "invalid ppt_scheme: expected a string, got {other:?}"
+            )));
+        }
+        None => {
+            return match [PPT_SERIALIZER, PPT_CIPHER, PPT_KEYID]
+                .into_iter()
+                .find(|key| options.contains_key(*key))
+            {
+                Some(key) => Err(Error::new(format!("{key} requires ppt_scheme"))),
+                None => Ok(()),
+            };
+        }
+    };
+
+    if scheme != "wamp" && scheme != "mqtt" && !scheme.starts_with("x_") {
+        return Err(Error::new(format!(
+            "invalid ppt_scheme {scheme:?}: custom schemes must start with \"x_\""
+        )));
+    }
+
+    if let Some(key) = [PPT_CIPHER, PPT_KEYID]
+        .into_iter()
+        .find(|key| options.contains_key(*key))
+        .filter(|_| !options.contains_key(PPT_SERIALIZER))
+    {
+        return Err(Error::new(format!("{key} requires ppt_serializer")));
+    }
+
+    Ok(())
+}
+
+/// Like `validate_ppt_options`, and additionally checks that a passthrough
+/// payload is exactly one positional argument without keyword arguments.
+pub(crate) fn validate_ppt(
+    options: &HashMap<String, Value>,
+    args: &[Value],
+    kwargs: &HashMap<String, Value>,
+) -> Result<(), Error> {
+    validate_ppt_options(options)?;
+
+    if is_passthrough(options) && (args.len() != 1 || !kwargs.is_empty()) {
+        return Err(Error::new(
+            "invalid passthrough payload: expected a single argument and no keyword arguments",
+        ));
+    }
+
+    Ok(())
+}
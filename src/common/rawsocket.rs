@@ -0,0 +1,65 @@
+use crate::common::types::Error;
+
+/// Checks whether the router rejected our rawsocket handshake. An error reply
+/// has the lower nibble of the second octet set to zero and carries the error
+/// code in the upper nibble.
+pub(crate) fn check_handshake_response(buf: &[u8; 4]) -> Result<(), Error> {
+    if buf[1] & 0x0F != 0 {
+        return Ok(());
+    }
+
+    let reason = match buf[1] >> 4 {
+        1 => "router rejected serializer",
+        2 => "max message length unacceptable",
+        3 => "use of reserved bits (unsupported feature)",
+        4 => "maximum connection count reached",
+        _ => "unknown error",
+    };
+
+    Err(Error::new(format!("rawsocket handshake failed: {reason}")))
+}
+
+/// The largest message the router accepts, as announced in the upper nibble
+/// of the second octet of its handshake reply: `2^(9 + n)` bytes.
+pub(crate) fn max_message_size(buf: &[u8; 4]) -> usize {
+    1 << (9 + (buf[1] >> 4))
+}
+
+/// Refuses a message the router would drop the connection over.
+pub(crate) fn check_message_size(len: usize, max_message_size: usize) -> Result<(), Error> {
+    if len > max_message_size {
+        return Err(Error::new(format!(
+            "message of {len} bytes exceeds the router's limit of {max_message_size} bytes"
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_message_size_reads_the_length_nibble() {
+        assert_eq!(max_message_size(&[0x7F, 0x01, 0, 0]), 512);
+        assert_eq!(max_message_size(&[0x7F, 0x71, 0, 0]), 64 * 1024);
+        assert_eq!(max_message_size(&[0x7F, 0xF2, 0, 0]), 16 * 1024 * 1024);
+    }
+
+    #[test]
+    fn check_handshake_response_decodes_the_error_code() {
+        assert!(check_handshake_response(&[0x7F, 0xF1, 0, 0]).is_ok());
+
+        let error = check_handshake_response(&[0x7F, 0x10, 0, 0]).unwrap_err();
+        assert!(error.message.contains("router rejected serializer"), "{error}");
+        let error = check_handshake_response(&[0x7F, 0x40, 0, 0]).unwrap_err();
+        assert!(error.message.contains("maximum connection count reached"), "{error}");
+    }
+
+    #[test]
+    fn check_message_size_refuses_oversized_messages() {
+        assert!(check_message_size(512, 512).is_ok());
+        assert!(check_message_size(513, 512).is_err());
+    }
+}
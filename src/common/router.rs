@@ -0,0 +1,325 @@
+//! A minimal single-realm router for tests. It speaks WAMP over JSON and
+//! answers REGISTER, SUBSCRIBE, CALL and PUBLISH from any number of sessions,
+//! so session behavior can be checked end to end over memory peers. The sync
+//! and async flavors only pump frames between their peers and a `Realm`.
+
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+
+const GOODBYE: u64 = 6;
+const ERROR: u64 = 8;
+const PUBLISH: u64 = 16;
+const PUBLISHED: u64 = 17;
+const SUBSCRIBE: u64 = 32;
+const SUBSCRIBED: u64 = 33;
+const UNSUBSCRIBE: u64 = 34;
+const UNSUBSCRIBED: u64 = 35;
+const EVENT: u64 = 36;
+const CALL: u64 = 48;
+const CANCEL: u64 = 49;
+const RESULT: u64 = 50;
+const REGISTER: u64 = 64;
+const REGISTERED: u64 = 65;
+const UNREGISTER: u64 = 66;
+const UNREGISTERED: u64 = 67;
+const INVOCATION: u64 = 68;
+const INTERRUPT: u64 = 69;
+const YIELD: u64 = 70;
+
+struct Registration {
+    id: i64,
+    session: i64,
+    procedure: String,
+    policy: String,
+    invoke: String,
+    disclose_caller: bool,
+}
+
+struct Subscription {
+    id: i64,
+    session: i64,
+    topic: String,
+    policy: String,
+}
+
+#[derive(Clone)]
+struct PendingInvocation {
+    caller: i64,
+    call_id: i64,
+    callee: i64,
+}
+
+/// The routing state shared by all sessions of the stub. Every call to
+/// `receive` returns the frames to deliver and to which session.
+#[derive(Default)]
+pub(crate) struct Realm {
+    sessions: i64,
+    next_id: i64,
+    registrations: Vec<Registration>,
+    subscriptions: Vec<Subscription>,
+    invocations: HashMap<i64, PendingInvocation>,
+    // calls per procedure so far, to rotate through roundrobin callees
+    rotations: HashMap<String, usize>,
+    // every message received, in order, with the session that sent it
+    received: Vec<(i64, Value)>,
+}
+
+impl Realm {
+    /// Attaches a new session and returns its id.
+    pub(crate) fn join(&mut self) -> i64 {
+        self.sessions += 1;
+        self.sessions
+    }
+
+    /// Drops everything the session registered or subscribed.
+    pub(crate) fn leave(&mut self, session: i64) {
+        self.registrations
+            .retain(|registration| registration.session != session);
+        self.subscriptions
+            .retain(|subscription| subscription.session != session);
+    }
+
+    /// Messages received so far, with the id of the session that sent them.
+    pub(crate) fn received(&self) -> Vec<(i64, Value)> {
+        self.received.clone()
+    }
+
+    pub(crate) fn receive(&mut self, session: i64, payload: &[u8]) -> Vec<(i64, Vec<u8>)> {
+        let Ok(Value::Array(msg)) = serde_json::from_slice::<Value>(payload) else {
+            return Vec::new();
+        };
+        self.received.push((session, Value::Array(msg.clone())));
+
+        let mut out = Vec::new();
+        let mut send = |to: i64, msg: Value| out.push((to, serde_json::to_vec(&msg).unwrap()));
+        let int = |index: usize| msg.get(index).and_then(Value::as_i64).unwrap_or_default();
+        let string = |index: usize| msg.get(index).and_then(Value::as_str).unwrap_or_default().to_string();
+        let options = msg.get(2).and_then(Value::as_object).cloned().unwrap_or_default();
+
+        match msg.first().and_then(Value::as_u64).unwrap_or_default() {
+            REGISTER => {
+                let procedure = string(3);
+                let policy = option(&options, "match").unwrap_or("exact").to_string();
+                let invoke = option(&options, "invoke").unwrap_or("single").to_string();
+                let taken = self
+                    .registrations
+                    .iter()
+                    .find(|registration| registration.procedure == procedure && registration.policy == policy);
+                if let Some(taken) = taken
+                    && (taken.invoke == "single" || taken.invoke != invoke)
+                {
+                    send(
+                        session,
+                        json!([ERROR, REGISTER, int(1), {}, "wamp.error.procedure_already_exists"]),
+                    );
+                    return out;
+                }
+
+                let id = self.next_id();
+                self.registrations.push(Registration {
+                    id,
+                    session,
+                    procedure,
+                    policy,
+                    invoke,
+                    disclose_caller: options.get("disclose_caller") == Some(&Value::Bool(true)),
+                });
+                send(session, json!([REGISTERED, int(1), id]));
+            }
+            UNREGISTER => {
+                let id = int(2);
+                self.registrations.retain(|registration| registration.id != id);
+                send(session, json!([UNREGISTERED, int(1)]));
+            }
+            SUBSCRIBE => {
+                let id = self.next_id();
+                self.subscriptions.push(Subscription {
+                    id,
+                    session,
+                    topic: string(3),
+                    policy: option(&options, "match").unwrap_or("exact").to_string(),
+                });
+                send(session, json!([SUBSCRIBED, int(1), id]));
+            }
+            UNSUBSCRIBE => {
+                let id = int(2);
+                self.subscriptions.retain(|subscription| subscription.id != id);
+                send(session, json!([UNSUBSCRIBED, int(1)]));
+            }
+            CALL => {
+                let procedure = string(3);
+                let candidates: Vec<&Registration> = self
+                    .registrations
+                    .iter()
+                    .filter(|registration| matches(&registration.policy, &registration.procedure, &procedure))
+                    .collect();
+                let Some(first) = candidates.first() else {
+                    send(
+                        session,
+                        json!([ERROR, CALL, int(1), {}, "wamp.error.no_such_procedure"]),
+                    );
+                    return out;
+                };
+
+                let callee = match first.invoke.as_str() {
+                    "roundrobin" => {
+                        let rotation = self.rotations.entry(procedure.clone()).or_default();
+                        *rotation += 1;
+                        candidates[(*rotation - 1) % candidates.len()]
+                    }
+                    "last" => candidates[candidates.len() - 1],
+                    _ => *first,
+                };
+
+                let mut details = Map::new();
+                if callee.disclose_caller || options.get("disclose_me") == Some(&Value::Bool(true)) {
+                    details.insert("caller".to_string(), json!(session));
+                }
+                if options.get("receive_progress") == Some(&Value::Bool(true)) {
+                    details.insert("receive_progress".to_string(), json!(true));
+                }
+                if callee.policy != "exact" {
+                    details.insert("procedure".to_string(), json!(procedure));
+                }
+
+                let (registration, to) = (callee.id, callee.session);
+                let id = self.next_id();
+                self.invocations.insert(
+                    id,
+                    PendingInvocation {
+                        caller: session,
+                        call_id: int(1),
+                        callee: to,
+                    },
+                );
+                send(to, payload_of(json!([INVOCATION, id, registration, details]), &msg, 4));
+            }
+            CANCEL => {
+                let call_id = int(1);
+                let pending = self
+                    .invocations
+                    .iter()
+                    .find(|(_, pending)| pending.caller == session && pending.call_id == call_id);
+                if let Some((id, pending)) = pending {
+                    send(pending.callee, json!([INTERRUPT, id, options]));
+                }
+            }
+            YIELD => {
+                let progress = options.get("progress") == Some(&Value::Bool(true));
+                let Some(pending) = self.take_invocation(int(1), progress) else {
+                    return out;
+                };
+                let details = match progress {
+                    true => json!({"progress": true}),
+                    false => json!({}),
+                };
+                send(
+                    pending.caller,
+                    payload_of(json!([RESULT, pending.call_id, details]), &msg, 3),
+                );
+            }
+            ERROR if int(1) == INVOCATION as i64 => {
+                let Some(pending) = self.take_invocation(int(2), false) else {
+                    return out;
+                };
+                let error = json!([
+                    ERROR,
+                    CALL,
+                    pending.call_id,
+                    msg.get(3).cloned().unwrap_or(json!({})),
+                    string(4)
+                ]);
+                send(pending.caller, payload_of(error, &msg, 5));
+            }
+            PUBLISH => {
+                let topic = string(3);
+                let publication = self.next_id();
+                let exclude_me = options.get("exclude_me") != Some(&Value::Bool(false));
+                for subscription in &self.subscriptions {
+                    if (exclude_me && subscription.session == session)
+                        || !matches(&subscription.policy, &subscription.topic, &topic)
+                    {
+                        continue;
+                    }
+
+                    let mut details = Map::new();
+                    if subscription.policy != "exact" {
+                        details.insert("topic".to_string(), json!(topic));
+                    }
+                    if options.get("disclose_me") == Some(&Value::Bool(true)) {
+                        details.insert("publisher".to_string(), json!(session));
+                    }
+                    let event = json!([EVENT, subscription.id, publication, details]);
+                    send(subscription.session, payload_of(event, &msg, 4));
+                }
+                if options.get("acknowledge") == Some(&Value::Bool(true)) {
+                    send(session, json!([PUBLISHED, int(1), publication]));
+                }
+            }
+            GOODBYE => {
+                self.leave(session);
+                send(session, json!([GOODBYE, {}, "wamp.close.goodbye_and_out"]));
+            }
+            _ => {}
+        }
+        out
+    }
+
+    /// Removes the registration or subscription `id` on the router's own
+    /// account and returns the UNREGISTERED or UNSUBSCRIBED announcing it.
+    pub(crate) fn revoke(&mut self, id: i64, reason: &str) -> Option<(i64, Vec<u8>)> {
+        let (session, msg) = if let Some(index) = self.registrations.iter().position(|r| r.id == id) {
+            let registration = self.registrations.remove(index);
+            let details = json!({"registration": id, "reason": reason});
+            (registration.session, json!([UNREGISTERED, 0, details]))
+        } else {
+            let index = self.subscriptions.iter().position(|s| s.id == id)?;
+            let subscription = self.subscriptions.remove(index);
+            let details = json!({"subscription": id, "reason": reason});
+            (subscription.session, json!([UNSUBSCRIBED, 0, details]))
+        };
+        Some((session, serde_json::to_vec(&msg).unwrap()))
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Looks up the invocation `id`, which stays pending for progressive results.
+    fn take_invocation(&mut self, id: i64, progress: bool) -> Option<PendingInvocation> {
+        if progress {
+            return self.invocations.get(&id).cloned();
+        }
+        self.invocations.remove(&id)
+    }
+}
+
+fn option<'a>(options: &'a Map<String, Value>, key: &str) -> Option<&'a str> {
+    options.get(key).and_then(Value::as_str)
+}
+
+/// Appends the args and kwargs found at `from` in `msg`, if any, to `base`.
+fn payload_of(mut base: Value, msg: &[Value], from: usize) -> Value {
+    if let Value::Array(items) = &mut base {
+        items.extend(msg.iter().skip(from).cloned());
+    }
+    base
+}
+
+/// Whether `uri` is matched by `pattern` under the given match policy.
+fn matches(policy: &str, pattern: &str, uri: &str) -> bool {
+    match policy {
+        "prefix" => uri.starts_with(pattern),
+        "wildcard" => {
+            let pattern: Vec<&str> = pattern.split('.').collect();
+            let uri: Vec<&str> = uri.split('.').collect();
+            pattern.len() == uri.len()
+                && pattern
+                    .iter()
+                    .zip(&uri)
+                    .all(|(pattern, part)| pattern.is_empty() || pattern == part)
+        }
+        _ => pattern == uri,
+    }
+}
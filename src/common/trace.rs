@@ -0,0 +1,128 @@
+use crate::common::types::{CallResponse, Error};
+
+/// How a traced call or invocation ended, recorded as the `outcome` field.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Outcome {
+    Ok,
+    Error,
+    Timeout,
+}
+
+impl Outcome {
+    pub(crate) fn of(result: &Result<CallResponse, Error>) -> Self {
+        match result {
+            Ok(response) if response.error.is_none() => Outcome::Ok,
+            _ => Outcome::Error,
+        }
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Error => "error",
+            Outcome::Timeout => "timeout",
+        }
+    }
+}
+
+/// A `tracing` span around an outgoing call or an incoming invocation that
+/// records the outcome and the duration in milliseconds once finished.
+/// Without the `tracing` feature it is empty and every method a no-op.
+#[cfg(feature = "tracing")]
+pub(crate) struct Span {
+    span: tracing::Span,
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "tracing")]
+impl Span {
+    pub(crate) fn call(procedure: &str) -> Self {
+        Self {
+            span: tracing::debug_span!(
+                "call",
+                procedure,
+                request_id = tracing::field::Empty,
+                outcome = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            ),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn invocation(registration_id: i64, request_id: i64) -> Self {
+        Self {
+            span: tracing::debug_span!(
+                "invocation",
+                registration_id,
+                request_id,
+                outcome = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            ),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn record_request_id(&self, request_id: i64) {
+        self.span.record("request_id", request_id);
+    }
+
+    /// Runs `f` inside the span, so events it logs are attributed to it.
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+
+    /// Enters the span whenever `future` is polled.
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> tracing::instrument::Instrumented<F> {
+        tracing::Instrument::instrument(future, self.span.clone())
+    }
+
+    pub(crate) fn finish(self, outcome: Outcome) {
+        self.span.record("outcome", outcome.as_str());
+        self.span
+            .record("duration_ms", self.start.elapsed().as_secs_f64() * 1000.0);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    #[inline(always)]
+    pub(crate) fn call(_procedure: &str) -> Self {
+        Self
+    }
+
+    #[inline(always)]
+    pub(crate) fn invocation(_registration_id: i64, _request_id: i64) -> Self {
+        Self
+    }
+
+    #[inline(always)]
+    pub(crate) fn record_request_id(&self, _request_id: i64) {}
+
+    #[inline(always)]
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+
+    #[inline(always)]
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> F {
+        future
+    }
+
+    #[inline(always)]
+    pub(crate) fn finish(self, _outcome: Outcome) {}
+}
+
+/// Logs that a call got `receive_progress` only because it has an
+/// `on_progress` callback.
+#[cfg(feature = "tracing")]
+pub(crate) fn receive_progress_implied(procedure: &str) {
+    tracing::debug!(procedure, "on_progress is set, enabling receive_progress");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub(crate) fn receive_progress_implied(_procedure: &str) {}
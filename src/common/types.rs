@@ -1,7 +1,18 @@
-use std::collections::HashMap;
+use crate::common::ppt::{PPT_CIPHER, PPT_KEYID, PPT_SCHEME, PPT_SERIALIZER, payload, validate_ppt};
+use crate::common::trace;
+use native_tls::{Certificate, TlsConnector};
+use serde::de::DeserializeOwned;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use wampproto::messages::call::Call;
+use wampproto::messages::message::Message;
 use wampproto::messages::publish::Publish;
 pub use wampproto::messages::types::Value;
 use wampproto::serializers::cbor::CBORSerializer;
@@ -37,6 +48,11 @@ pub struct SessionDetails {
     realm: String,
     authid: String,
     auth_role: String,
+    authmethod: Option<String>,
+    authprovider: Option<String>,
+    authextra: HashMap<String, Value>,
+    // None unless the details came with a WELCOME, e.g. for in-memory sessions
+    features: Option<HashSet<String>>,
 }
 
 impl SessionDetails {
@@ -46,9 +62,25 @@ impl SessionDetails {
             realm,
             authid,
             auth_role,
+            authmethod: None,
+            authprovider: None,
+            authextra: Default::default(),
+            features: Default::default(),
         }
     }
 
+    pub fn with_auth_details(
+        mut self,
+        authmethod: Option<String>,
+        authprovider: Option<String>,
+        authextra: HashMap<String, Value>,
+    ) -> Self {
+        self.authmethod = authmethod;
+        self.authprovider = authprovider;
+        self.authextra = authextra;
+        self
+    }
+
     pub fn id(&self) -> i64 {
         self.id
     }
@@ -64,6 +96,39 @@ impl SessionDetails {
     pub fn auth_role(&self) -> String {
         self.auth_role.clone()
     }
+
+    pub fn authmethod(&self) -> Option<String> {
+        self.authmethod.clone()
+    }
+
+    pub fn authprovider(&self) -> Option<String> {
+        self.authprovider.clone()
+    }
+
+    pub fn authextra(&self) -> &HashMap<String, Value> {
+        &self.authextra
+    }
+
+    pub(crate) fn with_features(mut self, features: HashSet<String>) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Whether the router advertised `feature`, e.g. `call_canceling`, for its
+    /// dealer or broker role in WELCOME. Features a router leaves out are
+    /// treated as unsupported. Details that were not built from a WELCOME,
+    /// like those passed to `Session::new`, don't restrict anything.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.as_ref().is_none_or(|features| features.contains(feature))
+    }
+
+    pub(crate) fn require(&self, feature: &str) -> Result<(), Error> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::new(format!("the router does not support {feature}")))
+        }
+    }
 }
 
 pub trait _SerializerSpec: Debug + Sync + Send {
@@ -155,12 +220,33 @@ impl _SerializerSpec for MsgPackSerializerSpec {
     }
 }
 
+type ProgressCallbackType = dyn Fn(CallResponse) + Send + Sync;
+
+/// Callback invoked for every intermediate result of a progressive call.
+#[derive(Clone)]
+pub struct ProgressFn(pub Arc<ProgressCallbackType>);
+
+impl fmt::Debug for ProgressFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<ProgressFn>")
+    }
+}
+
+impl ProgressFn {
+    pub fn invoke(&self, response: CallResponse) {
+        self.0(response)
+    }
+}
+
 #[derive(Debug)]
 pub struct _OutgoingRequest {
     uri: String,
     options: HashMap<String, Value>,
     args: Vec<Value>,
     kwargs: HashMap<String, Value>,
+    timeout: Option<Duration>,
+    progress: Option<ProgressFn>,
+    payload_serializer: Option<PayloadSerializer>,
 }
 
 impl _OutgoingRequest {
@@ -170,6 +256,9 @@ impl _OutgoingRequest {
             args: Default::default(),
             kwargs: Default::default(),
             options: Default::default(),
+            timeout: None,
+            progress: None,
+            payload_serializer: None,
         }
     }
 
@@ -193,6 +282,16 @@ impl _OutgoingRequest {
         self
     }
 
+    /// Sends `data` as the only argument, replacing any args and kwargs. The
+    /// bytes are moved into the outgoing message rather than cloned with the
+    /// args. MessagePack and CBOR carry them as a native binary value, JSON
+    /// as base64.
+    pub fn binary(mut self, data: Vec<u8>) -> Self {
+        self.args = vec![Value::Bytes(data)];
+        self.kwargs.clear();
+        self
+    }
+
     pub fn option<T: Into<Value>>(mut self, key: &str, value: T) -> Self {
         self.options.insert(key.to_string(), value.into());
         self
@@ -202,40 +301,625 @@ impl _OutgoingRequest {
         self.options = options;
         self
     }
+
+    /// Asks the router to disclose this session's identity, to the callee for
+    /// calls and to subscribers for publishes. Callees read it through
+    /// `Invocation::caller` and friends, subscribers through `Event::publisher`.
+    pub fn disclose_me(mut self, disclose_me: bool) -> Self {
+        self.options.insert("disclose_me".to_string(), Value::Bool(disclose_me));
+        self
+    }
+
+    /// Marks the payload as passthrough (PPT): the args hold a single opaque,
+    /// possibly encrypted, value which the router and the session forward
+    /// untouched. `scheme` is `wamp`, `mqtt` or a custom one starting with
+    /// `x_`. Sending fails if the payload isn't exactly one argument without
+    /// kwargs, or if the other `ppt_*` options are set without this one.
+    pub fn ppt_scheme(mut self, scheme: &str) -> Self {
+        self.options.insert(PPT_SCHEME.to_string(), scheme.into());
+        self
+    }
+
+    /// The serializer the passthrough payload was encoded with, e.g. `cbor`.
+    pub fn ppt_serializer(mut self, serializer: &str) -> Self {
+        self.options.insert(PPT_SERIALIZER.to_string(), serializer.into());
+        self
+    }
+
+    /// The cipher the passthrough payload was encrypted with. Requires
+    /// `ppt_serializer`.
+    pub fn ppt_cipher(mut self, cipher: &str) -> Self {
+        self.options.insert(PPT_CIPHER.to_string(), cipher.into());
+        self
+    }
+
+    /// Identifies the key the passthrough payload was encrypted with.
+    /// Requires `ppt_serializer`.
+    pub fn ppt_keyid(mut self, keyid: &str) -> Self {
+        self.options.insert(PPT_KEYID.to_string(), keyid.into());
+        self
+    }
+
+    pub(crate) fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    pub(crate) fn validate_ppt(&self) -> Result<(), Error> {
+        validate_ppt(&self.options, &self.args, &self.kwargs)
+    }
 }
 
+const RECEIVE_PROGRESS: &str = "receive_progress";
+
 pub type CallRequest = _OutgoingRequest;
 pub type PublishRequest = _OutgoingRequest;
 
 impl CallRequest {
-    pub(crate) fn to_call(&self, request_id: i64) -> Call {
+    /// Sets how long to wait for the call to return. The duration is also sent
+    /// to the router as the `timeout` call option so it can abort server-side.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Sets the `receive_progress` call option, without it the dealer only ever
+    /// sends the final result. Intermediate results are handed to the
+    /// `on_progress` callback, or dropped if there is none. The timeout of the
+    /// call covers all of them, progress doesn't extend it.
+    pub fn receive_progress(mut self) -> Self {
+        self.options.insert(RECEIVE_PROGRESS.to_string(), Value::Bool(true));
+        self
+    }
+
+    /// Asks the callee for progressive results and invokes `callback` for each
+    /// intermediate result. The call itself resolves with the final result.
+    /// The callback runs while the caller waits for that, e.g. inside
+    /// `Session::call`, and not on the session's reader. Implies
+    /// `receive_progress`.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(CallResponse) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressFn(Arc::new(callback)));
+        self
+    }
+
+    /// Encodes the args and kwargs of just this call or publish with
+    /// `serializer` and sends them as a passthrough payload, e.g. MessagePack
+    /// for a large call on a JSON session. The single argument then holds the
+    /// encoded `{"args": [...], "kwargs": {...}}`, `ppt_serializer` names the
+    /// encoding and `ppt_scheme` defaults to `wamp`. The callee or subscribers
+    /// have to decode it, the router forwards it untouched.
+    pub fn payload_serializer(mut self, serializer: PayloadSerializer) -> Self {
+        self.payload_serializer = Some(serializer);
+        self
+    }
+
+    /// Applies `payload_serializer`, if set, before the call or publish is
+    /// validated and sent.
+    pub(crate) fn encode_payload(mut self) -> Result<Self, Error> {
+        let Some(serializer) = self.payload_serializer.take() else {
+            return Ok(self);
+        };
+
+        let encoded = serializer.encode(&self.args, &self.kwargs)?;
+        self.args = vec![Value::Bytes(encoded)];
+        self.kwargs.clear();
+        self.options
+            .entry(PPT_SCHEME.to_string())
+            .or_insert_with(|| "wamp".into());
+        self.options
+            .insert(PPT_SERIALIZER.to_string(), serializer.name().into());
+        Ok(self)
+    }
+
+    pub(crate) fn progress(&self) -> Option<ProgressFn> {
+        self.progress.clone()
+    }
+
+    /// Whether the call asks for progressive results, either explicitly or by
+    /// having a progress callback.
+    pub(crate) fn wants_progress(&self) -> bool {
+        self.progress.is_some() || matches!(self.options.get(RECEIVE_PROGRESS), Some(Value::Bool(true)))
+    }
+
+    /// Turns the request into the CALL, moving everything it carries into the
+    /// message rather than copying it.
+    pub(crate) fn into_call(self, request_id: i64) -> Call {
+        let wants_progress = self.wants_progress();
+        let mut options = self.options;
+        if let Some(timeout) = self.timeout {
+            options.insert("timeout".to_string(), Value::Int(timeout.as_millis() as i64));
+        }
+        if wants_progress {
+            let requested = options.insert(RECEIVE_PROGRESS.to_string(), Value::Bool(true));
+            if !matches!(requested, Some(Value::Bool(true))) {
+                trace::receive_progress_implied(&self.uri);
+            }
+        }
+
+        let (args, kwargs) = payload(&options, self.args, self.kwargs);
         Call {
             request_id,
-            options: self.options.clone(),
-            procedure: self.uri.clone(),
-            args: Some(self.args.clone()),
-            kwargs: Some(self.kwargs.clone()),
+            options,
+            procedure: self.uri,
+            args,
+            kwargs,
         }
     }
 }
 
 impl PublishRequest {
-    pub(crate) fn to_publish(&self, request_id: i64) -> Publish {
+    /// Excludes the sessions with the given ids from receiving the event.
+    pub fn exclude(mut self, session_ids: Vec<i64>) -> Self {
+        self.options.insert("exclude".to_string(), session_id_list(session_ids));
+        self
+    }
+
+    /// Only delivers the event to the sessions with the given ids.
+    pub fn eligible(mut self, session_ids: Vec<i64>) -> Self {
+        self.options
+            .insert("eligible".to_string(), session_id_list(session_ids));
+        self
+    }
+
+    /// Whether the publisher receives its own event when subscribed to the
+    /// topic. Routers exclude the publisher by default.
+    pub fn exclude_me(mut self, exclude_me: bool) -> Self {
+        self.options.insert("exclude_me".to_string(), Value::Bool(exclude_me));
+        self
+    }
+
+    /// Asks the broker to acknowledge the publication. `publish` then waits
+    /// for the acknowledgement, giving up after the request's timeout or
+    /// `DEFAULT_ACKNOWLEDGE_TIMEOUT` if none is set.
+    pub fn acknowledge(mut self) -> Self {
+        self.options.insert("acknowledge".to_string(), Value::Bool(true));
+        self
+    }
+
+    /// Turns the request into the PUBLISH, moving everything it carries into
+    /// the message rather than copying it.
+    pub(crate) fn into_publish(self, request_id: i64) -> Publish {
+        // routers only take a boolean, so normalize whatever truthy value the
+        // option was set to instead of silently publishing unacknowledged.
+        let mut options = self.options;
+        if options
+            .remove("acknowledge")
+            .is_some_and(|acknowledge| is_truthy(&acknowledge))
+        {
+            options.insert("acknowledge".to_string(), Value::Bool(true));
+        }
+
+        let (args, kwargs) = payload(&options, self.args, self.kwargs);
         Publish {
             request_id,
-            options: self.options.clone(),
-            topic: self.uri.clone(),
-            args: Some(self.args.clone()),
-            kwargs: Some(self.kwargs.clone()),
+            options,
+            topic: self.uri,
+            args,
+            kwargs,
         }
     }
 }
 
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(value) => *value,
+        Value::Int(value) => *value != 0,
+        Value::Str(value) => matches!(value.to_ascii_lowercase().as_str(), "true" | "1" | "yes"),
+        _ => false,
+    }
+}
+
+fn session_id_list(session_ids: Vec<i64>) -> Value {
+    Value::List(session_ids.into_iter().map(Value::Int).collect())
+}
+
+fn as_i64(value: Option<&Value>) -> Option<i64> {
+    match value {
+        Some(Value::Int(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+fn as_str(value: Option<&Value>) -> Option<&str> {
+    match value {
+        Some(Value::Str(value)) => Some(value),
+        _ => None,
+    }
+}
+
+fn as_bool(value: Option<&Value>) -> Option<bool> {
+    match value {
+        Some(Value::Bool(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+fn as_f64(value: Option<&Value>) -> Option<f64> {
+    match value {
+        Some(Value::Float(value)) => Some(*value),
+        Some(Value::Int(value)) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn args_as<T: DeserializeOwned>(args: &[Value]) -> Result<T, Error> {
+    let json = serde_json::Value::Array(args.iter().map(to_json).collect());
+    serde_json::from_value(json).map_err(|e| Error::new(format!("failed to deserialize args: {e}")))
+}
+
+fn kwargs_as<T: DeserializeOwned>(kwargs: &HashMap<String, Value>) -> Result<T, Error> {
+    let json = serde_json::Value::Object(kwargs.iter().map(|(k, v)| (k.clone(), to_json(v))).collect());
+    serde_json::from_value(json).map_err(|e| Error::new(format!("failed to deserialize kwargs: {e}")))
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(i) => serde_json::Value::from(*i),
+        Value::Float(f) => serde_json::Value::from(*f),
+        Value::Str(s) => serde_json::Value::String(s.clone()),
+        Value::Bytes(bytes) => serde_json::Value::from(bytes.clone()),
+        Value::List(list) => serde_json::Value::Array(list.iter().map(to_json).collect()),
+        Value::Dict(dict) => serde_json::Value::Object(dict.iter().map(|(k, v)| (k.clone(), to_json(v))).collect()),
+    }
+}
+
+/// Encoding of a single passthrough payload, independent of the serializer
+/// the session negotiated. See `CallRequest::payload_serializer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadSerializer {
+    Json,
+    Cbor,
+    MsgPack,
+}
+
+impl PayloadSerializer {
+    /// The `ppt_serializer` value announcing this encoding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PayloadSerializer::Json => "json",
+            PayloadSerializer::Cbor => "cbor",
+            PayloadSerializer::MsgPack => "msgpack",
+        }
+    }
+
+    fn encode(&self, args: &[Value], kwargs: &HashMap<String, Value>) -> Result<Vec<u8>, Error> {
+        let payload = EncodedPayload { args, kwargs };
+        let encoded = match self {
+            PayloadSerializer::Json => serde_json::to_vec(&payload).map_err(|e| e.to_string()),
+            PayloadSerializer::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(&payload, &mut encoded)
+                    .map(|_| encoded)
+                    .map_err(|e| e.to_string())
+            }
+            PayloadSerializer::MsgPack => rmp_serde::to_vec_named(&payload).map_err(|e| e.to_string()),
+        };
+        encoded.map_err(|e| Error::new(format!("failed to encode payload: {e}")))
+    }
+}
+
+struct EncodedPayload<'a> {
+    args: &'a [Value],
+    kwargs: &'a HashMap<String, Value>,
+}
+
+impl Serialize for EncodedPayload<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let args: Vec<EncodedValue> = self.args.iter().map(EncodedValue).collect();
+        let kwargs: HashMap<&String, EncodedValue> = self.kwargs.iter().map(|(k, v)| (k, EncodedValue(v))).collect();
+
+        let mut state = serializer.serialize_struct("Payload", 2)?;
+        state.serialize_field("args", &args)?;
+        state.serialize_field("kwargs", &kwargs)?;
+        state.end()
+    }
+}
+
+// bytes stay bytes, unlike through to_json, so CBOR and MessagePack carry them natively
+struct EncodedValue<'a>(&'a Value);
+
+impl Serialize for EncodedValue<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Str(s) => serializer.serialize_str(s),
+            Value::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Value::List(list) => serializer.collect_seq(list.iter().map(EncodedValue)),
+            Value::Dict(dict) => serializer.collect_map(dict.iter().map(|(k, v)| (k, EncodedValue(v)))),
+        }
+    }
+}
+
+/// Converts any serializable value into a `Value`, for payloads `Value` has
+/// no `From` impl for. `arg`, `kwarg` and `Yield::arg` take the types `Value`
+/// converts from directly, like strings, booleans and integers; wrap anything
+/// else in `to_value`:
+///
+/// | Rust type                                     | `Value`       |
+/// |-----------------------------------------------|---------------|
+/// | `bool`                                        | `Bool`        |
+/// | `i8` to `i64`, `u8` to `u32`, small `u64`     | `Int`         |
+/// | `u64` above `i64::MAX`, `f32`, `f64`          | `Float`       |
+/// | `char`, `String`, `&str`                      | `Str`         |
+/// | `Option<T>`                                   | `Null` or `T` |
+/// | `Vec<T>`, slices, tuples, sets                | `List`        |
+/// | `HashMap<String, T>`, structs                 | `Dict`        |
+/// | `()`                                          | `Null`        |
+///
+/// Byte vectors become a `List` of integers, build `Value::Bytes` directly to
+/// send them as binary.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, Error> {
+    let json = serde_json::to_value(value).map_err(|e| Error::new(format!("failed to serialize value: {e}")))?;
+    Ok(from_json(json))
+}
+
+fn from_json(json: serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(s) => Value::Str(s),
+        serde_json::Value::Array(list) => Value::List(list.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(dict) => Value::Dict(dict.into_iter().map(|(k, v)| (k, from_json(v))).collect()),
+    }
+}
+
+/// What a subscription is for, without its handler: the part a
+/// `SubscriptionRegistry` can persist, e.g. to restore subscriptions after a
+/// restart. Serializes as `{"topic": ..., "options": {...}, "ordered": ...}`.
+#[derive(Debug, Clone)]
+pub struct SubscriptionSpec {
+    pub topic: String,
+    pub options: HashMap<String, Value>,
+    pub ordered: bool,
+}
+
+impl SubscriptionSpec {
+    pub fn new<S: Into<String>>(topic: S) -> Self {
+        Self {
+            topic: topic.into(),
+            options: Default::default(),
+            ordered: false,
+        }
+    }
+}
+
+impl Serialize for SubscriptionSpec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let options: serde_json::Map<String, serde_json::Value> =
+            self.options.iter().map(|(k, v)| (k.clone(), to_json(v))).collect();
+
+        let mut spec = serializer.serialize_struct("SubscriptionSpec", 3)?;
+        spec.serialize_field("topic", &self.topic)?;
+        spec.serialize_field("options", &options)?;
+        spec.serialize_field("ordered", &self.ordered)?;
+        spec.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscriptionSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        let serde_json::Value::Object(mut fields) = serde_json::Value::deserialize(deserializer)? else {
+            return Err(D::Error::custom("subscription spec must be an object"));
+        };
+
+        let topic = match fields.remove("topic") {
+            Some(serde_json::Value::String(topic)) => topic,
+            _ => return Err(D::Error::custom("subscription spec needs a string topic")),
+        };
+        let options = match fields.remove("options") {
+            Some(serde_json::Value::Object(options)) => options.into_iter().map(|(k, v)| (k, from_json(v))).collect(),
+            None | Some(serde_json::Value::Null) => Default::default(),
+            Some(_) => return Err(D::Error::custom("subscription spec options must be an object")),
+        };
+        let ordered = match fields.remove("ordered") {
+            Some(serde_json::Value::Bool(ordered)) => ordered,
+            None => false,
+            Some(_) => return Err(D::Error::custom("subscription spec ordered must be a boolean")),
+        };
+
+        Ok(Self {
+            topic,
+            options,
+            ordered,
+        })
+    }
+}
+
+/// How the dealer should treat the callee when a call gets canceled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelMode {
+    Skip,
+    Kill,
+    KillNoWait,
+}
+
+impl CancelMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelMode::Skip => "skip",
+            CancelMode::Kill => "kill",
+            CancelMode::KillNoWait => "killnowait",
+        }
+    }
+}
+
+/// How the dealer picks a callee when several sessions share a registration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvokePolicy {
+    Single,
+    RoundRobin,
+    Random,
+    First,
+    Last,
+}
+
+impl InvokePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvokePolicy::Single => "single",
+            InvokePolicy::RoundRobin => "roundrobin",
+            InvokePolicy::Random => "random",
+            InvokePolicy::First => "first",
+            InvokePolicy::Last => "last",
+        }
+    }
+}
+
+/// Fired when the dealer interrupts an in-flight invocation.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptToken(Arc<AtomicBool>);
+
+impl InterruptToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
 #[derive(Debug)]
 pub struct _IncomingRequest {
     pub args: Vec<Value>,
     pub kwargs: HashMap<String, Value>,
     pub details: HashMap<String, Value>,
+    pub(crate) interrupt: InterruptToken,
+}
+
+impl _IncomingRequest {
+    /// Whether the dealer has interrupted this invocation. Long-running handlers
+    /// should poll this and return early once it is set.
+    pub fn is_cancelled(&self) -> bool {
+        self.interrupt.is_cancelled()
+    }
+
+    pub fn interrupt_token(&self) -> InterruptToken {
+        self.interrupt.clone()
+    }
+
+    /// Session id of the caller, if the procedure was registered with
+    /// `disclose_caller` or the caller asked to be disclosed.
+    pub fn caller(&self) -> Option<i64> {
+        self.detail_int("caller")
+    }
+
+    pub fn caller_authid(&self) -> Option<String> {
+        self.detail_str("caller_authid")
+    }
+
+    pub fn caller_authrole(&self) -> Option<String> {
+        self.detail_str("caller_authrole")
+    }
+
+    /// The concrete procedure that was called. Routers only include it for
+    /// pattern-based (prefix or wildcard) registrations.
+    pub fn procedure(&self) -> Option<String> {
+        self.detail_str("procedure")
+    }
+
+    /// Whether the caller asked for progressive results. Without it the
+    /// dealer rejects progressive yields, so a handler should only stream
+    /// progress when this is true.
+    pub fn wants_progress(&self) -> bool {
+        matches!(self.details.get(RECEIVE_PROGRESS), Some(Value::Bool(true)))
+    }
+
+    /// Session id of the publisher, if it was disclosed by the router.
+    pub fn publisher(&self) -> Option<i64> {
+        self.detail_int("publisher")
+    }
+
+    pub fn publisher_authid(&self) -> Option<String> {
+        self.detail_str("publisher_authid")
+    }
+
+    /// The concrete topic an event was published to. Routers only include it
+    /// for pattern-based (prefix or wildcard) subscriptions.
+    pub fn topic(&self) -> Option<String> {
+        self.detail_str("topic")
+    }
+
+    /// Deserializes the positional arguments into `T`, e.g. a tuple or a `Vec`.
+    pub fn args_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        args_as(&self.args)
+    }
+
+    /// Deserializes the keyword arguments into `T`, e.g. a struct.
+    pub fn kwargs_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        kwargs_as(&self.kwargs)
+    }
+
+    /// The positional argument at `index` if it is an integer.
+    pub fn arg_i64(&self, index: usize) -> Option<i64> {
+        as_i64(self.args.get(index))
+    }
+
+    /// The positional argument at `index` if it is a string.
+    pub fn arg_str(&self, index: usize) -> Option<&str> {
+        as_str(self.args.get(index))
+    }
+
+    /// The positional argument at `index` if it is a boolean.
+    pub fn arg_bool(&self, index: usize) -> Option<bool> {
+        as_bool(self.args.get(index))
+    }
+
+    /// The positional argument at `index` if it is a number, integers included.
+    pub fn arg_f64(&self, index: usize) -> Option<f64> {
+        as_f64(self.args.get(index))
+    }
+
+    /// The keyword argument `key` if it is an integer.
+    pub fn kwarg_i64(&self, key: &str) -> Option<i64> {
+        as_i64(self.kwargs.get(key))
+    }
+
+    /// The keyword argument `key` if it is a string.
+    pub fn kwarg_str(&self, key: &str) -> Option<&str> {
+        as_str(self.kwargs.get(key))
+    }
+
+    /// The keyword argument `key` if it is a boolean.
+    pub fn kwarg_bool(&self, key: &str) -> Option<bool> {
+        as_bool(self.kwargs.get(key))
+    }
+
+    /// The keyword argument `key` if it is a number, integers included.
+    pub fn kwarg_f64(&self, key: &str) -> Option<f64> {
+        as_f64(self.kwargs.get(key))
+    }
+
+    fn detail_str(&self, key: &str) -> Option<String> {
+        match self.details.get(key) {
+            Some(Value::Str(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn detail_int(&self, key: &str) -> Option<i64> {
+        match self.details.get(key) {
+            Some(Value::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
 }
 
 pub type Invocation = _IncomingRequest;
@@ -321,6 +1005,79 @@ pub struct CallResponse {
     pub error: Option<WampError>,
 }
 
+impl CallResponse {
+    /// Turns an error response into `Err`, so that callers can use `?`.
+    pub fn into_result(mut self) -> Result<CallResponse, WampError> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(self),
+        }
+    }
+
+    /// Deserializes the positional arguments into `T`, e.g. a tuple or a `Vec`.
+    pub fn args_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        args_as(self.args.as_deref().unwrap_or_default())
+    }
+
+    /// Deserializes the keyword arguments into `T`, e.g. a struct.
+    pub fn kwargs_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        kwargs_as(self.kwargs.as_ref().unwrap_or(&HashMap::new()))
+    }
+
+    /// Deserializes the first positional argument into `T`, for the many
+    /// procedures that return a single value.
+    pub fn arg0<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let arg = self
+            .args
+            .as_deref()
+            .and_then(|args| args.first())
+            .ok_or_else(|| Error::new("result has no positional arguments"))?;
+
+        serde_json::from_value(to_json(arg))
+            .map_err(|e| Error::new(format!("failed to deserialize first argument: {e}")))
+    }
+
+    /// The positional argument at `index` if it is an integer.
+    pub fn arg_i64(&self, index: usize) -> Option<i64> {
+        as_i64(self.args.as_deref().and_then(|args| args.get(index)))
+    }
+
+    /// The positional argument at `index` if it is a string.
+    pub fn arg_str(&self, index: usize) -> Option<&str> {
+        as_str(self.args.as_deref().and_then(|args| args.get(index)))
+    }
+
+    /// The positional argument at `index` if it is a boolean.
+    pub fn arg_bool(&self, index: usize) -> Option<bool> {
+        as_bool(self.args.as_deref().and_then(|args| args.get(index)))
+    }
+
+    /// The positional argument at `index` if it is a number, integers included.
+    pub fn arg_f64(&self, index: usize) -> Option<f64> {
+        as_f64(self.args.as_deref().and_then(|args| args.get(index)))
+    }
+
+    /// The keyword argument `key` if it is an integer.
+    pub fn kwarg_i64(&self, key: &str) -> Option<i64> {
+        as_i64(self.kwargs.as_ref().and_then(|kwargs| kwargs.get(key)))
+    }
+
+    /// The keyword argument `key` if it is a string.
+    pub fn kwarg_str(&self, key: &str) -> Option<&str> {
+        as_str(self.kwargs.as_ref().and_then(|kwargs| kwargs.get(key)))
+    }
+
+    /// The keyword argument `key` if it is a boolean.
+    pub fn kwarg_bool(&self, key: &str) -> Option<bool> {
+        as_bool(self.kwargs.as_ref().and_then(|kwargs| kwargs.get(key)))
+    }
+
+    /// The keyword argument `key` if it is a number, integers included.
+    pub fn kwarg_f64(&self, key: &str) -> Option<f64> {
+        as_f64(self.kwargs.as_ref().and_then(|kwargs| kwargs.get(key)))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct WampError {
     pub uri: String,
@@ -328,6 +1085,54 @@ pub struct WampError {
     pub kwargs: Option<HashMap<String, Value>>,
 }
 
+impl WampError {
+    /// The human readable message most routers and callees put in the first
+    /// positional argument, if that is a string.
+    pub fn message(&self) -> Option<String> {
+        as_str(self.args.as_ref().and_then(|args| args.first())).map(str::to_string)
+    }
+
+    /// Deserializes the keyword arguments, which carry the structured details
+    /// of the error, into `T`.
+    pub fn detail<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        kwargs_as(self.kwargs.as_ref().unwrap_or(&HashMap::new()))
+    }
+
+    /// Deserializes just the keyword argument `key`, e.g. an error code.
+    pub fn detail_field<T: DeserializeOwned>(&self, key: &str) -> Result<T, Error> {
+        let value = self
+            .kwargs
+            .as_ref()
+            .and_then(|kwargs| kwargs.get(key))
+            .ok_or_else(|| Error::new(format!("error has no detail {key:?}")))?;
+
+        serde_json::from_value(to_json(value))
+            .map_err(|e| Error::new(format!("failed to deserialize detail {key:?}: {e}")))
+    }
+}
+
+impl fmt::Display for WampError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.uri)?;
+        if let Some(args) = self.args.as_ref().filter(|args| !args.is_empty()) {
+            write!(f, ": {args:?}")?;
+        }
+        if let Some(kwargs) = self.kwargs.as_ref().filter(|kwargs| !kwargs.is_empty()) {
+            write!(f, " {kwargs:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WampError {}
+
+/// Reason and details of the GOODBYE that ended a session.
+#[derive(Debug, Clone, Default)]
+pub struct GoodbyeInfo {
+    pub reason: String,
+    pub details: HashMap<String, Value>,
+}
+
 #[derive(Debug, Default)]
 pub struct PublishResponse {
     pub error: Option<WampError>,
@@ -339,6 +1144,410 @@ pub struct RegisterResponse {
     pub error: Option<WampError>,
 }
 
+/// Default upper bound for establishing a connection, i.e. the TCP connect
+/// plus the TLS and transport handshakes.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time an acknowledged publish waits for the broker's PUBLISHED.
+pub const DEFAULT_ACKNOWLEDGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default number of events an ordered subscription buffers while its handler
+/// is busy. Once the buffer is full the session stops reading from the
+/// connection until the handler catches up.
+pub const DEFAULT_EVENT_BUFFER_SIZE: usize = 1024;
+
+/// Default number of progressive results a call buffers while its progress
+/// callback is busy. Once the buffer is full the session stops reading from
+/// the connection until the callback catches up.
+pub const DEFAULT_CALL_BUFFER_SIZE: usize = 64;
+
+/// How often a joiner retries when the connection fails before the router
+/// welcomed the session, e.g. because it dropped between HELLO and WELCOME.
+/// Rejections by the router, like a failed authentication, are not retried.
+/// The delay before retry `n` is `backoff * 2^(n-1)`.
+#[derive(Debug, Clone, Copy)]
+pub struct JoinRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl JoinRetry {
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        Self { attempts, backoff }
+    }
+
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retry.saturating_sub(1).min(16))
+    }
+}
+
+/// How a client reconnects after its connection dropped. The delay before
+/// attempt `n` is `initial_backoff * 2^(n-1)`, capped at `max_backoff` and
+/// reduced by a random fraction of up to `jitter`.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub max_attempts: Option<u32>,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives up after `max_attempts` failed attempts. Retries forever by default.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Fraction between 0 and 1 by which each delay is randomly shortened, so
+    /// that many clients don't reconnect in lockstep.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+
+        // a freshly keyed hasher is a good enough source of randomness for jitter
+        let random = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+        delay.mul_f64(1.0 - self.jitter * random)
+    }
+}
+
+/// Progress of an automatic reconnect, see `ReconnectPolicy`.
+#[derive(Debug, Clone)]
+pub enum ReconnectEvent {
+    /// The connection dropped and reconnecting starts.
+    Disconnected,
+    /// Attempt number `attempt` is made after waiting `delay`.
+    Attempt { attempt: u32, delay: Duration },
+    /// Attempt number `attempt` failed.
+    Failed { attempt: u32, error: String },
+    /// The session rejoined and its registrations and subscriptions were
+    /// restored. Their ids have changed.
+    Reconnected(SessionDetails),
+    /// `max_attempts` was reached, the session stays disconnected.
+    GaveUp,
+}
+
+type ReconnectCallbackType = dyn Fn(ReconnectEvent) + Send + Sync;
+
+#[derive(Clone)]
+pub struct ReconnectFn(pub Arc<ReconnectCallbackType>);
+
+impl fmt::Debug for ReconnectFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<ReconnectFn>")
+    }
+}
+
+impl ReconnectFn {
+    pub fn invoke(&self, event: ReconnectEvent) {
+        self.0(event)
+    }
+}
+
+type ErrorCallbackType = dyn Fn(Error) + Send + Sync;
+
+/// Receives errors that happen in a session's background work, like a failed
+/// write of an invocation result, which have no caller to return them to.
+#[derive(Clone)]
+pub struct ErrorFn(pub Arc<ErrorCallbackType>);
+
+impl fmt::Debug for ErrorFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<ErrorFn>")
+    }
+}
+
+impl ErrorFn {
+    pub fn invoke(&self, error: Error) {
+        self.0(error)
+    }
+}
+
+type MessageCallbackType = dyn Fn(&dyn Message) + Send + Sync;
+
+/// Sees every message received from the router before the session handles
+/// it, e.g. to log traffic or to pick up message types the session ignores.
+#[derive(Clone)]
+pub struct MessageFn(pub Arc<MessageCallbackType>);
+
+impl fmt::Debug for MessageFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<MessageFn>")
+    }
+}
+
+impl MessageFn {
+    pub fn invoke(&self, msg: &dyn Message) {
+        self.0(msg)
+    }
+}
+
+/// A registration or subscription the router removed on its own, announced by
+/// an UNREGISTERED or UNSUBSCRIBED that answers no request of the session.
+#[derive(Debug, Clone)]
+pub enum Revocation {
+    Registration {
+        registration_id: i64,
+        reason: Option<String>,
+    },
+    Subscription {
+        subscription_id: i64,
+        reason: Option<String>,
+    },
+}
+
+impl Revocation {
+    /// Reads a revocation from the details of the router's message, which name
+    /// the revoked id under `key` and optionally carry a `reason` URI.
+    pub(crate) fn from_details(details: &HashMap<String, Value>, key: &str) -> Option<(i64, Option<String>)> {
+        let id = match details.get(key) {
+            Some(Value::Int(id)) => *id,
+            _ => return None,
+        };
+        let reason = match details.get("reason") {
+            Some(Value::Str(reason)) => Some(reason.clone()),
+            _ => None,
+        };
+
+        Some((id, reason))
+    }
+}
+
+type EventFilterCallbackType = dyn Fn(&Event) -> bool + Send + Sync;
+
+/// Decides whether an event is handed to its subscription's handler, see
+/// `SubscribeRequest::filter`.
+#[derive(Clone)]
+pub struct EventFilterFn(pub Arc<EventFilterCallbackType>);
+
+impl fmt::Debug for EventFilterFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<EventFilterFn>")
+    }
+}
+
+impl EventFilterFn {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.0(event)
+    }
+}
+
+type RevokedCallbackType = dyn Fn(Revocation) + Send + Sync;
+
+/// Told when the router revokes a registration or subscription, after the
+/// session dropped its handler.
+#[derive(Clone)]
+pub struct RevokedFn(pub Arc<RevokedCallbackType>);
+
+impl fmt::Debug for RevokedFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<RevokedFn>")
+    }
+}
+
+impl RevokedFn {
+    pub fn invoke(&self, revocation: Revocation) {
+        self.0(revocation)
+    }
+}
+
+/// WebSocket keepalive settings: a ping is sent every `interval` and the
+/// connection is closed once `max_missed_pongs` pings went unanswered.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    pub interval: Duration,
+    pub max_missed_pongs: u32,
+}
+
+impl KeepAlive {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_missed_pongs: 3,
+        }
+    }
+}
+
+/// An application-level heartbeat: `procedure` is called with `args` every
+/// `interval`, and a call left unanswered for `timeout` closes the connection
+/// as if it had dropped. Any answer counts, an error result still proves the
+/// round trip through the router works.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub procedure: String,
+    pub args: Vec<Value>,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Heartbeat {
+    pub fn new<S: Into<String>>(procedure: S, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            procedure: procedure.into(),
+            args: Default::default(),
+            interval,
+            timeout,
+        }
+    }
+
+    pub fn args(mut self, args: Vec<Value>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub(crate) fn request(&self) -> CallRequest {
+        CallRequest::new(self.procedure.clone())
+            .args(self.args.clone())
+            .with_timeout(self.timeout)
+    }
+}
+
+/// TLS settings for secure transports.
+#[derive(Clone)]
+pub struct TlsConfig {
+    root_certificates: Vec<Certificate>,
+    use_system_roots: bool,
+    accept_invalid_certs: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            root_certificates: Default::default(),
+            use_system_roots: true,
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    pub fn with_root_certificate_pem(self, pem: &[u8]) -> Result<Self, Error> {
+        let certificate = Certificate::from_pem(pem).map_err(|e| Error::new(format!("invalid certificate: {e}")))?;
+        Ok(self.with_root_certificate(certificate))
+    }
+
+    /// Whether to trust the system trust store in addition to the configured
+    /// root certificates. Enabled by default.
+    pub fn with_system_roots(mut self, use_system_roots: bool) -> Self {
+        self.use_system_roots = use_system_roots;
+        self
+    }
+
+    /// Disables certificate verification entirely. Only meant for testing
+    /// against routers with self-signed certificates.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    pub(crate) fn connector(&self) -> Result<TlsConnector, Error> {
+        let mut builder = TlsConnector::builder();
+        builder.disable_built_in_roots(!self.use_system_roots);
+        builder.danger_accept_invalid_certs(self.accept_invalid_certs);
+        for certificate in &self.root_certificates {
+            builder.add_root_certificate(certificate.clone());
+        }
+
+        builder
+            .build()
+            .map_err(|e| Error::new(format!("failed to create tls connector: {e}")))
+    }
+}
+
 pub type TransportType = usize;
 pub const TRANSPORT_WEB_SOCKET: TransportType = 1;
 pub const TRANSPORT_RAW_SOCKET: TransportType = 2;
+pub const TRANSPORT_MEMORY: TransportType = 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(uri: &str, args: Option<Vec<Value>>, kwargs: Option<HashMap<String, Value>>) -> WampError {
+        WampError {
+            uri: uri.to_string(),
+            args,
+            kwargs,
+        }
+    }
+
+    #[test]
+    fn message_is_the_first_string_argument() {
+        let no_callee = error(
+            "wamp.error.no_such_procedure",
+            Some(vec![Value::Str(
+                "no callee registered for procedure 'io.xconn.echo'".to_string(),
+            )]),
+            None,
+        );
+        assert_eq!(
+            no_callee.message().as_deref(),
+            Some("no callee registered for procedure 'io.xconn.echo'")
+        );
+
+        assert_eq!(error("wamp.error.canceled", None, None).message(), None);
+        assert_eq!(error("wamp.error.canceled", Some(vec![]), None).message(), None);
+        assert_eq!(
+            error("wamp.error.invalid_argument", Some(vec![Value::Int(1)]), None).message(),
+            None
+        );
+    }
+
+    #[test]
+    fn detail_deserializes_the_kwargs() {
+        let kwargs = HashMap::from([
+            ("code".to_string(), Value::Int(42)),
+            ("reason".to_string(), Value::Str("quota exceeded".to_string())),
+        ]);
+        let runtime = error("wamp.error.runtime_error", None, Some(kwargs));
+
+        let detail: HashMap<String, serde_json::Value> = runtime.detail().unwrap();
+        assert_eq!(detail["code"], serde_json::json!(42));
+        assert_eq!(runtime.detail_field::<i64>("code").unwrap(), 42);
+        assert_eq!(runtime.detail_field::<String>("reason").unwrap(), "quota exceeded");
+        assert!(runtime.detail_field::<i64>("reason").is_err());
+        assert!(runtime.detail_field::<i64>("missing").is_err());
+    }
+
+    #[test]
+    fn detail_of_an_error_without_kwargs_is_empty() {
+        let not_authorized = error("wamp.error.not_authorized", None, None);
+        let detail: HashMap<String, i64> = not_authorized.detail().unwrap();
+        assert!(detail.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::common::types::{Error, Value};
+use std::collections::HashMap;
+
+/// Checks `uri` against the WAMP URI rules before it goes out, so that an
+/// obviously malformed URI fails locally instead of coming back from the
+/// router as `wamp.error.invalid_uri`.
+///
+/// Loose URIs may use any character but whitespace, `.` and `#` in their
+/// components, strict URIs only `[0-9a-z_]`. The `match` option relaxes the
+/// rules: a prefix may end in a dot and a wildcard may have empty components.
+pub(crate) fn validate_uri(uri: &str, options: &HashMap<String, Value>, strict: bool) -> Result<(), Error> {
+    let policy = match_policy(options);
+
+    if uri.is_empty() && policy != "wildcard" {
+        return Err(Error::new("invalid uri: uri is empty"));
+    }
+
+    let components: Vec<&str> = uri.split('.').collect();
+    let last = components.len() - 1;
+    for (i, component) in components.iter().enumerate() {
+        if component.is_empty() {
+            let allowed = match policy {
+                "wildcard" => true,
+                "prefix" => i == last && i > 0,
+                _ => false,
+            };
+            if !allowed {
+                return Err(Error::new(format!("invalid uri {uri:?}: empty component")));
+            }
+            continue;
+        }
+
+        let valid = if strict {
+            component
+                .chars()
+                .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase() || c == '_')
+        } else {
+            component.chars().all(|c| !c.is_whitespace() && c != '#')
+        };
+        if !valid {
+            return Err(Error::new(format!("invalid uri {uri:?}: bad component {component:?}")));
+        }
+    }
+
+    Ok(())
+}
+
+/// The `match` option of a registration or subscription, `exact` if unset.
+pub(crate) fn match_policy(options: &HashMap<String, Value>) -> &str {
+    match options.get("match") {
+        Some(Value::Str(policy)) => policy.as_str(),
+        _ => "exact",
+    }
+}
@@ -1,5 +1,7 @@
 #[cfg(feature = "async")]
 pub mod async_;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 #[cfg(feature = "sync")]
 pub mod sync;
 
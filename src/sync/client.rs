@@ -1,16 +1,38 @@
-use crate::common::types::{CBORSerializerSpec, Error, JSONSerializerSpec, SerializerSpec};
-use crate::sync::session::Session;
+use crate::common::types::{
+    CBORSerializerSpec, DEFAULT_CONNECT_TIMEOUT, Error, ErrorFn, JSONSerializerSpec, JoinRetry, KeepAlive, MessageFn,
+    MsgPackSerializerSpec, Revocation, RevokedFn, SerializerSpec, SessionDetails, TlsConfig,
+};
+use crate::sync::peer::Peer;
+use crate::sync::session::{Session, SessionOptions};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::sync::joiner::WebSocketJoiner;
+use crate::sync::joiner::{RawSocketJoiner, WebSocketJoiner};
 use wampproto::authenticators::anonymous::AnonymousAuthenticator;
 use wampproto::authenticators::authenticator::ClientAuthenticator;
 use wampproto::authenticators::cryptosign::CryptoSignAuthenticator;
+use wampproto::authenticators::scram::SCRAMAuthenticator;
 use wampproto::authenticators::ticket::TicketAuthenticator;
 use wampproto::authenticators::wampcra::WAMPCRAAuthenticator;
+use wampproto::messages::message::Message;
 
 pub struct Client {
     serializer: Box<dyn SerializerSpec>,
     authenticator: Box<dyn ClientAuthenticator>,
+    connect_timeout: Duration,
+    tls: Option<TlsConfig>,
+    keepalive: Option<KeepAlive>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_concurrent_invocations: Option<usize>,
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: Option<usize>,
+    call_buffer_size: Option<usize>,
+    strict_uris: bool,
+    manual_dispatch: bool,
+    join_retry: Option<JoinRetry>,
 }
 
 impl Client {
@@ -18,33 +40,314 @@ impl Client {
         Self {
             serializer,
             authenticator,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            tls: None,
+            keepalive: None,
+            read_timeout: None,
+            write_timeout: None,
+            max_concurrent_invocations: None,
+            error_handler: None,
+            message_handler: None,
+            revoked_handler: None,
+            event_buffer_size: None,
+            call_buffer_size: None,
+            strict_uris: false,
+            manual_dispatch: false,
+            join_retry: None,
         }
     }
 
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
     pub fn connect(self, uri: &str, realm: &str) -> Result<Session, Error> {
-        let serializer = self.serializer.serializer();
-        let joiner = WebSocketJoiner::new(self.serializer, self.authenticator);
-        match joiner.join(uri, realm) {
-            Ok((peer, details)) => Ok(Session::new(details, peer, serializer)),
-            Err(e) => Err(Error::new(e.to_string())),
+        let (read_timeout, write_timeout) = (self.read_timeout, self.write_timeout);
+        // applied before the session's reader starts waiting on the peer
+        let session =
+            |(peer, details): (Box<dyn Peer>, SessionDetails), serializer, options| -> Result<Session, Error> {
+                peer.set_read_timeout(read_timeout)?;
+                peer.set_write_timeout(write_timeout)?;
+                Ok(Session::with_options(details, peer, serializer, options))
+            };
+        let options = SessionOptions {
+            max_concurrent_invocations: self.max_concurrent_invocations,
+            error_handler: self.error_handler.clone(),
+            message_handler: self.message_handler.clone(),
+            revoked_handler: self.revoked_handler.clone(),
+            event_buffer_size: self.event_buffer_size,
+            call_buffer_size: self.call_buffer_size,
+            subprotocol: Some(self.serializer.subprotocol()),
+            strict_uris: self.strict_uris,
+            manual_dispatch: self.manual_dispatch,
+        };
+
+        if uri.starts_with("ws://") || uri.starts_with("wss://") {
+            let serializer = self.serializer.serializer();
+            let mut joiner =
+                WebSocketJoiner::new(self.serializer, self.authenticator).with_connect_timeout(self.connect_timeout);
+            if let Some(tls) = self.tls {
+                joiner = joiner.with_tls_config(tls);
+            }
+            if let Some(retry) = self.join_retry {
+                joiner = joiner.with_join_retry(retry);
+            }
+            if let Some(keepalive) = self.keepalive {
+                joiner = joiner
+                    .with_keepalive(keepalive.interval)
+                    .with_keepalive_max_missed(keepalive.max_missed_pongs);
+            }
+            match joiner.join(uri, realm) {
+                Ok(joined) => session(joined, serializer, options),
+                Err(e) => Err(Error::new(e.to_string())),
+            }
+        } else if uri.starts_with("rs://")
+            || uri.starts_with("rss://")
+            || uri.starts_with("tcp://")
+            || uri.starts_with("tcps://")
+        {
+            let serializer = self.serializer.serializer();
+            let mut joiner =
+                RawSocketJoiner::new(self.serializer, self.authenticator).with_connect_timeout(self.connect_timeout);
+            if let Some(retry) = self.join_retry {
+                joiner = joiner.with_join_retry(retry);
+            }
+            match joiner.join(uri, realm) {
+                Ok(joined) => session(joined, serializer, options),
+                Err(e) => Err(Error::new(e.to_string())),
+            }
+        } else {
+            Err(Error::new("Invalid URI scheme".to_string()))
         }
     }
 }
 
+/// Anonymous authentication over JSON. The async client defaults to CBOR
+/// instead, this one stays on JSON so existing users keep the serializer they
+/// negotiate today. The authenticated `connect_*` helpers use CBOR on both.
 impl Default for Client {
     fn default() -> Self {
-        Self {
-            serializer: Box::new(JSONSerializerSpec {}),
-            authenticator: Box::new(AnonymousAuthenticator::new("", Default::default())),
+        Self::new(
+            Box::new(JSONSerializerSpec {}),
+            Box::new(AnonymousAuthenticator::new("", Default::default())),
+        )
+    }
+}
+
+/// Fluent configuration for a `Client`. Anything left unset falls back to the
+/// same defaults as `Client::default()`.
+#[derive(Default)]
+pub struct ClientBuilder {
+    serializer: Option<Box<dyn SerializerSpec>>,
+    authenticator: Option<Box<dyn ClientAuthenticator>>,
+    connect_timeout: Option<Duration>,
+    tls: Option<TlsConfig>,
+    keepalive: Option<KeepAlive>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_concurrent_invocations: Option<usize>,
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: Option<usize>,
+    call_buffer_size: Option<usize>,
+    strict_uris: bool,
+    manual_dispatch: bool,
+    join_retry: Option<JoinRetry>,
+}
+
+impl ClientBuilder {
+    pub fn serializer(mut self, serializer: Box<dyn SerializerSpec>) -> Self {
+        self.serializer = Some(serializer);
+        self
+    }
+
+    pub fn authenticator(mut self, authenticator: Box<dyn ClientAuthenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Upper bound for the TCP connect and the transport handshake, defaults
+    /// to `DEFAULT_CONNECT_TIMEOUT`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// TLS settings for `wss://` URIs. The sync client has no secure rawsocket.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// WebSocket keepalive; ignored for rawsocket transports.
+    pub fn keepalive(mut self, keepalive: KeepAlive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Fails a read from the connection with a timeout error once nothing
+    /// arrived for `timeout`. The session reads all the time, so this ends
+    /// the session, and with it a pending `leave`, once the router stays
+    /// silent for that long. Pick it above the longest expected quiet period.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails a write that blocks for longer than `timeout` with a timeout
+    /// error, e.g. when the router stopped reading. Only rawsocket writes
+    /// block; WebSocket writes are queued, a stalled router is detected by
+    /// the keepalive there.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Runs invocation and event handlers on a pool of this many worker
+    /// threads instead of spawning a thread for each, which saves the thread
+    /// setup per call on a busy callee. Further invocations and events are
+    /// queued and picked up in the order they arrived; with more than one
+    /// worker they may still finish in any order, a single worker runs them
+    /// strictly one after another. Ordered subscriptions keep their own thread
+    /// and are not affected. A handler that blocks on a call back into this
+    /// session holds its worker meanwhile, so size the pool for that. A limit
+    /// of 0 is treated as 1.
+    pub fn max_concurrent_invocations(mut self, limit: usize) -> Self {
+        self.max_concurrent_invocations = Some(limit);
+        self
+    }
+
+    /// Receives errors from the session's background work that have no caller
+    /// to be returned to, like failing to send an invocation result. Without a
+    /// handler these are dropped.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Error) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(ErrorFn(Arc::new(callback)));
+        self
+    }
+
+    /// Sees every message the router sends before the session handles it. The
+    /// hook runs on the reader, so it should return quickly.
+    pub fn on_message<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&dyn Message) + Send + Sync + 'static,
+    {
+        self.message_handler = Some(MessageFn(Arc::new(callback)));
+        self
+    }
+
+    /// Told when the router revokes a registration or subscription on its own,
+    /// e.g. because an admin removed it. The session has already dropped the
+    /// handler by then. The hook runs on the reader, so it should return quickly.
+    pub fn on_revoked<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Revocation) + Send + Sync + 'static,
+    {
+        self.revoked_handler = Some(RevokedFn(Arc::new(callback)));
+        self
+    }
+
+    /// How many events an ordered subscription buffers while its handler is
+    /// busy, defaults to `DEFAULT_EVENT_BUFFER_SIZE`. When the buffer is full
+    /// the session stops reading from the connection until the handler caught
+    /// up, which also holds back call results and other events.
+    pub fn event_buffer_size(mut self, size: usize) -> Self {
+        self.event_buffer_size = Some(size);
+        self
+    }
+
+    /// How many progressive results a call buffers until the caller gets to
+    /// them, defaults to `DEFAULT_CALL_BUFFER_SIZE`. Progress callbacks run
+    /// on the caller's side while it waits for the final result, so a slow
+    /// callback only fills its own call's buffer. Once that is full the
+    /// session stops reading from the connection until the callback caught
+    /// up, which also holds back other results and events. A value of 0 is
+    /// treated as 1. Publishes and (un)registrations expect a single answer
+    /// and are never buffered beyond it.
+    pub fn call_buffer_size(mut self, size: usize) -> Self {
+        self.call_buffer_size = Some(size);
+        self
+    }
+
+    /// Checks outgoing URIs against the strict WAMP rules, i.e. only
+    /// `[0-9a-z_]` in components, instead of the loose ones. Either way an
+    /// invalid URI fails locally before anything is sent.
+    pub fn strict_uris(mut self, strict: bool) -> Self {
+        self.strict_uris = strict;
+        self
+    }
+
+    /// Queues invocations and events instead of running their handlers on
+    /// threads of their own. They run one after another on whichever thread
+    /// calls `Session::poll_once` or `Session::run`, which makes handler
+    /// execution deterministic, e.g. in tests. Overrides
+    /// `max_concurrent_invocations`, and ordered subscriptions use the same
+    /// queue instead of a thread of their own.
+    pub fn manual_dispatch(mut self) -> Self {
+        self.manual_dispatch = true;
+        self
+    }
+
+    /// Retries connecting when the connection drops before the router
+    /// welcomed the session. A rejected join is never retried.
+    pub fn join_retry(mut self, retry: JoinRetry) -> Self {
+        self.join_retry = Some(retry);
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let default = Client::default();
+        Client {
+            serializer: self.serializer.unwrap_or(default.serializer),
+            authenticator: self.authenticator.unwrap_or(default.authenticator),
+            connect_timeout: self.connect_timeout.unwrap_or(default.connect_timeout),
+            tls: self.tls,
+            keepalive: self.keepalive,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            max_concurrent_invocations: self.max_concurrent_invocations,
+            error_handler: self.error_handler,
+            message_handler: self.message_handler,
+            revoked_handler: self.revoked_handler,
+            event_buffer_size: self.event_buffer_size,
+            call_buffer_size: self.call_buffer_size,
+            strict_uris: self.strict_uris,
+            manual_dispatch: self.manual_dispatch,
+            join_retry: self.join_retry,
         }
     }
 }
 
+/// Connects with the given serializer and authenticator, e.g. a custom one
+/// not covered by the `connect_*` helpers below.
+pub fn connect_with(
+    uri: &str,
+    realm: &str,
+    serializer: Box<dyn SerializerSpec>,
+    authenticator: Box<dyn ClientAuthenticator>,
+) -> Result<Session, Error> {
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm)
+}
+
+/// Joins anonymously using JSON, see `Client::default`.
 pub fn connect_anonymous(uri: &str, realm: &str) -> Result<Session, Error> {
     let client = Client::default();
     client.connect(uri, realm)
 }
 
+/// Joins anonymously using MessagePack.
+pub fn connect_anonymous_msgpack(uri: &str, realm: &str) -> Result<Session, Error> {
+    let serializer = Box::new(MsgPackSerializerSpec {});
+    let authenticator = Box::new(AnonymousAuthenticator::new("", Default::default()));
+
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm)
+}
+
 pub fn connect_ticket(uri: &str, realm: &str, authid: &str, ticket: &str) -> Result<Session, Error> {
     let serializer = Box::new(CBORSerializerSpec {});
     let authenticator = Box::new(TicketAuthenticator::new(authid, ticket, Default::default()));
@@ -61,6 +364,14 @@ pub fn connect_wampcra(uri: &str, realm: &str, authid: &str, secret: &str) -> Re
     client.connect(uri, realm)
 }
 
+pub fn connect_scram(uri: &str, realm: &str, authid: &str, secret: &str) -> Result<Session, Error> {
+    let serializer = Box::new(CBORSerializerSpec {});
+    let authenticator = Box::new(SCRAMAuthenticator::new(authid, secret, Default::default()));
+
+    let client = Client::new(serializer, authenticator);
+    client.connect(uri, realm)
+}
+
 pub fn connect_cryptosign(uri: &str, realm: &str, authid: &str, private_key_hex: &str) -> Result<Session, Error> {
     let serializer = Box::new(CBORSerializerSpec {});
     let authenticator = CryptoSignAuthenticator::try_new(authid, private_key_hex, Default::default())
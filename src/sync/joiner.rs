@@ -1,18 +1,44 @@
-use crate::common::types::{Error, JSONSerializerSpec, SerializerSpec, SessionDetails};
+use crate::common::joiner::{
+    JoinError, host_and_port, inspect_join_reply, select_serializer, upgrade_error, with_roles, with_welcome_details,
+};
+use crate::common::types::{
+    DEFAULT_CONNECT_TIMEOUT, Error, JSONSerializerSpec, JoinRetry, KeepAlive, SerializerSpec, SessionDetails,
+    TlsConfig, Value,
+};
 use crate::sync::peer::Peer;
-use crate::sync::websocket::WebSocketPeer;
-use std::net::{TcpStream, ToSocketAddrs};
+use crate::sync::rawsocket;
+use crate::sync::rawsocket::{connect_tcp, dial, set_deadlines};
+use crate::sync::websocket::{WebSocketPeer, tcp_stream};
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
 use tungstenite::client::IntoClientRequest;
-use tungstenite::{ClientHandshake, ClientRequestBuilder};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{ClientRequestBuilder, Connector, WebSocket, client_tls_with_config};
 use url::Url;
 use wampproto::authenticators::anonymous::AnonymousAuthenticator;
 use wampproto::authenticators::authenticator::ClientAuthenticator;
 use wampproto::joiner;
 use wampproto::serializers::serializer::Serializer;
 
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Joins a realm over WebSocket. permessage-deflate is not offered during the
+/// handshake because tungstenite does not implement it yet, so the size limits
+/// below always apply to uncompressed payloads.
 pub struct WebSocketJoiner {
-    serializer: Box<dyn SerializerSpec>,
+    serializers: Vec<Box<dyn SerializerSpec>>,
     authenticator: Box<dyn ClientAuthenticator>,
+    keepalive: Option<KeepAlive>,
+    tls: Option<TlsConfig>,
+    connect_timeout: Duration,
+    join_timeout: Duration,
+    config: WebSocketConfig,
+    retry: Option<JoinRetry>,
+    roles: Option<HashMap<String, Value>>,
 }
 
 impl Default for WebSocketJoiner {
@@ -24,66 +50,255 @@ impl Default for WebSocketJoiner {
     }
 }
 
-/// This function opens a tcp stream, and upgrades that to websocket.
-/// It then returns the tcp socket itself so that it can be used for doing
-/// multithreaded IO.
-fn connect_and_upgrade(addr: &str, subprotocol: &str) -> Result<TcpStream, Error> {
+/// This function opens a tcp stream, optionally wraps it in TLS for `wss://`
+/// URIs, and upgrades that to websocket. Returns the subprotocol the server
+/// selected, if any.
+fn connect_and_upgrade(
+    addr: &str,
+    subprotocols: &[String],
+    tls: Option<&TlsConfig>,
+    connect_timeout: Duration,
+    config: WebSocketConfig,
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Option<String>), JoinError> {
     // Parse URI and extract host/port
     let uri = addr
         .parse::<Url>()
-        .map_err(|e| Error::new(format!("Invalid URI: {e}")))?;
+        .map_err(|e| JoinError::Rejected(Error::new(format!("Invalid URI: {e}"))))?;
 
-    let host = uri
-        .host_str()
-        .ok_or_else(|| Error::new("Missing host in URI".to_string()))?;
-
-    let port = uri
-        .port_or_known_default()
-        .ok_or_else(|| Error::new("Missing or invalid port".to_string()))?;
+    let (host, port) = host_and_port(&uri).map_err(JoinError::Rejected)?;
 
     // Connect to the socket
-    let socket_addr = (host, port)
-        .to_socket_addrs()
-        .map_err(|e| Error::new(format!("Failed to resolve address: {e}")))?
-        .next()
-        .ok_or_else(|| Error::new("Could not resolve any addresses".to_string()))?;
+    let stream = connect_tcp(&host, port, connect_timeout).map_err(JoinError::Transient)?;
+    upgrade(&uri, stream, subprotocols, tls, connect_timeout, config)
+}
 
-    let stream = TcpStream::connect(socket_addr).map_err(|e| Error::new(format!("Connection failed: {e}")))?;
+/// Performs the TLS (for wss:// URIs) and WebSocket handshake over `stream`.
+fn upgrade(
+    uri: &Url,
+    stream: TcpStream,
+    subprotocols: &[String],
+    tls: Option<&TlsConfig>,
+    timeout: Duration,
+    config: WebSocketConfig,
+) -> Result<(WebSocket<MaybeTlsStream<TcpStream>>, Option<String>), JoinError> {
+    set_deadlines(&stream, Some(timeout)).map_err(JoinError::Transient)?;
 
-    // Perform WebSocket handshake
-    let request = ClientRequestBuilder::new(uri.as_str().parse().unwrap()).with_sub_protocol(subprotocol);
+    let connector = match tls {
+        Some(tls) => Some(Connector::NativeTls(tls.connector().map_err(JoinError::Rejected)?)),
+        None => None,
+    };
 
-    let handshake = ClientHandshake::start(
-        stream
-            .try_clone()
-            .map_err(|e| Error::new(format!("Failed to clone stream: {e}")))?,
+    // Perform TLS (for wss://) and WebSocket handshake
+    let request_uri = uri
+        .as_str()
+        .parse()
+        .map_err(|e| JoinError::Rejected(Error::new(format!("Invalid URI: {e}"))))?;
+    let mut request = ClientRequestBuilder::new(request_uri);
+    for subprotocol in subprotocols {
+        request = request.with_sub_protocol(subprotocol);
+    }
+    let (ws, response) = client_tls_with_config(
         request
             .into_client_request()
-            .map_err(|e| Error::new(format!("Invalid client request: {e}")))?,
-        None,
+            .map_err(|e| JoinError::Rejected(Error::new(format!("Invalid client request: {e}"))))?,
+        stream,
+        Some(config),
+        connector,
     )
-    .map_err(|e| Error::new(format!("Handshake initialization failed: {e}")))?;
+    .map_err(|e| match e {
+        // a read deadline surfaces as WouldBlock, which tungstenite reports as an interrupted handshake
+        HandshakeError::Interrupted(_) => JoinError::Transient(Error::new("connect timed out")),
+        HandshakeError::Failure(e) => upgrade_error(e, subprotocols),
+    })?;
 
-    handshake
-        .handshake()
-        .map_err(|e| Error::new(format!("Handshake failed: {e}")))?;
+    let stream = tcp_stream(ws.get_ref()).map_err(JoinError::Rejected)?;
+    set_deadlines(stream, None).map_err(JoinError::Transient)?;
 
-    Ok(stream)
+    let selected = response
+        .headers()
+        .get("Sec-WebSocket-Protocol")
+        .and_then(|protocol| protocol.to_str().ok())
+        .map(|protocol| protocol.to_string());
+    Ok((ws, selected))
 }
 
 impl WebSocketJoiner {
     pub fn new(serializer: Box<dyn SerializerSpec>, authenticator: Box<dyn ClientAuthenticator>) -> Self {
         Self {
-            serializer,
+            serializers: vec![serializer],
             authenticator,
+            keepalive: None,
+            tls: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            config: WebSocketConfig::default(),
+            retry: None,
+            roles: None,
+        }
+    }
+
+    /// Offers all given serializers in order of preference and uses the one
+    /// the router selects. Replaces the serializer passed to `new`.
+    pub fn with_serializers(mut self, serializers: Vec<Box<dyn SerializerSpec>>) -> Self {
+        if !serializers.is_empty() {
+            self.serializers = serializers;
         }
+        self
+    }
+
+    /// Upper bound for the TCP connect and the transport handshake.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Upper bound for the whole HELLO/WELCOME exchange.
+    pub fn with_join_timeout(mut self, timeout: Duration) -> Self {
+        self.join_timeout = timeout;
+        self
+    }
+
+    /// Announces `roles` in HELLO instead of the default client roles, e.g.
+    /// only `subscriber` for a session that never publishes, or without a
+    /// feature the router rejects. Keys are role names, values the role
+    /// details, like `{"subscriber": {"features": {}}}`.
+    pub fn with_roles(mut self, roles: HashMap<String, Value>) -> Self {
+        self.roles = Some(roles);
+        self
+    }
+
+    /// Retries the connect and join when the connection fails before the
+    /// session was welcomed. Every attempt starts with a fresh copy of the
+    /// authenticator, so challenge-response state is never reused.
+    pub fn with_join_retry(mut self, retry: JoinRetry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Largest message accepted from the router. Defaults to 64 MiB.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.config = self.config.max_message_size(Some(max_message_size));
+        self
+    }
+
+    /// Largest single frame accepted from the router. Defaults to 16 MiB.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.config = self.config.max_frame_size(Some(max_frame_size));
+        self
+    }
+
+    /// Uses the given TLS settings for `wss://` connections instead of the
+    /// system defaults, e.g. to trust a private CA.
+    pub fn with_tls_config(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sends a websocket ping every `interval` to keep idle connections alive.
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(KeepAlive::new(interval));
+        self
+    }
+
+    /// Number of unanswered pings after which the connection is closed. Only
+    /// has an effect together with `with_keepalive`.
+    pub fn with_keepalive_max_missed(mut self, max_missed_pongs: u32) -> Self {
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.max_missed_pongs = max_missed_pongs;
+        }
+        self
     }
 
     pub fn join(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
-        let conn = connect_and_upgrade(uri, self.serializer.subprotocol().as_str())?;
-        let peer = WebSocketPeer::try_new(conn, self.serializer.is_binary())?;
+        let (peer, details, _) = self.join_negotiated(uri, realm)?;
+        Ok((peer, details))
+    }
+
+    /// Like `join`, but also returns the serializer the router selected, which
+    /// is needed to create the `Session` when several were offered.
+    pub fn join_negotiated(
+        &self,
+        uri: &str,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), Error> {
+        retry(self.retry, || self.join_negotiated_once(uri, realm))
+    }
+
+    /// Like `join_negotiated`, but runs the WebSocket upgrade and the WAMP
+    /// handshake over an already connected `stream` instead of dialing
+    /// `uri`. The uri only fills the upgrade request and decides whether TLS
+    /// is negotiated on top. Join retries do not apply, the caller owns the
+    /// stream and it cannot be reopened.
+    pub fn join_over(
+        &self,
+        stream: TcpStream,
+        uri: &str,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), Error> {
+        let uri = uri
+            .parse::<Url>()
+            .map_err(|e| Error::new(format!("Invalid URI: {e}")))?;
+        let subprotocols: Vec<String> = self.serializers.iter().map(|spec| spec.subprotocol()).collect();
+        let upgraded = upgrade(
+            &uri,
+            stream,
+            &subprotocols,
+            self.tls.as_ref(),
+            self.connect_timeout,
+            self.config,
+        )?;
+
+        Ok(self.join_upgraded(upgraded, realm)?)
+    }
+
+    fn join_negotiated_once(
+        &self,
+        uri: &str,
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), JoinError> {
+        let subprotocols: Vec<String> = self.serializers.iter().map(|spec| spec.subprotocol()).collect();
+        let upgraded = connect_and_upgrade(uri, &subprotocols, self.tls.as_ref(), self.connect_timeout, self.config)?;
+        self.join_upgraded(upgraded, realm)
+    }
+
+    /// Selects the serializer from the upgrade response and joins `realm`.
+    fn join_upgraded(
+        &self,
+        (conn, selected): (WebSocket<MaybeTlsStream<TcpStream>>, Option<String>),
+        realm: &str,
+    ) -> Result<(Box<dyn Peer>, SessionDetails, Box<dyn SerializerSpec>), JoinError> {
+        let serializer = select_serializer(&self.serializers, selected.as_deref()).map_err(JoinError::Rejected)?;
+
+        let peer =
+            WebSocketPeer::try_new(conn, serializer.is_binary(), self.keepalive).map_err(JoinError::Transient)?;
         let auth = self.authenticator.clone();
-        join(peer, realm, self.serializer.serializer(), auth)
+        let (peer, details) = join_once(
+            peer,
+            realm,
+            serializer.serializer(),
+            auth,
+            self.roles.as_ref(),
+            self.join_timeout,
+        )?;
+        Ok((peer, details, serializer))
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails for good or the retries are used up.
+fn retry<T>(retry: Option<JoinRetry>, attempt: impl Fn() -> Result<T, JoinError>) -> Result<T, Error> {
+    let mut retries = 0;
+    loop {
+        match attempt() {
+            Ok(joined) => return Ok(joined),
+            Err(JoinError::Transient(e)) => match retry {
+                Some(retry) if retries < retry.attempts => {
+                    retries += 1;
+                    thread::sleep(retry.delay(retries));
+                }
+                _ => return Err(e),
+            },
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
@@ -92,32 +307,274 @@ pub fn join(
     realm: &str,
     serializer: Box<dyn Serializer>,
     authenticator: Box<dyn ClientAuthenticator>,
+    timeout: Duration,
 ) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
+    Ok(join_once(peer, realm, serializer, authenticator, None, timeout)?)
+}
+
+fn join_once(
+    peer: Box<dyn Peer>,
+    realm: &str,
+    serializer: Box<dyn Serializer>,
+    authenticator: Box<dyn ClientAuthenticator>,
+    roles: Option<&HashMap<String, Value>>,
+    timeout: Duration,
+) -> Result<(Box<dyn Peer>, SessionDetails), JoinError> {
+    let deadline = Instant::now() + timeout;
     let mut proto = joiner::Joiner::new(realm, serializer.clone(), authenticator);
 
     let hello_raw = proto
         .send_hello()
-        .map_err(|e| Error::new(format!("failed to send hello: {e}")))?;
-    peer.write(hello_raw)?;
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to send hello: {e}"))))?;
+    let hello_raw = match roles {
+        Some(roles) => with_roles(serializer.as_ref(), hello_raw, roles).map_err(JoinError::Rejected)?,
+        None => hello_raw,
+    };
+    peer.write(hello_raw).map_err(JoinError::Transient)?;
 
+    let mut welcome = None;
     loop {
-        if let Ok(reply) = peer.read() {
-            match proto.receive(reply) {
-                Ok(Some(to_send)) => peer.write(to_send)?,
-                Ok(None) => {
-                    if let Ok(Some(details)) = proto.session_details() {
-                        let details = SessionDetails::new(
-                            details.id,
-                            details.realm.to_string(),
-                            details.authid.to_string(),
-                            details.auth_role.to_string(),
-                        );
-
-                        return Ok((peer, details));
-                    }
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .ok_or_else(|| JoinError::Transient(Error::new("failed to join: timed out")))?;
+
+        let reply = peer
+            .read_timeout(remaining)
+            .map_err(|e| JoinError::Transient(Error::new(format!("failed to read: {e}"))))?;
+
+        // parsed once, then inspected and handed to the protocol joiner
+        let msg = serializer
+            .deserialize(reply)
+            .map_err(|e| JoinError::Rejected(Error::new(format!("failed to join: {e}"))))?;
+        if let Some(details) = inspect_join_reply(msg.as_ref()).map_err(JoinError::Rejected)? {
+            welcome = Some(details);
+        }
+
+        match proto.receive_message(msg) {
+            Ok(Some(to_send)) => {
+                let to_send = serializer
+                    .serialize(to_send.as_ref())
+                    .map_err(|e| JoinError::Rejected(Error::new(format!("failed to join: {e}"))))?;
+                peer.write(to_send).map_err(JoinError::Transient)?
+            }
+            Ok(None) => {
+                if let Ok(Some(details)) = proto.session_details() {
+                    let details = SessionDetails::new(
+                        details.id,
+                        details.realm.to_string(),
+                        details.authid.to_string(),
+                        details.auth_role.to_string(),
+                    );
+                    let details = with_welcome_details(details, welcome.take());
+
+                    return Ok((peer, details));
                 }
-                Err(e) => return Err(Error::new(format!("failed to join: {e}"))),
             }
+            Err(e) => return Err(JoinError::Rejected(Error::new(format!("failed to join: {e}")))),
         }
     }
 }
+
+pub struct RawSocketJoiner {
+    serializer: Box<dyn SerializerSpec>,
+    authenticator: Box<dyn ClientAuthenticator>,
+    connect_timeout: Duration,
+    join_timeout: Duration,
+    retry: Option<JoinRetry>,
+    roles: Option<HashMap<String, Value>>,
+}
+
+impl Default for RawSocketJoiner {
+    fn default() -> Self {
+        Self::new(
+            Box::new(JSONSerializerSpec {}),
+            Box::new(AnonymousAuthenticator::default()),
+        )
+    }
+}
+
+impl RawSocketJoiner {
+    pub fn new(serializer: Box<dyn SerializerSpec>, authenticator: Box<dyn ClientAuthenticator>) -> Self {
+        Self {
+            serializer,
+            authenticator,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            retry: None,
+            roles: None,
+        }
+    }
+
+    /// Upper bound for the TCP connect and the transport handshake.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Upper bound for the whole HELLO/WELCOME exchange.
+    pub fn with_join_timeout(mut self, timeout: Duration) -> Self {
+        self.join_timeout = timeout;
+        self
+    }
+
+    /// Announces `roles` in HELLO instead of the default client roles, see
+    /// `WebSocketJoiner::with_roles`.
+    pub fn with_roles(mut self, roles: HashMap<String, Value>) -> Self {
+        self.roles = Some(roles);
+        self
+    }
+
+    /// Retries the connect and join when the connection fails before the
+    /// session was welcomed, with a fresh copy of the authenticator each time.
+    pub fn with_join_retry(mut self, retry: JoinRetry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn join(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
+        retry(self.retry, || self.join_once(uri, realm))
+    }
+
+    /// Like `join`, but runs the rawsocket and WAMP handshakes over an
+    /// already connected `stream` instead of dialing. Join retries do not
+    /// apply, the caller owns the stream and it cannot be reopened.
+    pub fn join_over(&self, stream: TcpStream, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), Error> {
+        let peer = rawsocket::handshake(stream, self.serializer.as_ref(), self.connect_timeout)?;
+
+        Ok(join_once(
+            peer,
+            realm,
+            self.serializer.serializer(),
+            self.authenticator.clone(),
+            self.roles.as_ref(),
+            self.join_timeout,
+        )?)
+    }
+
+    fn join_once(&self, uri: &str, realm: &str) -> Result<(Box<dyn Peer>, SessionDetails), JoinError> {
+        let peer = dial(uri, self.serializer.as_ref(), self.connect_timeout)?;
+
+        join_once(
+            peer,
+            realm,
+            self.serializer.serializer(),
+            self.authenticator.clone(),
+            self.roles.as_ref(),
+            self.join_timeout,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::memory::MemoryPeer;
+    use native_tls::{Identity, TlsAcceptor};
+    use std::net::TcpListener;
+    use tungstenite::Message;
+    use tungstenite::handshake::server::{Request, Response};
+    use tungstenite::http::HeaderValue;
+
+    const CA: &[u8] = include_bytes!("../../testdata/tls/ca.pem");
+    const WELCOME: &str = r#"[2,1,{"authid":"anonymous","authrole":"anonymous","roles":{"broker":{},"dealer":{}}}]"#;
+
+    /// Accepts one wss connection with a certificate issued by the test CA and
+    /// welcomes the session. Returns the port to dial and the router thread.
+    fn wss_router() -> (u16, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let identity = Identity::from_pkcs8(
+            include_bytes!("../../testdata/tls/server.pem"),
+            include_bytes!("../../testdata/tls/server.key"),
+        )
+        .unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+
+        let router = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // an untrusting client aborts the TLS handshake
+            let Ok(stream) = acceptor.accept(stream) else {
+                return;
+            };
+            let mut ws = tungstenite::accept_hdr(stream, |_: &Request, mut response: Response| {
+                let protocol = HeaderValue::from_static("wamp.2.json");
+                response.headers_mut().insert("Sec-WebSocket-Protocol", protocol);
+                Ok(response)
+            })
+            .unwrap();
+            ws.read().unwrap();
+            ws.send(Message::text(WELCOME)).unwrap();
+        });
+        (port, router)
+    }
+
+    #[test]
+    fn websocket_join_trusts_a_custom_root_for_wss() {
+        let (port, router) = wss_router();
+        let tls = TlsConfig::new()
+            .with_system_roots(false)
+            .with_root_certificate_pem(CA)
+            .unwrap();
+        let joiner = WebSocketJoiner::default().with_tls_config(tls);
+
+        let (_peer, details) = joiner.join(&format!("wss://localhost:{port}/ws"), "realm1").unwrap();
+        router.join().unwrap();
+
+        assert_eq!(details.id(), 1);
+        assert_eq!(details.authid(), "anonymous");
+    }
+
+    #[test]
+    fn websocket_join_rejects_an_untrusted_wss_certificate() {
+        let (port, router) = wss_router();
+        let joiner = WebSocketJoiner::default().with_tls_config(TlsConfig::new().with_system_roots(false));
+
+        let result = joiner.join(&format!("wss://localhost:{port}/ws"), "realm1");
+        router.join().unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn websocket_join_rejects_a_malformed_uri() {
+        assert!(WebSocketJoiner::default().join("not a url", "realm1").is_err());
+    }
+
+    #[test]
+    fn rawsocket_join_rejects_a_malformed_uri() {
+        let joiner = RawSocketJoiner::default();
+        assert!(joiner.join("not a url", "realm1").is_err());
+        assert!(joiner.join("rs://localhost", "realm1").is_err());
+    }
+
+    #[test]
+    fn join_fails_when_the_router_drops_mid_handshake() {
+        let (client, router) = MemoryPeer::pair();
+        // reads the HELLO and drops the connection instead of answering
+        let server = thread::spawn(move || {
+            router.read().unwrap();
+        });
+
+        let started = Instant::now();
+        let serializer = JSONSerializerSpec {}.serializer();
+        let authenticator = Box::new(AnonymousAuthenticator::default());
+        let result = join(client, "realm1", serializer, authenticator, Duration::from_secs(30));
+        server.join().unwrap();
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn join_gives_up_when_the_router_never_answers() {
+        let (client, _router) = MemoryPeer::pair();
+
+        let started = Instant::now();
+        let serializer = JSONSerializerSpec {}.serializer();
+        let authenticator = Box::new(AnonymousAuthenticator::default());
+        let result = join(client, "realm1", serializer, authenticator, Duration::from_millis(200));
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
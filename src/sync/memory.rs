@@ -0,0 +1,241 @@
+use crate::common::types::{Error, TRANSPORT_MEMORY, TransportType};
+use crate::sync::peer::Peer;
+use std::sync::{Mutex, mpsc};
+use std::time::Duration;
+
+// `None` marks the end of the connection, so that either end can close it
+// while the other still holds a sender.
+type Frame = Option<Vec<u8>>;
+
+/// One end of an in-memory connection, mainly useful to drive a `Session`
+/// from tests without a router. Whatever is written to one end of a `pair`
+/// is read from the other.
+#[derive(Debug)]
+pub struct MemoryPeer {
+    reader: Mutex<mpsc::Receiver<Frame>>,
+    writer: mpsc::Sender<Frame>,
+    // feeds this end's own reader, to wake it up when closed
+    wake: mpsc::Sender<Frame>,
+}
+
+impl MemoryPeer {
+    fn received(&self, frame: Frame) -> Result<Vec<u8>, Error> {
+        match frame {
+            Some(data) => Ok(data),
+            None => {
+                // keep the connection closed for later reads
+                _ = self.wake.send(None);
+                Err(Error::new("connection closed"))
+            }
+        }
+    }
+}
+
+impl Peer for MemoryPeer {
+    fn kind(&self) -> TransportType {
+        TRANSPORT_MEMORY
+    }
+
+    fn read(&self) -> Result<Vec<u8>, Error> {
+        let frame = {
+            let reader = self.reader.lock().unwrap();
+            reader.recv().map_err(|e| Error::new(format!("read error: {e}")))?
+        };
+        self.received(frame)
+    }
+
+    fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let frame = {
+            let reader = self.reader.lock().unwrap();
+            reader
+                .recv_timeout(timeout)
+                .map_err(|e| Error::new(format!("read error: {e}")))?
+        };
+        self.received(frame)
+    }
+
+    fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.writer
+            .send(Some(data))
+            .map_err(|e| Error::new(format!("write error: {e}")))
+    }
+
+    fn close(&self) {
+        _ = self.writer.send(None);
+        _ = self.wake.send(None);
+    }
+}
+
+impl Drop for MemoryPeer {
+    fn drop(&mut self) {
+        _ = self.writer.send(None);
+    }
+}
+
+impl MemoryPeer {
+    /// Creates two connected peers. Dropping or closing one end closes the other.
+    pub fn pair() -> (Box<dyn Peer>, Box<dyn Peer>) {
+        let (left_writer, right_reader) = mpsc::channel();
+        let (right_writer, left_reader) = mpsc::channel();
+
+        let left = MemoryPeer {
+            reader: Mutex::new(left_reader),
+            writer: left_writer.clone(),
+            wake: right_writer.clone(),
+        };
+        let right = MemoryPeer {
+            reader: Mutex::new(right_reader),
+            writer: right_writer,
+            wake: left_writer,
+        };
+
+        (Box::new(left), Box::new(right))
+    }
+}
+
+/// A router for tests that serves any number of sessions over memory peers,
+/// the routing itself is done by `common::router::Realm`.
+#[cfg(test)]
+pub(crate) mod router {
+    use super::MemoryPeer;
+    use crate::common::router::Realm;
+    use crate::common::types::SessionDetails;
+    use crate::sync::peer::Peer;
+    use crate::sync::session::{Session, SessionOptions};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use wampproto::serializers::json::JSONSerializer;
+
+    #[derive(Clone, Default)]
+    pub(crate) struct RouterStub {
+        realm: Arc<Mutex<Realm>>,
+        peers: Arc<Mutex<HashMap<i64, Arc<dyn Peer>>>>,
+    }
+
+    impl RouterStub {
+        pub(crate) fn new() -> Self {
+            Self::default()
+        }
+
+        /// Attaches a new session with the default options.
+        pub(crate) fn session(&self) -> Session {
+            self.session_with(Default::default())
+        }
+
+        pub(crate) fn session_with(&self, options: SessionOptions) -> Session {
+            let (details, peer) = self.connect();
+            Session::with_options(details, peer, Box::new(JSONSerializer {}), options)
+        }
+
+        /// Attaches a new session and returns its details and the client end
+        /// of its connection, for tests that wrap the peer before creating
+        /// the session. The session has to use JSON.
+        pub(crate) fn connect(&self) -> (SessionDetails, Box<dyn Peer>) {
+            let (client, router) = MemoryPeer::pair();
+            let router: Arc<dyn Peer> = router.into();
+            let id = self.realm.lock().unwrap().join();
+            self.peers.lock().unwrap().insert(id, router.clone());
+
+            let stub = self.clone();
+            thread::spawn(move || {
+                while let Ok(payload) = router.read() {
+                    let replies = stub.realm.lock().unwrap().receive(id, &payload);
+                    for (to, data) in replies {
+                        let peer = stub.peers.lock().unwrap().get(&to).cloned();
+                        if let Some(peer) = peer {
+                            _ = peer.write(data);
+                        }
+                    }
+                }
+                stub.realm.lock().unwrap().leave(id);
+                stub.peers.lock().unwrap().remove(&id);
+            });
+
+            let details = SessionDetails::new(id, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+            (details, client)
+        }
+
+        /// Drops the connection of session `id` without a GOODBYE.
+        pub(crate) fn disconnect(&self, id: i64) {
+            if let Some(peer) = self.peers.lock().unwrap().remove(&id) {
+                peer.close();
+            }
+        }
+
+        /// Revokes a registration or subscription as if the router removed it
+        /// on its own.
+        pub(crate) fn revoke(&self, id: i64, reason: &str) {
+            let revoked = self.realm.lock().unwrap().revoke(id, reason);
+            if let Some((to, data)) = revoked {
+                let peer = self.peers.lock().unwrap().get(&to).cloned();
+                if let Some(peer) = peer {
+                    _ = peer.write(data);
+                }
+            }
+        }
+
+        /// Messages received so far, with the id of the session that sent them.
+        pub(crate) fn received(&self) -> Vec<(i64, serde_json::Value)> {
+            self.realm.lock().unwrap().received()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::router::RouterStub;
+    use super::*;
+    use crate::sync::types::{CallRequest, Event, Invocation, PublishRequest, RegisterRequest, SubscribeRequest};
+    use crate::sync::types::{Value, Yield};
+
+    #[test]
+    fn pair_carries_frames_both_ways_until_dropped() {
+        let (left, right) = MemoryPeer::pair();
+        left.write(b"ping".to_vec()).unwrap();
+        assert_eq!(right.read().unwrap(), b"ping");
+        right.write(b"pong".to_vec()).unwrap();
+        assert_eq!(left.read().unwrap(), b"pong");
+
+        drop(right);
+        assert!(left.read().is_err());
+    }
+
+    #[test]
+    fn stub_routes_a_call_to_the_callee() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+
+        let registered = callee
+            .register(RegisterRequest::new("io.xconn.echo", |inv: Invocation| {
+                Yield::new(inv.args, inv.kwargs)
+            }))
+            .unwrap();
+        assert!(registered.error.is_none());
+
+        let response = caller.call(CallRequest::new("io.xconn.echo").arg("hello")).unwrap();
+        assert_eq!(response.args, Some(vec![Value::Str("hello".to_string())]));
+    }
+
+    #[test]
+    fn stub_delivers_published_events() {
+        let router = RouterStub::new();
+        let subscriber = router.session();
+        let publisher = router.session();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        subscriber
+            .subscribe(SubscribeRequest::new("io.xconn.topic", move |event: Event| {
+                _ = sender.lock().unwrap().send(event.args);
+            }))
+            .unwrap();
+        publisher
+            .publish(PublishRequest::new("io.xconn.topic").arg("hello").acknowledge())
+            .unwrap();
+
+        let args = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(args, vec![Value::Str("hello".to_string())]);
+    }
+}
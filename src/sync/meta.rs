@@ -0,0 +1,82 @@
+use crate::common::meta::{
+    REGISTRATION_LIST, REGISTRATION_LOOKUP, REGISTRATION_MATCH, SESSION_COUNT, SESSION_LIST, SUBSCRIPTION_LIST,
+    SUBSCRIPTION_LOOKUP, SUBSCRIPTION_MATCH, parse_id, parse_id_list, parse_lists, parse_optional_id,
+};
+pub use crate::common::meta::{RegistrationLists, SubscriptionLists};
+use crate::common::types::{CallRequest, Error};
+use crate::sync::session::Session;
+
+/// Queries about the sessions joined to the realm.
+#[derive(Clone, Copy)]
+pub struct Sessions<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Sessions<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub fn count(&self) -> Result<i64, Error> {
+        parse_id(self.session.call(CallRequest::new(SESSION_COUNT))?)
+    }
+
+    pub fn list(&self) -> Result<Vec<i64>, Error> {
+        parse_id_list(self.session.call(CallRequest::new(SESSION_LIST))?)
+    }
+}
+
+/// Queries about the procedures registered on the realm.
+#[derive(Clone, Copy)]
+pub struct Registrations<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Registrations<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub fn list(&self) -> Result<RegistrationLists, Error> {
+        parse_lists(self.session.call(CallRequest::new(REGISTRATION_LIST))?)
+    }
+
+    /// Returns the ID of the exact-match registration for `procedure`, if any.
+    pub fn lookup(&self, procedure: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(
+            self.session
+                .call(CallRequest::new(REGISTRATION_LOOKUP).arg(procedure))?,
+        )
+    }
+
+    /// Returns the ID of the registration a call to `procedure` would be routed to.
+    pub fn matching(&self, procedure: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(self.session.call(CallRequest::new(REGISTRATION_MATCH).arg(procedure))?)
+    }
+}
+
+/// Queries about the topics subscribed to on the realm.
+#[derive(Clone, Copy)]
+pub struct Subscriptions<'a> {
+    session: &'a Session,
+}
+
+impl<'a> Subscriptions<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self { session }
+    }
+
+    pub fn list(&self) -> Result<SubscriptionLists, Error> {
+        parse_lists(self.session.call(CallRequest::new(SUBSCRIPTION_LIST))?)
+    }
+
+    /// Returns the ID of the exact-match subscription for `topic`, if any.
+    pub fn lookup(&self, topic: &str) -> Result<Option<i64>, Error> {
+        parse_optional_id(self.session.call(CallRequest::new(SUBSCRIPTION_LOOKUP).arg(topic))?)
+    }
+
+    /// Returns the IDs of all subscriptions an event published to `topic` would reach.
+    pub fn matching(&self, topic: &str) -> Result<Vec<i64>, Error> {
+        parse_id_list(self.session.call(CallRequest::new(SUBSCRIPTION_MATCH).arg(topic))?)
+    }
+}
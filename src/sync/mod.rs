@@ -1,6 +1,10 @@
 pub mod client;
 pub mod joiner;
+pub mod memory;
+pub mod meta;
 pub mod peer;
+mod pool;
+pub mod rawsocket;
 pub mod session;
 pub mod types;
 pub mod websocket;
@@ -1,8 +1,26 @@
 use crate::common::types::{Error, TransportType};
 use std::fmt::Debug;
+use std::time::Duration;
 
 pub trait Peer: Debug + Send + Sync {
     fn kind(&self) -> TransportType;
     fn read(&self) -> Result<Vec<u8>, Error>;
+    fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error>;
     fn write(&self, data: Vec<u8>) -> Result<(), Error>;
+
+    /// Closes the connection so that pending and later reads fail. The
+    /// default does nothing and leaves closing to dropping the peer.
+    fn close(&self) {}
+
+    /// Bounds how long `read` blocks before it fails with a timeout error,
+    /// `None` waits indefinitely. The default ignores the setting.
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Bounds how long `write` blocks before it fails with a timeout error,
+    /// `None` waits indefinitely. The default ignores the setting.
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> Result<(), Error> {
+        Ok(())
+    }
 }
@@ -0,0 +1,120 @@
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs invocation and event handlers, either on a thread of their own or, if
+/// a limit is configured, on a fixed number of worker threads that pick up
+/// queued jobs one after another. Jobs start in the order they were spawned,
+/// the workers share one queue so a long job doesn't hold back the others.
+///
+/// In manual mode nothing runs by itself, jobs wait in the queue until the
+/// user's thread picks them up through `poll_once` or `run`.
+pub(crate) enum Dispatcher {
+    Unbounded,
+    Pool(mpsc::Sender<Job>),
+    Manual {
+        // taken by close, so that run returns once the queue is drained
+        sender: Mutex<Option<mpsc::Sender<Job>>>,
+        receiver: Mutex<mpsc::Receiver<Job>>,
+    },
+}
+
+impl Dispatcher {
+    pub(crate) fn new(max_concurrent: Option<usize>) -> Self {
+        let Some(workers) = max_concurrent else {
+            return Dispatcher::Unbounded;
+        };
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                loop {
+                    // the lock is only held while waiting for the next job
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    // a panicking handler must not take its worker down with it
+                    _ = catch_unwind(AssertUnwindSafe(job));
+                }
+            });
+        }
+
+        Dispatcher::Pool(sender)
+    }
+
+    pub(crate) fn manual() -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        Dispatcher::Manual {
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    pub(crate) fn is_manual(&self) -> bool {
+        matches!(self, Dispatcher::Manual { .. })
+    }
+
+    pub(crate) fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self {
+            Dispatcher::Unbounded => {
+                thread::spawn(job);
+            }
+            Dispatcher::Pool(sender) => {
+                _ = sender.send(Box::new(job));
+            }
+            Dispatcher::Manual { sender, .. } => {
+                if let Some(sender) = sender.lock().unwrap().as_ref() {
+                    _ = sender.send(Box::new(job));
+                }
+            }
+        }
+    }
+
+    /// Runs the next queued job on the calling thread, if there is one.
+    pub(crate) fn poll_once(&self) -> bool {
+        let Dispatcher::Manual { receiver, .. } = self else {
+            return false;
+        };
+
+        let job = receiver.lock().unwrap().try_recv();
+        match job {
+            Ok(job) => {
+                job();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Runs queued jobs on the calling thread as they come in, until the
+    /// dispatcher was closed and everything queued before has run.
+    pub(crate) fn run(&self) {
+        let Dispatcher::Manual { receiver, .. } = self else {
+            return;
+        };
+
+        loop {
+            // the lock is only held while waiting for the next job
+            let job = match receiver.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+            job();
+        }
+    }
+
+    /// Stops accepting jobs, called once the connection is gone.
+    pub(crate) fn close(&self) {
+        if let Dispatcher::Manual { sender, .. } = self {
+            sender.lock().unwrap().take();
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::common::joiner::{JoinError, host_and_port};
+use crate::common::rawsocket::{check_handshake_response, check_message_size, max_message_size};
+use crate::common::types::{Error, SerializerSpec, TRANSPORT_RAW_SOCKET, TransportType};
+use crate::sync::peer::Peer;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use url::Url;
+use wampproto::transports::rawsocket::{
+    DEFAULT_MAX_MSG_SIZE, Handshake, Message as RSMessage, MessageHeader, receive_handshake, receive_message_header,
+    send_handshake, send_message_header,
+};
+
+#[derive(Debug)]
+pub struct RawSocketPeer {
+    reader: Mutex<TcpStream>,
+    writer: Mutex<TcpStream>,
+    // restored after a read_timeout call
+    read_timeout: Mutex<Option<Duration>>,
+    // as negotiated in the handshake, larger messages are refused by write
+    max_message_size: usize,
+}
+
+impl Peer for RawSocketPeer {
+    fn kind(&self) -> TransportType {
+        TRANSPORT_RAW_SOCKET
+    }
+
+    fn read(&self) -> Result<Vec<u8>, Error> {
+        let mut reader = self.reader.lock().unwrap();
+        read_message(&mut reader)
+    }
+
+    fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let mut reader = self.reader.lock().unwrap();
+        reader
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| Error::new(format!("failed to set read timeout: {e}")))?;
+
+        let result = read_message(&mut reader);
+        _ = reader.set_read_timeout(*self.read_timeout.lock().unwrap());
+        result
+    }
+
+    fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+        check_message_size(data.len(), self.max_message_size)?;
+        let header = MessageHeader::new(RSMessage::Wamp, data.len());
+        let header_raw = send_message_header(&header);
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&header_raw).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => Error::new("write timed out"),
+            _ => Error::new(format!("failed to send header: {e}")),
+        })?;
+
+        writer.write_all(&data).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => Error::new("write timed out"),
+            _ => Error::new(format!("failed to send payload: {e}")),
+        })?;
+
+        Ok(())
+    }
+
+    fn close(&self) {
+        // both halves share the socket, so this also ends a blocked read
+        _ = self.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.reader
+            .lock()
+            .unwrap()
+            .set_read_timeout(timeout)
+            .map_err(|e| Error::new(format!("failed to set read timeout: {e}")))?;
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.writer
+            .lock()
+            .unwrap()
+            .set_write_timeout(timeout)
+            .map_err(|e| Error::new(format!("failed to set write timeout: {e}")))
+    }
+}
+
+impl RawSocketPeer {
+    pub fn try_new(stream: TcpStream) -> Result<Box<dyn Peer>, Error> {
+        Self::with_max_message_size(stream, DEFAULT_MAX_MSG_SIZE)
+    }
+
+    /// Like `try_new` for a router that accepts messages up to
+    /// `max_message_size` bytes, as announced in its handshake reply.
+    pub fn with_max_message_size(stream: TcpStream, max_message_size: usize) -> Result<Box<dyn Peer>, Error> {
+        let writer = stream
+            .try_clone()
+            .map_err(|e| Error::new(format!("clone error: {e}")))?;
+
+        Ok(Box::new(RawSocketPeer {
+            reader: Mutex::new(stream),
+            writer: Mutex::new(writer),
+            read_timeout: Mutex::new(None),
+            max_message_size,
+        }))
+    }
+}
+
+fn read_message(reader: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => Error::new("read timed out"),
+        _ => Error::new(format!("failed to read message header: {e}")),
+    })?;
+
+    let header =
+        receive_message_header(&buf).map_err(|e| Error::new(format!("failed to parse message header: {e}")))?;
+
+    let mut buf = vec![0u8; header.length()];
+    reader
+        .read_exact(&mut buf)
+        .map_err(|e| Error::new(format!("failed to read message: {e}")))?;
+
+    Ok(buf)
+}
+
+pub fn connect_rawsocket(
+    uri: &str,
+    serializer: Box<dyn SerializerSpec>,
+    connect_timeout: Duration,
+) -> Result<Box<dyn Peer>, Error> {
+    Ok(dial(uri, serializer.as_ref(), connect_timeout)?)
+}
+
+/// Like `connect_rawsocket`, but tells the joiner whether a failure is worth
+/// retrying: only the TCP connect and I/O during the handshake are.
+pub(crate) fn dial(
+    uri: &str,
+    serializer: &dyn SerializerSpec,
+    connect_timeout: Duration,
+) -> Result<Box<dyn Peer>, JoinError> {
+    let parsed = Url::parse(uri).map_err(|e| JoinError::Rejected(Error::new(format!("invalid uri: {e}"))))?;
+    if matches!(parsed.scheme(), "rss" | "tcps") {
+        return Err(JoinError::Rejected(Error::new(
+            "secure rawsocket is not supported by the sync client",
+        )));
+    }
+
+    let (host, port) = host_and_port(&parsed).map_err(JoinError::Rejected)?;
+    let stream = connect_tcp(&host, port, connect_timeout).map_err(JoinError::Transient)?;
+    handshake(stream, serializer, connect_timeout)
+}
+
+/// Runs the rawsocket handshake over an established stream and wraps it in a
+/// peer that respects the message size limit the router announced.
+pub(crate) fn handshake(
+    mut stream: TcpStream,
+    serializer: &dyn SerializerSpec,
+    timeout: Duration,
+) -> Result<Box<dyn Peer>, JoinError> {
+    // bound the rawsocket handshake as well, a silent peer would block forever otherwise
+    set_deadlines(&stream, Some(timeout)).map_err(JoinError::Transient)?;
+
+    let handshake = Handshake::new(serializer.serializer_id(), DEFAULT_MAX_MSG_SIZE);
+
+    let handshake_raw = send_handshake(&handshake)
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to serialize handshake: {e}"))))?;
+
+    stream
+        .write_all(&handshake_raw)
+        .map_err(|e| JoinError::Transient(Error::new(format!("failed to send handshake: {e}"))))?;
+
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => JoinError::Transient(Error::new("connect timed out")),
+        _ => JoinError::Transient(Error::new(format!("failed to read handshake response: {e}"))),
+    })?;
+
+    // the router refused the handshake, e.g. for an unsupported serializer
+    check_handshake_response(&buf).map_err(JoinError::Rejected)?;
+    _ = receive_handshake(&buf)
+        .map_err(|e| JoinError::Rejected(Error::new(format!("failed to parse handshake response: {e}"))))?;
+
+    set_deadlines(&stream, None).map_err(JoinError::Transient)?;
+    RawSocketPeer::with_max_message_size(stream, max_message_size(&buf)).map_err(JoinError::Transient)
+}
+
+/// Connects to the first reachable address `host` resolves to. IPv6 and IPv4
+/// addresses are tried alternately, each getting an even share of what is left
+/// of `connect_timeout`, so that a dead address family only delays the connect
+/// instead of failing it.
+pub(crate) fn connect_tcp(host: &str, port: u16, connect_timeout: Duration) -> Result<TcpStream, Error> {
+    let resolved: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| Error::new(format!("Failed to resolve address: {e}")))?
+        .collect();
+    if resolved.is_empty() {
+        return Err(Error::new("Could not resolve any addresses".to_string()));
+    }
+
+    let prefer_v6 = resolved[0].is_ipv6();
+    let (mut preferred, mut other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        resolved.into_iter().partition(|addr| addr.is_ipv6() == prefer_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut addrs = Vec::with_capacity(preferred.len() + other.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        addrs.extend(preferred.pop());
+        addrs.extend(other.pop());
+    }
+
+    let deadline = Instant::now() + connect_timeout;
+    let mut last_error = None;
+    for (i, addr) in addrs.iter().enumerate() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let attempt_timeout = remaining / (addrs.len() - i) as u32;
+        match TcpStream::connect_timeout(addr, attempt_timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(match last_error {
+        Some(e) if e.kind() != ErrorKind::TimedOut => Error::new(format!("connect error: {e}")),
+        _ => Error::new("connect timed out"),
+    })
+}
+
+pub(crate) fn set_deadlines(stream: &TcpStream, timeout: Option<Duration>) -> Result<(), Error> {
+    stream
+        .set_read_timeout(timeout)
+        .map_err(|e| Error::new(format!("failed to set read timeout: {e}")))?;
+    stream
+        .set_write_timeout(timeout)
+        .map_err(|e| Error::new(format!("failed to set write timeout: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::types::JSONSerializerSpec;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn handshake_refused_by_the_router_is_not_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut router, _) = listener.accept().unwrap();
+        let refuse = thread::spawn(move || {
+            let mut hello = [0u8; 4];
+            router.read_exact(&mut hello).unwrap();
+            // error code 1, serializer unsupported
+            router.write_all(&[0x7F, 0x10, 0, 0]).unwrap();
+            router
+        });
+
+        let result = handshake(client, &JSONSerializerSpec {}, TIMEOUT);
+        let _router = refuse.join().unwrap();
+        assert!(matches!(result, Err(JoinError::Rejected(_))));
+    }
+
+    #[test]
+    fn handshake_dropped_by_the_router_is_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        drop(listener.accept().unwrap());
+
+        let result = handshake(client, &JSONSerializerSpec {}, TIMEOUT);
+        assert!(matches!(result, Err(JoinError::Transient(_))));
+    }
+
+    #[test]
+    fn dial_retries_a_refused_connection_but_not_a_bad_uri() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let serializer = JSONSerializerSpec {};
+        let refused = dial(&format!("rs://127.0.0.1:{port}"), &serializer, TIMEOUT);
+        assert!(matches!(refused, Err(JoinError::Transient(_))));
+        let secure = dial(&format!("rss://127.0.0.1:{port}"), &serializer, TIMEOUT);
+        assert!(matches!(secure, Err(JoinError::Rejected(_))));
+        let invalid = dial("not a url", &serializer, TIMEOUT);
+        assert!(matches!(invalid, Err(JoinError::Rejected(_))));
+    }
+}
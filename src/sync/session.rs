@@ -1,18 +1,29 @@
+use crate::common::ppt::{payload, validate_ppt, validate_ppt_options};
+use crate::common::trace::{Outcome, Span};
 use crate::common::types::{
-    CallRequest, CallResponse, Error, Event as XEvent, Invocation as XInvocation, PublishRequest, PublishResponse,
-    RegisterResponse, SessionDetails, SubscribeResponse, WampError,
+    CallRequest, CallResponse, CancelMode, DEFAULT_ACKNOWLEDGE_TIMEOUT, DEFAULT_CALL_BUFFER_SIZE,
+    DEFAULT_EVENT_BUFFER_SIZE, Error, ErrorFn, Event as XEvent, EventFilterFn, GoodbyeInfo, Heartbeat, InterruptToken,
+    Invocation as XInvocation, MessageFn, ProgressFn, PublishRequest, PublishResponse, RegisterResponse, Revocation,
+    RevokedFn, SessionDetails, SubscribeResponse, SubscriptionSpec, TransportType, WampError,
 };
+use crate::common::uri::{match_policy, validate_uri};
 use crate::sync::peer::Peer;
-use crate::sync::types::{EventFn, RegisterFn, RegisterRequest, SubscribeRequest};
+use crate::sync::pool::Dispatcher;
+use crate::sync::types::{EventFn, RegisterFn, RegisterRequest, SubscribeRequest, SubscriptionRegistry};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex, mpsc};
-use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use wampproto::idgen::SessionScopeIDGenerator;
 use wampproto::messages::call::MESSAGE_TYPE_CALL;
+use wampproto::messages::cancel::Cancel;
 use wampproto::messages::error::{Error as ErrorMsg, MESSAGE_TYPE_ERROR};
 use wampproto::messages::event::{Event, MESSAGE_TYPE_EVENT};
 use wampproto::messages::goodbye::{Goodbye, MESSAGE_TYPE_GOODBYE};
+use wampproto::messages::interrupt::{Interrupt, MESSAGE_TYPE_INTERRUPT};
 use wampproto::messages::invocation::{Invocation, MESSAGE_TYPE_INVOCATION};
 use wampproto::messages::message::Message;
 use wampproto::messages::publish::MESSAGE_TYPE_PUBLISH;
@@ -30,32 +41,165 @@ use wampproto::messages::unsubscribed::{MESSAGE_TYPE_UNSUBSCRIBED, Unsubscribed}
 use wampproto::messages::yield_::Yield;
 use wampproto::serializers::serializer::Serializer;
 
+/// A joined WAMP session. All state is shared behind `Arc`s, so cloning is
+/// cheap and every clone talks to the same connection. Sessions are
+/// `Send + Sync` and all methods take `&self`, so calls, publishes and
+/// (un)registrations can be issued from many threads concurrently. Only one
+/// clone will observe the disconnect in `wait_disconnect`.
+#[derive(Clone)]
 pub struct Session {
-    _details: SessionDetails,
+    details: SessionDetails,
     serializer: Arc<Box<dyn Serializer>>,
-    idgen: SessionScopeIDGenerator,
+    subprotocol: Option<String>,
+    // shared by every clone, next_id is atomic so any thread may call it
+    idgen: Arc<SessionScopeIDGenerator>,
     peer: Arc<Box<dyn Peer>>,
 
     state: Arc<State>,
-    goodbye_receiver_channel: Mutex<mpsc::Receiver<()>>,
-    exist_receiver_channel: Mutex<mpsc::Receiver<()>>,
+    goodbye_receiver_channel: Arc<Mutex<mpsc::Receiver<()>>>,
+    exist_receiver_channel: Arc<Mutex<mpsc::Receiver<GoodbyeInfo>>>,
+    // only held by the user's clones, not the heartbeat's, so that it is
+    // dropped together with the last of them
+    closer: Option<Arc<Closer>>,
+}
+
+/// Closes the connection and stops the reader thread, either through
+/// `Session::close` or once the last clone of the session is dropped.
+struct Closer {
+    state: Arc<State>,
+    peer: Arc<Box<dyn Peer>>,
+    reader: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Closer {
+    fn close(&self) {
+        self.state.closing.store(true, Ordering::SeqCst);
+        // the reader notices the closed connection, fails whatever is still
+        // pending and exits.
+        self.peer.close();
+
+        let reader = self.reader.lock().unwrap().take();
+        if let Some(reader) = reader {
+            // a handler on the reader thread may drop the last clone
+            if reader.thread().id() != thread::current().id() {
+                _ = reader.join();
+            }
+        }
+    }
+}
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Optional behavior configured through the client builder.
+#[derive(Debug, Default)]
+pub(crate) struct SessionOptions {
+    /// Run invocation and event handlers on this many worker threads instead
+    /// of a thread per handler.
+    pub(crate) max_concurrent_invocations: Option<usize>,
+    /// Receives errors from the background reader and handler threads.
+    pub(crate) error_handler: Option<ErrorFn>,
+    /// Sees every incoming message before it is dispatched.
+    pub(crate) message_handler: Option<MessageFn>,
+    /// Told about registrations and subscriptions the router revoked.
+    pub(crate) revoked_handler: Option<RevokedFn>,
+    /// Capacity of the queue feeding each ordered subscription's handler.
+    pub(crate) event_buffer_size: Option<usize>,
+    /// Capacity of each call's queue of progressive results.
+    pub(crate) call_buffer_size: Option<usize>,
+    /// The WAMP subprotocol of the serializer in use, e.g. `wamp.2.cbor`.
+    pub(crate) subprotocol: Option<String>,
+    /// Validate outgoing URIs against the strict rather than the loose rules.
+    pub(crate) strict_uris: bool,
+    /// Queue invocations and events for `poll_once`/`run` instead of running
+    /// them on threads of their own.
+    pub(crate) manual_dispatch: bool,
+}
+
+struct Registration {
+    // kept to list the registration through `Session::registrations`
+    procedure: String,
+    callback: RegisterFn,
+    // sent along with every YIELD, e.g. to mark results as passthrough
+    result_options: HashMap<String, Value>,
+}
+
+struct Subscription {
+    // kept to hand the subscription out through `Session::registry`
+    spec: SubscriptionSpec,
+    callback: EventFn,
+    // set for ordered subscriptions, feeds the thread that runs the handler
+    queue: Option<mpsc::SyncSender<(XEvent, HandlerGuard)>>,
+    filter: Option<EventFilterFn>,
+}
+
+// the handler travels with the request and is stored by the reader when the
+// router confirms, so it is in place before any INVOCATION or EVENT for it
+struct PendingRegister {
+    sender: mpsc::SyncSender<RegisterResponse>,
+    registration: Registration,
+}
+
+struct PendingSubscribe {
+    sender: mpsc::SyncSender<SubscribeResponse>,
+    subscription: Subscription,
+}
+
+// an acknowledged publish waiting for its PUBLISHED, and for how long at most
+type PendingPublish = (mpsc::Receiver<PublishResponse>, Duration);
+
+// what the reader passes on to a waiting call, the caller hands progressive results
+// to its progress callback itself so a slow callback doesn't hold up the reader
+enum CallUpdate {
+    Progress(CallResponse),
+    Done(CallResponse),
+}
+
+// the reader removes the entry before sending the final response, and sends without
+// holding the lock since a buffer full of progressive results blocks it
+struct PendingCall {
+    sender: mpsc::SyncSender<CallUpdate>,
+    // intermediate results are only expected if the call asked for them
+    receive_progress: bool,
 }
 
 struct State {
     // RPC states
-    call_requests: Mutex<HashMap<i64, mpsc::Sender<CallResponse>>>,
-    register_requests: Mutex<HashMap<i64, mpsc::Sender<RegisterResponse>>>,
-    unregister_requests: Mutex<HashMap<i64, mpsc::Sender<Option<WampError>>>>,
-    registrations: Mutex<HashMap<i64, RegisterFn>>,
+    call_requests: Mutex<HashMap<i64, PendingCall>>,
+    register_requests: Mutex<HashMap<i64, PendingRegister>>,
+    unregister_requests: Mutex<HashMap<i64, mpsc::SyncSender<Option<WampError>>>>,
+    registrations: Mutex<HashMap<i64, Registration>>,
+    invocations: Mutex<HashMap<i64, InterruptToken>>,
 
     // PubSub states
-    publish_requests: Mutex<HashMap<i64, mpsc::Sender<PublishResponse>>>,
-    subscribe_requests: Mutex<HashMap<i64, mpsc::Sender<SubscribeResponse>>>,
-    unsubscribe_requests: Mutex<HashMap<i64, mpsc::Sender<Option<WampError>>>>,
-    subscriptions: Mutex<HashMap<i64, EventFn>>,
+    publish_requests: Mutex<HashMap<i64, mpsc::SyncSender<PublishResponse>>>,
+    subscribe_requests: Mutex<HashMap<i64, PendingSubscribe>>,
+    unsubscribe_requests: Mutex<HashMap<i64, mpsc::SyncSender<Option<WampError>>>>,
+    subscriptions: Mutex<HashMap<i64, Subscription>>,
 
     // goodbye stuff
     goodbye_sent: Mutex<bool>,
+
+    // set by shutdown, new invocations and events are turned away
+    closing: AtomicBool,
+    // invocation and event handlers dispatched but not yet finished
+    in_flight: Arc<InFlight>,
+
+    // runs invocation and event handlers
+    dispatcher: Dispatcher,
+
+    error_handler: Option<ErrorFn>,
+    message_handler: Option<MessageFn>,
+    revoked_handler: Option<RevokedFn>,
+    event_buffer_size: usize,
+    call_buffer_size: usize,
+    strict_uris: bool,
+
+    // why the reader stopped, set once the connection is gone
+    closed: Mutex<Option<String>>,
 }
 
 impl Default for State {
@@ -65,31 +209,118 @@ impl Default for State {
             register_requests: Default::default(),
             unregister_requests: Default::default(),
             registrations: Default::default(),
+            invocations: Default::default(),
             publish_requests: Default::default(),
             subscribe_requests: Default::default(),
             unsubscribe_requests: Default::default(),
             subscriptions: Default::default(),
 
             goodbye_sent: Mutex::new(false),
+            closing: AtomicBool::new(false),
+            in_flight: Default::default(),
+            dispatcher: Dispatcher::new(None),
+            error_handler: None,
+            message_handler: None,
+            revoked_handler: None,
+            event_buffer_size: DEFAULT_EVENT_BUFFER_SIZE,
+            call_buffer_size: DEFAULT_CALL_BUFFER_SIZE,
+            strict_uris: false,
+            closed: Mutex::new(None),
         }
     }
 }
 
+/// Counts the handlers that were dispatched but did not finish yet, shared
+/// apart from `State` so ordered subscription workers don't keep it alive.
+#[derive(Default)]
+struct InFlight {
+    count: Mutex<usize>,
+    // signalled when the last of them finished, for shutdown
+    idle: Condvar,
+}
+
+/// Marks a handler as finished when dropped, which also happens when the
+/// handler panicked.
+struct HandlerGuard(Arc<InFlight>);
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.0.idle.notify_all();
+        }
+    }
+}
+
+impl State {
+    fn handler_started(&self) -> HandlerGuard {
+        *self.in_flight.count.lock().unwrap() += 1;
+        HandlerGuard(self.in_flight.clone())
+    }
+
+    /// Hands an error that has no caller to return to over to the user's handler.
+    fn report(&self, error: Error) {
+        if let Some(handler) = &self.error_handler {
+            handler.invoke(error);
+        }
+    }
+
+    /// The error for a request whose response channel closed without an answer.
+    fn dropped(&self, action: &str) -> Error {
+        match self.closed.lock().unwrap().as_ref() {
+            Some(reason) => Error::new(format!("{action} failed: {reason}")),
+            None => Error::new(format!("{action} failed")),
+        }
+    }
+
+    fn clear_pending(&self) {
+        self.call_requests.lock().unwrap().clear();
+        self.register_requests.lock().unwrap().clear();
+        self.unregister_requests.lock().unwrap().clear();
+        self.publish_requests.lock().unwrap().clear();
+        self.subscribe_requests.lock().unwrap().clear();
+        self.unsubscribe_requests.lock().unwrap().clear();
+    }
+}
+
 impl Session {
     pub fn new(details: SessionDetails, peer: Box<dyn Peer>, serializer: Box<dyn Serializer>) -> Self {
+        Self::with_options(details, peer, serializer, SessionOptions::default())
+    }
+
+    pub(crate) fn with_options(
+        details: SessionDetails,
+        peer: Box<dyn Peer>,
+        serializer: Box<dyn Serializer>,
+        options: SessionOptions,
+    ) -> Self {
         let stored_serializer = Arc::new(serializer);
         let thread_serializer = stored_serializer.clone();
 
-        let stored_state = Arc::new(State::default());
+        let stored_state = Arc::new(State {
+            dispatcher: match options.manual_dispatch {
+                true => Dispatcher::manual(),
+                false => Dispatcher::new(options.max_concurrent_invocations),
+            },
+            error_handler: options.error_handler,
+            message_handler: options.message_handler,
+            revoked_handler: options.revoked_handler,
+            event_buffer_size: options.event_buffer_size.unwrap_or(DEFAULT_EVENT_BUFFER_SIZE),
+            call_buffer_size: options.call_buffer_size.unwrap_or(DEFAULT_CALL_BUFFER_SIZE),
+            strict_uris: options.strict_uris,
+            ..Default::default()
+        });
         let thread_state = stored_state.clone();
 
         let stored_peer = Arc::new(peer);
         let thread_peer = stored_peer.clone();
 
         let (goodbye_sender, goodbye_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel();
-        let (exit_sender, exit_receiver): (mpsc::Sender<()>, mpsc::Receiver<()>) = mpsc::channel();
+        let (exit_sender, exit_receiver): (mpsc::Sender<GoodbyeInfo>, mpsc::Receiver<GoodbyeInfo>) = mpsc::channel();
 
-        thread::spawn(move || {
+        let reader = thread::spawn(move || {
+            let mut reason = "session disconnected".to_string();
             while let Ok(payload) = thread_peer.read() {
                 match thread_serializer.deserialize(payload) {
                     Ok(msg) => {
@@ -103,22 +334,46 @@ impl Session {
                         );
                     }
                     Err(e) => {
-                        eprintln!("Error: {e}");
+                        reason = format!("session disconnected: protocol error: failed to parse message: {e}");
+                        thread_state.report(Error::new(format!("failed to parse message: {e}")));
                         break;
                     }
                 }
             }
+
+            // the connection is gone, close every pending request so that
+            // waiting callers return an error instead of blocking forever.
+            *thread_state.closed.lock().unwrap() = Some(reason);
+            thread_state.clear_pending();
+            thread_state.dispatcher.close();
+            if *thread_state.goodbye_sent.lock().unwrap() {
+                // the router closed the connection without answering our GOODBYE,
+                // don't keep leave() waiting for it.
+                _ = goodbye_sender.send(());
+            }
+            _ = exit_sender.send(GoodbyeInfo {
+                reason: "wamp.error.connection_lost".to_string(),
+                details: Default::default(),
+            });
+        });
+
+        let closer = Arc::new(Closer {
+            state: stored_state.clone(),
+            peer: stored_peer.clone(),
+            reader: Mutex::new(Some(reader)),
         });
 
         Self {
-            _details: details,
+            details,
+            subprotocol: options.subprotocol.clone(),
             peer: stored_peer,
             serializer: stored_serializer,
-            idgen: SessionScopeIDGenerator::new(),
+            idgen: Arc::new(SessionScopeIDGenerator::new()),
 
             state: stored_state,
-            goodbye_receiver_channel: Mutex::new(goodbye_receiver),
-            exist_receiver_channel: Mutex::new(exit_receiver),
+            goodbye_receiver_channel: Arc::new(Mutex::new(goodbye_receiver)),
+            exist_receiver_channel: Arc::new(Mutex::new(exit_receiver)),
+            closer: Some(closer),
         }
     }
 
@@ -128,14 +383,23 @@ impl Session {
         serializer: Arc<Box<dyn Serializer>>,
         peer: Arc<Box<dyn Peer>>,
         goodbye_sender: mpsc::Sender<()>,
-        exist_sender: mpsc::Sender<()>,
+        exist_sender: mpsc::Sender<GoodbyeInfo>,
     ) {
+        if let Some(handler) = &state.message_handler {
+            handler.invoke(msg.as_ref());
+        }
+
         match msg.message_type() {
             MESSAGE_TYPE_REGISTERED => {
                 let registered = msg.as_any().downcast_ref::<Registered>().unwrap();
                 let mut register_requests = state.register_requests.lock().unwrap();
-                if let Some(callback) = register_requests.remove(&registered.request_id) {
-                    _ = callback.send(RegisterResponse {
+                if let Some(pending) = register_requests.remove(&registered.request_id) {
+                    state
+                        .registrations
+                        .lock()
+                        .unwrap()
+                        .insert(registered.registration_id, pending.registration);
+                    _ = pending.sender.send(RegisterResponse {
                         registration_id: registered.registration_id,
                         error: None,
                     });
@@ -146,62 +410,176 @@ impl Session {
                 let mut unregister_requests = state.unregister_requests.lock().unwrap();
                 if let Some(callback) = unregister_requests.remove(&unregistered.request_id) {
                     _ = callback.send(None);
+                } else if let Some((registration_id, reason)) =
+                    Revocation::from_details(&unregistered.details, "registration")
+                {
+                    // no request of ours, the router revoked the registration
+                    drop(unregister_requests);
+                    let removed = state.registrations.lock().unwrap().remove(&registration_id).is_some();
+                    if removed && let Some(handler) = &state.revoked_handler {
+                        handler.invoke(Revocation::Registration {
+                            registration_id,
+                            reason,
+                        });
+                    }
                 }
             }
             MESSAGE_TYPE_RESULT => {
                 let result = msg.as_any().downcast_ref::<Result_>().unwrap();
                 let mut call_requests = state.call_requests.lock().unwrap();
-                if let Some(callback) = call_requests.remove(&result.request_id) {
-                    _ = callback.send(CallResponse {
+
+                let progress = matches!(result.details.get("progress"), Some(Value::Bool(true)))
+                    && call_requests
+                        .get(&result.request_id)
+                        .is_some_and(|call| call.receive_progress);
+                let sender = match progress {
+                    true => call_requests.get(&result.request_id).map(|call| call.sender.clone()),
+                    false => call_requests.remove(&result.request_id).map(|call| call.sender),
+                };
+                drop(call_requests);
+
+                if let Some(sender) = sender {
+                    let response = CallResponse {
                         args: result.args.clone(),
                         kwargs: result.kwargs.clone(),
                         error: None,
+                    };
+                    _ = sender.send(match progress {
+                        true => CallUpdate::Progress(response),
+                        false => CallUpdate::Done(response),
                     });
                 }
             }
             MESSAGE_TYPE_INVOCATION => {
                 let invocation = msg.as_any().downcast_ref::<Invocation>().unwrap();
-                let registrations = state.registrations.lock().unwrap();
-                let callback = registrations.get(&invocation.registration_id).cloned();
-                if callback.is_none() {
+                if state.closing.load(Ordering::SeqCst) {
+                    let error = ErrorMsg {
+                        message_type: MESSAGE_TYPE_INVOCATION,
+                        request_id: invocation.request_id,
+                        details: Default::default(),
+                        uri: "wamp.error.unavailable".to_string(),
+                        args: None,
+                        kwargs: None,
+                    };
+                    if let Ok(to_send) = serializer.serialize(&error) {
+                        _ = peer.write(to_send);
+                    }
                     return;
                 }
 
+                // the lock is released right after the lookup, not held while dispatching
+                let Some((callback, result_options)) = state
+                    .registrations
+                    .lock()
+                    .unwrap()
+                    .get(&invocation.registration_id)
+                    .map(|registration| (registration.callback.clone(), registration.result_options.clone()))
+                else {
+                    return;
+                };
+
                 let inv = XInvocation {
                     args: invocation.args.clone().map_or_else(Default::default, |args| args),
                     kwargs: invocation.kwargs.clone().map_or_else(Default::default, |kwargs| kwargs),
                     details: invocation.details.clone(),
+                    interrupt: InterruptToken::default(),
                 };
 
                 let request_id = invocation.request_id;
-                let callback = callback.unwrap();
-                thread::spawn(move || {
-                    let response = callback(inv);
-                    let yield_ = Yield {
-                        request_id,
-                        options: Default::default(),
-                        args: Some(response.args),
-                        kwargs: Some(response.kwargs),
+                let interrupt = inv.interrupt_token();
+                state.invocations.lock().unwrap().insert(request_id, interrupt.clone());
+                let finished = state.handler_started();
+                let task_state = state.clone();
+                let registration_id = invocation.registration_id;
+                state.dispatcher.spawn(move || {
+                    let _finished = finished;
+                    // started here rather than on the reader, a pool may have queued the handler
+                    let span = Span::invocation(registration_id, request_id);
+                    let response = span.in_scope(|| callback.invoke(inv));
+                    task_state.invocations.lock().unwrap().remove(&request_id);
+
+                    // the dealer has already dropped an interrupted invocation, so
+                    // only let it know the cancellation went through.
+                    let mut outcome = Outcome::Error;
+                    let serialized = if interrupt.is_cancelled() {
+                        serializer.serialize(&ErrorMsg {
+                            message_type: MESSAGE_TYPE_INVOCATION,
+                            request_id,
+                            details: Default::default(),
+                            uri: "wamp.error.canceled".to_string(),
+                            args: None,
+                            kwargs: None,
+                        })
+                    } else if let Err(e) = validate_ppt(&result_options, &response.args, &response.kwargs) {
+                        task_state.report(Error::new(format!("invalid invocation result: {e}")));
+                        serializer.serialize(&ErrorMsg {
+                            message_type: MESSAGE_TYPE_INVOCATION,
+                            request_id,
+                            details: Default::default(),
+                            uri: "wamp.error.invalid_argument".to_string(),
+                            args: None,
+                            kwargs: None,
+                        })
+                    } else {
+                        outcome = Outcome::Ok;
+                        let (args, kwargs) = payload(&result_options, response.args, response.kwargs);
+                        serializer.serialize(&Yield {
+                            request_id,
+                            options: result_options,
+                            args,
+                            kwargs,
+                        })
                     };
 
-                    match serializer.serialize(&yield_) {
-                        Ok(to_send) => match peer.write(to_send) {
-                            Ok(()) => {}
-                            Err(e) => {
-                                eprintln!("Error sending message: {e}");
+                    let sent = serialized
+                        .map_err(|e| Error::new(format!("failed to serialize message: {e}")))
+                        .and_then(|to_send| {
+                            peer.write(to_send)
+                                .map_err(|e| Error::new(format!("failed to send invocation result: {e}")))
+                        });
+                    if let Err(e) = sent {
+                        // e.g. a YIELD above the transport's size limit, answer with an
+                        // ERROR so the caller isn't left waiting for a result
+                        let fallback = matches!(outcome, Outcome::Ok).then(|| ErrorMsg {
+                            message_type: MESSAGE_TYPE_INVOCATION,
+                            request_id,
+                            details: Default::default(),
+                            uri: "wamp.error.runtime_error".to_string(),
+                            args: Some(vec![Value::Str(e.to_string())]),
+                            kwargs: None,
+                        });
+                        task_state.report(e);
+
+                        if let Some(fallback) = fallback {
+                            outcome = Outcome::Error;
+                            let sent = serializer
+                                .serialize(&fallback)
+                                .map_err(|e| Error::new(format!("failed to serialize message: {e}")))
+                                .and_then(|to_send| peer.write(to_send));
+                            if let Err(e) = sent {
+                                task_state.report(Error::new(format!("failed to send invocation error: {e}")));
                             }
-                        },
-                        Err(e) => {
-                            eprintln!("Error sending message: {e}");
                         }
                     }
+                    span.finish(outcome);
                 });
             }
+            MESSAGE_TYPE_INTERRUPT => {
+                let interrupt = msg.as_any().downcast_ref::<Interrupt>().unwrap();
+                if let Some(token) = state.invocations.lock().unwrap().remove(&interrupt.request_id) {
+                    token.cancel();
+                }
+            }
             MESSAGE_TYPE_SUBSCRIBED => {
                 let subscribed = msg.as_any().downcast_ref::<Subscribed>().unwrap();
                 let mut subscribe_requests = state.subscribe_requests.lock().unwrap();
-                if let Some(callback) = subscribe_requests.remove(&subscribed.request_id) {
-                    _ = callback.send(SubscribeResponse {
+                if let Some(pending) = subscribe_requests.remove(&subscribed.request_id) {
+                    state
+                        .subscriptions
+                        .lock()
+                        .unwrap()
+                        .insert(subscribed.subscription_id, pending.subscription);
+                    _ = pending.sender.send(SubscribeResponse {
                         subscription_id: subscribed.subscription_id,
                         error: None,
                     });
@@ -212,6 +590,18 @@ impl Session {
                 let mut unsubscribe_requests = state.unsubscribe_requests.lock().unwrap();
                 if let Some(callback) = unsubscribe_requests.remove(&unsubscribed.request_id) {
                     _ = callback.send(None);
+                } else if let Some((subscription_id, reason)) =
+                    Revocation::from_details(&unsubscribed.details, "subscription")
+                {
+                    // no request of ours, the router revoked the subscription
+                    drop(unsubscribe_requests);
+                    let removed = state.subscriptions.lock().unwrap().remove(&subscription_id).is_some();
+                    if removed && let Some(handler) = &state.revoked_handler {
+                        handler.invoke(Revocation::Subscription {
+                            subscription_id,
+                            reason,
+                        });
+                    }
                 }
             }
             MESSAGE_TYPE_PUBLISHED => {
@@ -223,17 +613,37 @@ impl Session {
             }
             MESSAGE_TYPE_EVENT => {
                 let event = msg.as_any().downcast_ref::<Event>().unwrap();
+                if state.closing.load(Ordering::SeqCst) {
+                    return;
+                }
+
                 let subscriptions = state.subscriptions.lock().unwrap();
-                if let Some(callback) = subscriptions.get(&event.subscription_id) {
+                if let Some(subscription) = subscriptions.get(&event.subscription_id) {
                     let xevent = XEvent {
                         args: event.args.clone().map_or_else(Default::default, |args| args),
                         kwargs: event.kwargs.clone().map_or_else(Default::default, |kwargs| kwargs),
                         details: event.details.clone(),
+                        interrupt: Default::default(),
                     };
+                    if let Some(filter) = &subscription.filter
+                        && !filter.matches(&xevent)
+                    {
+                        return;
+                    }
+
+                    let finished = state.handler_started();
+                    if let Some(queue) = subscription.queue.clone() {
+                        // blocks while the buffer is full, so (un)subscribing must not be blocked meanwhile
+                        drop(subscriptions);
+                        _ = queue.send((xevent, finished));
+                        return;
+                    }
 
-                    let callback = *callback;
-                    thread::spawn(move || {
-                        callback(xevent);
+                    let callback = subscription.callback.clone();
+                    drop(subscriptions);
+                    state.dispatcher.spawn(move || {
+                        let _finished = finished;
+                        callback.invoke(xevent);
                     });
                 }
             }
@@ -241,9 +651,9 @@ impl Session {
                 let error = msg.as_any().downcast_ref::<ErrorMsg>().unwrap();
                 match error.message_type {
                     MESSAGE_TYPE_CALL => {
-                        let mut call_requests = state.call_requests.lock().unwrap();
-                        if let Some(response) = call_requests.remove(&error.request_id) {
-                            let _ = response.send(CallResponse {
+                        let call = state.call_requests.lock().unwrap().remove(&error.request_id);
+                        if let Some(call) = call {
+                            let _ = call.sender.send(CallUpdate::Done(CallResponse {
                                 args: None,
                                 kwargs: None,
                                 error: Some(WampError {
@@ -251,14 +661,14 @@ impl Session {
                                     args: error.args.clone(),
                                     kwargs: error.kwargs.clone(),
                                 }),
-                            });
+                            }));
                         }
                     }
 
                     MESSAGE_TYPE_REGISTER => {
                         let mut register_requests = state.register_requests.lock().unwrap();
-                        if let Some(response) = register_requests.remove(&error.request_id) {
-                            let _ = response.send(RegisterResponse {
+                        if let Some(pending) = register_requests.remove(&error.request_id) {
+                            let _ = pending.sender.send(RegisterResponse {
                                 registration_id: 0,
                                 error: Some(WampError {
                                     uri: error.uri.clone(),
@@ -282,8 +692,8 @@ impl Session {
 
                     MESSAGE_TYPE_SUBSCRIBE => {
                         let mut subscribe_requests = state.subscribe_requests.lock().unwrap();
-                        if let Some(response) = subscribe_requests.remove(&error.request_id) {
-                            let _ = response.send(SubscribeResponse {
+                        if let Some(pending) = subscribe_requests.remove(&error.request_id) {
+                            let _ = pending.sender.send(SubscribeResponse {
                                 subscription_id: 0,
                                 error: Some(WampError {
                                     uri: error.uri.clone(),
@@ -322,49 +732,264 @@ impl Session {
                 }
             }
             MESSAGE_TYPE_GOODBYE => {
+                let goodbye = msg.as_any().downcast_ref::<Goodbye>().unwrap();
                 let goodbye_was_sent = { state.goodbye_sent.lock().unwrap() };
                 if *goodbye_was_sent {
-                    goodbye_sender.send(()).unwrap();
+                    _ = goodbye_sender.send(());
                 }
 
-                exist_sender.send(()).unwrap();
+                // nobody may be listening anymore if the session was dropped
+                _ = exist_sender.send(GoodbyeInfo {
+                    reason: goodbye.reason.clone(),
+                    details: goodbye.details.clone(),
+                });
             }
             _ => {}
         }
     }
 
+    /// The details the router sent on join, including the features it supports.
+    pub fn details(&self) -> &SessionDetails {
+        &self.details
+    }
+
+    /// The kind of transport the session runs over, one of the `TRANSPORT_*` constants.
+    pub fn transport(&self) -> TransportType {
+        self.peer.kind()
+    }
+
+    /// The serializer negotiated for the session, e.g. `cbor`. Unknown for
+    /// sessions created through `Session::new` rather than a client.
+    pub fn serializer_name(&self) -> Option<String> {
+        self.subprotocol
+            .as_ref()
+            .map(|subprotocol| subprotocol.trim_start_matches("wamp.2.").to_string())
+    }
+
+    /// Sends a message as is, for message types or options the session has no
+    /// API for. The session does not track it, so any answer is only visible
+    /// through the client's `on_message` hook.
+    pub fn send_message(&self, msg: Box<dyn Message>) -> Result<(), Error> {
+        let to_send = self
+            .serializer
+            .serialize(msg.as_ref())
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+
+        self.peer
+            .write(to_send)
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
+
     pub fn call(&self, request: CallRequest) -> Result<CallResponse, Error> {
+        let span = Span::call(request.uri());
+        let timeout = request.timeout();
+        let handle = match self.call_with_handle(request) {
+            Ok(handle) => handle,
+            Err(e) => {
+                span.finish(Outcome::Error);
+                return Err(e);
+            }
+        };
+        span.record_request_id(handle.request_id);
+
+        let mut timed_out = false;
+        let result = match timeout {
+            Some(timeout) => span
+                .in_scope(|| handle.wait(Some(Instant::now() + timeout)))
+                .map_err(|e| {
+                    {
+                        let mut lock = self.state.call_requests.lock().unwrap();
+                        lock.remove(&handle.request_id)
+                    };
+                    match e {
+                        mpsc::RecvTimeoutError::Timeout => {
+                            timed_out = true;
+                            if self.details.supports("call_canceling") {
+                                _ = self.send_cancel(handle.request_id, CancelMode::KillNoWait);
+                            }
+                            Error::new(format!("call timed out after {}ms", timeout.as_millis()))
+                        }
+                        mpsc::RecvTimeoutError::Disconnected => self.state.dropped("call"),
+                    }
+                }),
+            None => span.in_scope(|| handle.wait(None)).map_err(|_| {
+                {
+                    let mut lock = self.state.call_requests.lock().unwrap();
+                    lock.remove(&handle.request_id)
+                };
+                self.state.dropped("call")
+            }),
+        };
+
+        span.finish(if timed_out {
+            Outcome::Timeout
+        } else {
+            Outcome::of(&result)
+        });
+        result
+    }
+
+    /// Calls the procedure and returns its first positional result as `T`. An
+    /// error response, a result without arguments and one that doesn't
+    /// deserialize into `T` all return `Err`.
+    pub fn call_typed<T: DeserializeOwned>(&self, request: CallRequest) -> Result<T, Error> {
+        self.call(request)?
+            .into_result()
+            .map_err(|e| Error::new(format!("call failed: {e}")))?
+            .arg0()
+    }
+
+    /// Sends the call and returns as soon as it is written, without waiting
+    /// for a response. Only meant for procedures that are known not to reply;
+    /// this is not a publish, the dealer still routes it to a single callee.
+    /// Should a RESULT or ERROR arrive after all it is dropped, and with it
+    /// any error the call ran into. If the callee never answers, the dealer
+    /// may keep the request open on its side until the session ends.
+    pub fn call_no_result(&self, request: CallRequest) -> Result<(), Error> {
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
+        let msg = request.into_call(self.idgen.next_id());
+        validate_uri(&msg.procedure, &msg.options, self.state.strict_uris)?;
+
+        let to_send = self
+            .serializer
+            .serialize(&msg)
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+        self.peer
+            .write(to_send)
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
+
+    /// Sends the call and returns a handle that exposes the request id and can
+    /// be used to cancel the call before waiting for its response.
+    pub fn call_with_handle(&self, request: CallRequest) -> Result<CallHandle<'_>, Error> {
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
         let request_id = self.idgen.next_id();
-        let msg = request.to_call(request_id);
+        let progress = request.progress();
+        let receive_progress = request.wants_progress();
+        if receive_progress {
+            self.details.require("progressive_call_results")?;
+        }
+        let msg = request.into_call(request_id);
+        validate_uri(&msg.procedure, &msg.options, self.state.strict_uris)?;
 
-        let (sender, receiver): (mpsc::Sender<CallResponse>, mpsc::Receiver<CallResponse>) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(self.state.call_buffer_size.max(1));
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         {
             let mut lock = self.state.call_requests.lock().unwrap();
-            lock.insert(request_id, sender)
+            lock.insert(
+                request_id,
+                PendingCall {
+                    sender,
+                    receive_progress,
+                },
+            )
+        };
+
+        if let Err(e) = self.peer.write(to_send) {
+            self.state.call_requests.lock().unwrap().remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
+
+        Ok(CallHandle {
+            session: self,
+            request_id,
+            receiver,
+            progress,
+        })
+    }
+
+    /// Asks the dealer to cancel an in-flight call. With `CancelMode::Skip` the
+    /// pending call is resolved right away, otherwise it is resolved by the
+    /// ERROR the dealer sends once the cancellation went through. Fails without
+    /// touching the call if the dealer didn't advertise `call_canceling`.
+    pub fn cancel(&self, request_id: i64, mode: CancelMode) -> Result<(), Error> {
+        self.details.require("call_canceling")?;
+        if mode == CancelMode::Skip {
+            let call = self.state.call_requests.lock().unwrap().remove(&request_id);
+            if let Some(call) = call {
+                // the caller may be the one to drain a full buffer, it then sees the call as dropped
+                _ = call.sender.try_send(CallUpdate::Done(CallResponse {
+                    args: None,
+                    kwargs: None,
+                    error: Some(WampError {
+                        uri: "wamp.error.canceled".to_string(),
+                        args: None,
+                        kwargs: None,
+                    }),
+                }));
+            }
+        }
+
+        self.send_cancel(request_id, mode)
+    }
+
+    fn send_cancel(&self, request_id: i64, mode: CancelMode) -> Result<(), Error> {
+        let msg = Cancel {
+            request_id,
+            options: HashMap::from([("mode".to_string(), mode.as_str().into())]),
         };
 
+        let to_send = self
+            .serializer
+            .serialize(&msg)
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
+
         self.peer
             .write(to_send)
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
-        let response = receiver.recv().map_err(|e| {
-            {
-                let mut lock = self.state.call_requests.lock().unwrap();
-                lock.remove(&request_id)
-            };
-            Error::new(format!("call failed: {e}"))
-        })?;
+            .map_err(|e| Error::new(format!("failed to send message: {e}")))
+    }
 
+    pub fn publish(&self, request: PublishRequest) -> Result<Option<PublishResponse>, Error> {
+        let (_, response) = self.publish_with_id(request)?;
         Ok(response)
     }
 
-    pub fn publish(&self, request: PublishRequest) -> Result<Option<PublishResponse>, Error> {
+    /// Like `publish`, but also returns the request id used for the PUBLISH,
+    /// e.g. for correlating it in logs. `call_with_handle` does the same for calls.
+    pub fn publish_with_id(&self, request: PublishRequest) -> Result<(i64, Option<PublishResponse>), Error> {
+        let (request_id, pending) = self.send_publish(request)?;
+        match pending {
+            Some(pending) => {
+                let response = self.wait_acknowledge(request_id, pending)?;
+                Ok((request_id, Some(response)))
+            }
+            None => Ok((request_id, None)),
+        }
+    }
+
+    /// Publishes all requests back to back without waiting for acknowledgements
+    /// in between, then collects the acknowledgements. The results are in the
+    /// same order as `requests`, so one failed publish doesn't hide the outcome
+    /// of the others.
+    pub fn publish_batch(&self, requests: Vec<PublishRequest>) -> Vec<Result<Option<PublishResponse>, Error>> {
+        let pending: Vec<_> = requests.into_iter().map(|request| self.send_publish(request)).collect();
+
+        pending
+            .into_iter()
+            .map(|sent| match sent {
+                Ok((request_id, Some(pending))) => self.wait_acknowledge(request_id, pending).map(Some),
+                Ok((_, None)) => Ok(None),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Writes the PUBLISH and, if it asked for an acknowledgement, returns
+    /// the receiver the PUBLISHED (or ERROR) is delivered to along with how
+    /// long to wait for it.
+    fn send_publish(&self, request: PublishRequest) -> Result<(i64, Option<PendingPublish>), Error> {
+        let timeout = request.timeout().unwrap_or(DEFAULT_ACKNOWLEDGE_TIMEOUT);
+        let request = request.encode_payload()?;
+        request.validate_ppt()?;
         let request_id = self.idgen.next_id();
-        let msg = request.to_publish(request_id);
+        let msg = request.into_publish(request_id);
+        validate_uri(&msg.topic, &msg.options, self.state.strict_uris)?;
 
         let acknowledge = {
             if let Some(Value::Bool(acknowledge)) = msg.options.get("acknowledge") {
@@ -377,10 +1002,11 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
         if acknowledge {
-            let (sender, receiver): (mpsc::Sender<PublishResponse>, mpsc::Receiver<PublishResponse>) = mpsc::channel();
+            let (sender, receiver): (mpsc::SyncSender<PublishResponse>, mpsc::Receiver<PublishResponse>) =
+                mpsc::sync_channel(1);
             {
                 let mut lock = self.state.publish_requests.lock().unwrap();
                 lock.insert(request_id, sender)
@@ -389,19 +1015,31 @@ impl Session {
             self.peer
                 .write(to_send)
                 .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
-            let response = receiver
-                .recv()
-                .map_err(|e| Error::new(format!("publish failed: {e}")))?;
-            Ok(Some(response))
+            Ok((request_id, Some((receiver, timeout))))
         } else {
             self.peer
                 .write(to_send)
                 .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
-            Ok(None)
+            Ok((request_id, None))
         }
     }
 
+    fn wait_acknowledge(&self, request_id: i64, (receiver, timeout): PendingPublish) -> Result<PublishResponse, Error> {
+        receiver.recv_timeout(timeout).map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => {
+                self.state.publish_requests.lock().unwrap().remove(&request_id);
+                Error::new("publish acknowledgement timed out")
+            }
+            mpsc::RecvTimeoutError::Disconnected => self.state.dropped("publish"),
+        })
+    }
+
     pub fn register(&self, request: RegisterRequest) -> Result<RegisterResponse, Error> {
+        validate_uri(&request.procedure(), request.options(), self.state.strict_uris)?;
+        if match_policy(request.options()) != "exact" {
+            self.details.require("pattern_based_registration")?;
+        }
+        validate_ppt_options(request.result_options())?;
         let request_id = self.idgen.next_id();
         let msg = Register {
             request_id,
@@ -409,32 +1047,54 @@ impl Session {
             procedure: request.procedure(),
         };
 
-        let (sender, receiver): (mpsc::Sender<RegisterResponse>, mpsc::Receiver<RegisterResponse>) = mpsc::channel();
+        let (sender, receiver): (mpsc::SyncSender<RegisterResponse>, mpsc::Receiver<RegisterResponse>) =
+            mpsc::sync_channel(1);
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
+        let registration = Registration {
+            procedure: request.procedure(),
+            callback: request.callback(),
+            result_options: request.result_options().clone(),
+        };
         {
             let mut lock = self.state.register_requests.lock().unwrap();
-            lock.insert(request_id, sender)
+            lock.insert(request_id, PendingRegister { sender, registration })
         };
 
-        self.peer
-            .write(to_send)
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
+        if let Err(e) = self.peer.write(to_send) {
+            self.state.register_requests.lock().unwrap().remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
         let response = receiver
             .recv()
             .map_err(|e| Error::new(format!("register failed: {e}")))?;
-        self.state
-            .registrations
-            .lock()
-            .unwrap()
-            .insert(response.registration_id, request.callback());
         Ok(response)
     }
 
     pub fn subscribe(&self, request: SubscribeRequest) -> Result<SubscribeResponse, Error> {
+        validate_uri(&request.topic(), request.options(), self.state.strict_uris)?;
+        if match_policy(request.options()) != "exact" {
+            self.details.require("pattern_based_subscription")?;
+        }
+        let callback = request.callback();
+        // manually dispatched events already run one after another
+        let ordered = request.is_ordered() && !self.state.dispatcher.is_manual();
+        let queue = ordered.then(|| {
+            let (sender, receiver) = mpsc::sync_channel(self.state.event_buffer_size);
+            let callback = callback.clone();
+            // holds no reference to the state, so it ends once the subscription
+            // and with it the sender is dropped
+            thread::spawn(move || {
+                for (event, _finished) in receiver {
+                    callback.invoke(event);
+                }
+            });
+            sender
+        });
+
         let request_id = self.idgen.next_id();
         let msg = Subscribe {
             request_id,
@@ -442,31 +1102,87 @@ impl Session {
             topic: request.topic(),
         };
 
-        let (sender, receiver): (mpsc::Sender<SubscribeResponse>, mpsc::Receiver<SubscribeResponse>) = mpsc::channel();
+        let (sender, receiver): (mpsc::SyncSender<SubscribeResponse>, mpsc::Receiver<SubscribeResponse>) =
+            mpsc::sync_channel(1);
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
 
+        let subscription = Subscription {
+            spec: SubscriptionSpec {
+                topic: request.topic(),
+                options: request.options().clone(),
+                ordered: request.is_ordered(),
+            },
+            callback,
+            queue,
+            filter: request.event_filter(),
+        };
         {
             let mut lock = self.state.subscribe_requests.lock().unwrap();
-            lock.insert(request_id, sender)
+            lock.insert(request_id, PendingSubscribe { sender, subscription })
         };
 
-        self.peer
-            .write(to_send)
-            .map_err(|e| Error::new(format!("failed to send message: {e}")))?;
+        if let Err(e) = self.peer.write(to_send) {
+            self.state.subscribe_requests.lock().unwrap().remove(&request_id);
+            return Err(Error::new(format!("failed to send message: {e}")));
+        }
         let response = receiver
             .recv()
             .map_err(|e| Error::new(format!("subscribe failed: {e}")))?;
-        self.state
-            .subscriptions
-            .lock()
-            .unwrap()
-            .insert(response.subscription_id, request.callback());
         Ok(response)
     }
 
+    /// The registrations the session currently holds as `(registration id,
+    /// procedure)`, ordered by id. Only reflects what the router confirmed,
+    /// e.g. for a health endpoint.
+    pub fn registrations(&self) -> Vec<(i64, String)> {
+        let registrations = self.state.registrations.lock().unwrap();
+        let mut listed: Vec<(i64, String)> = registrations
+            .iter()
+            .map(|(id, registration)| (*id, registration.procedure.clone()))
+            .collect();
+        listed.sort();
+        listed
+    }
+
+    /// The subscriptions the session currently holds as `(subscription id,
+    /// topic)`, ordered by id.
+    pub fn subscriptions(&self) -> Vec<(i64, String)> {
+        let subscriptions = self.state.subscriptions.lock().unwrap();
+        let mut listed: Vec<(i64, String)> = subscriptions
+            .iter()
+            .map(|(id, subscription)| (*id, subscription.spec.topic.clone()))
+            .collect();
+        listed.sort();
+        listed
+    }
+
+    /// The current subscriptions of the session, to subscribe them again on a
+    /// new session with `restore`.
+    pub fn registry(&self) -> SubscriptionRegistry {
+        let mut registry = SubscriptionRegistry::new();
+        for subscription in self.state.subscriptions.lock().unwrap().values() {
+            registry.insert(
+                subscription.spec.clone(),
+                subscription.callback.clone(),
+                subscription.filter.clone(),
+            );
+        }
+        registry
+    }
+
+    /// Subscribes everything in `registry`, returning one result per entry in
+    /// the registry's order.
+    pub fn restore(&self, registry: &SubscriptionRegistry) -> Vec<Result<SubscribeResponse, Error>> {
+        registry
+            .requests()
+            .into_iter()
+            .map(|request| self.subscribe(request))
+            .collect()
+    }
+
     pub fn leave(&self) -> Result<(), Error> {
         let msg = Goodbye {
             details: Default::default(),
@@ -476,7 +1192,7 @@ impl Session {
         let to_send = self
             .serializer
             .serialize(&msg)
-            .map_err(|e| Error::new(format!("proto failed to parse message: {e}")))?;
+            .map_err(|e| Error::new(format!("failed to serialize message: {e}")))?;
         {
             let mut sent = self.state.goodbye_sent.lock().unwrap();
             *sent = true;
@@ -492,7 +1208,666 @@ impl Session {
             .map_err(|e| Error::new(format!("leave failed: {e}")))
     }
 
-    pub fn wait_disconnect(&self) {
-        self.exist_receiver_channel.lock().unwrap().recv().unwrap();
+    /// Leaves the session once the work already accepted is done. New
+    /// invocations are answered with `wamp.error.unavailable` and new events
+    /// are dropped, while the running handlers get up to `timeout` to finish.
+    /// Writes are sent in order, so everything the handlers wrote goes out
+    /// ahead of the GOODBYE.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), Error> {
+        self.state.closing.store(true, Ordering::SeqCst);
+
+        let in_flight = &self.state.in_flight;
+        let count = in_flight.count.lock().unwrap();
+        _ = in_flight
+            .idle
+            .wait_timeout_while(count, timeout, |count| *count > 0)
+            .unwrap();
+
+        self.leave()
+    }
+
+    /// Closes the connection without leaving the realm and returns once the
+    /// reader thread has stopped. Requests still waiting for an answer fail.
+    /// Call `leave` first to end the session with a GOODBYE. Dropping the last
+    /// clone of the session does the same, but a handler that holds a clone
+    /// keeps the session alive.
+    pub fn close(&self) {
+        if let Some(closer) = &self.closer {
+            closer.close();
+        }
+    }
+
+    /// Runs the next queued invocation or event handler on the calling thread
+    /// and returns true, or returns false right away if none is waiting.
+    /// Handlers are only queued if the client was built with
+    /// `manual_dispatch`, otherwise this always returns false.
+    pub fn poll_once(&self) -> bool {
+        self.state.dispatcher.poll_once()
+    }
+
+    /// Runs queued invocation and event handlers on the calling thread as
+    /// they come in, until the connection is gone. Returns right away unless
+    /// the client was built with `manual_dispatch`.
+    pub fn run(&self) {
+        self.state.dispatcher.run();
+    }
+
+    /// Calls `heartbeat.procedure` every `heartbeat.interval` on a background
+    /// thread and closes the connection when a call goes unanswered, which
+    /// ends the session like a dropped connection would. Complements the
+    /// transport keepalive with a check that RPCs make it through the router.
+    pub fn enable_heartbeat(&self, heartbeat: Heartbeat) {
+        let mut session = self.clone();
+        session.closer = None;
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(heartbeat.interval);
+                if session.is_done() {
+                    return;
+                }
+
+                if let Err(e) = session.call(heartbeat.request()) {
+                    if !session.is_done() {
+                        session
+                            .state
+                            .report(Error::new(format!("heartbeat failed, closing the connection: {e}")));
+                        session.peer.close();
+                    }
+                    return;
+                }
+            }
+        });
+    }
+
+    fn is_done(&self) -> bool {
+        self.state.closing.load(Ordering::SeqCst) || self.state.closed.lock().unwrap().is_some()
+    }
+
+    /// Blocks until the router ends the session and returns the reason it gave.
+    pub fn wait_disconnect(&self) -> GoodbyeInfo {
+        self.exist_receiver_channel.lock().unwrap().recv().unwrap_or_default()
+    }
+
+    /// Like `wait_disconnect` but gives up after `timeout`, returns whether the
+    /// session disconnected in time.
+    pub fn wait_disconnect_timeout(&self, timeout: Duration) -> bool {
+        match self.exist_receiver_channel.lock().unwrap().recv_timeout(timeout) {
+            Ok(_) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
+            Err(mpsc::RecvTimeoutError::Timeout) => false,
+        }
+    }
+}
+
+/// An in-flight call returned by `Session::call_with_handle`.
+pub struct CallHandle<'a> {
+    session: &'a Session,
+    request_id: i64,
+    receiver: mpsc::Receiver<CallUpdate>,
+    progress: Option<ProgressFn>,
+}
+
+impl CallHandle<'_> {
+    pub fn request_id(&self) -> i64 {
+        self.request_id
+    }
+
+    pub fn cancel(&self, mode: CancelMode) -> Result<(), Error> {
+        self.session.cancel(self.request_id, mode)
+    }
+
+    /// Waits for the final response, invoking the progress callback for
+    /// every progressive result before it on the way.
+    pub fn response(self) -> Result<CallResponse, Error> {
+        self.wait(None).map_err(|_| {
+            {
+                let mut lock = self.session.state.call_requests.lock().unwrap();
+                lock.remove(&self.request_id)
+            };
+            self.session.state.dropped("call")
+        })
+    }
+
+    fn wait(&self, deadline: Option<Instant>) -> Result<CallResponse, mpsc::RecvTimeoutError> {
+        loop {
+            let update = match deadline {
+                Some(deadline) => self
+                    .receiver
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))?,
+                None => self.receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)?,
+            };
+            match update {
+                CallUpdate::Progress(response) => {
+                    if let Some(callback) = &self.progress {
+                        callback.invoke(response);
+                    }
+                }
+                CallUpdate::Done(response) => return Ok(response),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::types::{InvokePolicy, PayloadSerializer, Yield as XYield};
+    use crate::sync::memory::MemoryPeer;
+    use crate::sync::memory::router::RouterStub;
+    use wampproto::messages::call::Call;
+    use wampproto::serializers::json::JSONSerializer;
+
+    fn failing(_: XInvocation) -> XYield {
+        panic!("handler failed")
+    }
+
+    #[test]
+    fn shutdown_does_not_wait_for_a_handler_that_panicked() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+        callee.register(RegisterRequest::new("io.xconn.fail", failing)).unwrap();
+
+        // no result ever comes back, the timeout only gives the handler time to run
+        let request = CallRequest::new("io.xconn.fail").with_timeout(Duration::from_millis(200));
+        assert!(caller.call(request).is_err());
+
+        let started = Instant::now();
+        callee.shutdown(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn call_delivers_every_progressive_result() {
+        let (client, router) = MemoryPeer::pair();
+        let details = SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+        let session = Session::new(details, client, Box::new(JSONSerializer {}));
+
+        let callee = thread::spawn(move || {
+            let serializer = JSONSerializer {};
+            let msg = serializer.deserialize(router.read().unwrap()).unwrap();
+            let request_id = msg.as_any().downcast_ref::<Call>().unwrap().request_id;
+
+            for chunk in 0..3 {
+                let result = Result_ {
+                    request_id,
+                    details: HashMap::from([("progress".to_string(), Value::Bool(true))]),
+                    args: Some(vec![Value::Int(chunk)]),
+                    kwargs: None,
+                };
+                router.write(serializer.serialize(&result).unwrap()).unwrap();
+            }
+
+            let result = Result_ {
+                request_id,
+                details: Default::default(),
+                args: Some(vec![Value::Str("done".to_string())]),
+                kwargs: None,
+            };
+            router.write(serializer.serialize(&result).unwrap()).unwrap();
+            router
+        });
+
+        let chunks = Arc::new(Mutex::new(Vec::new()));
+        let received = chunks.clone();
+        let request = CallRequest::new("io.xconn.progress")
+            .on_progress(move |response| received.lock().unwrap().push(response.args.unwrap()));
+        let response = session.call(request).unwrap();
+        let _router = callee.join().unwrap();
+
+        assert_eq!(response.args, Some(vec![Value::Str("done".to_string())]));
+        assert_eq!(
+            *chunks.lock().unwrap(),
+            vec![vec![Value::Int(0)], vec![Value::Int(1)], vec![Value::Int(2)]]
+        );
+    }
+
+    #[test]
+    fn progress_callback_can_start_another_call() {
+        let (client, router) = MemoryPeer::pair();
+        let details = SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+        let session = Arc::new(Session::new(details, client, Box::new(JSONSerializer {})));
+
+        let callee = thread::spawn(move || {
+            let serializer = JSONSerializer {};
+            let msg = serializer.deserialize(router.read().unwrap()).unwrap();
+            let request_id = msg.as_any().downcast_ref::<Call>().unwrap().request_id;
+
+            for details in [
+                HashMap::from([("progress".to_string(), Value::Bool(true))]),
+                HashMap::new(),
+            ] {
+                let result = Result_ {
+                    request_id,
+                    details,
+                    args: None,
+                    kwargs: None,
+                };
+                router.write(serializer.serialize(&result).unwrap()).unwrap();
+            }
+            router
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let weak = Arc::downgrade(&session);
+        let request = CallRequest::new("io.xconn.progress").on_progress(move |_| {
+            let started = weak
+                .upgrade()
+                .map(|session| session.call_with_handle(CallRequest::new("io.xconn.other")).is_ok());
+            _ = sender.send(started);
+        });
+        let caller = {
+            let session = session.clone();
+            thread::spawn(move || session.call(request))
+        };
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Some(true)));
+        assert!(caller.join().unwrap().is_ok());
+        let _router = callee.join().unwrap();
+    }
+
+    #[test]
+    fn progress_callback_runs_on_the_calling_thread() {
+        let (client, router) = MemoryPeer::pair();
+        let details = SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+        let options = SessionOptions {
+            call_buffer_size: Some(1),
+            ..Default::default()
+        };
+        let session = Session::with_options(details, client, Box::new(JSONSerializer {}), options);
+
+        let callee = thread::spawn(move || {
+            let serializer = JSONSerializer {};
+            let msg = serializer.deserialize(router.read().unwrap()).unwrap();
+            let request_id = msg.as_any().downcast_ref::<Call>().unwrap().request_id;
+
+            // more than the buffer holds, the reader has to wait for the callback
+            for chunk in 0..5 {
+                let result = Result_ {
+                    request_id,
+                    details: HashMap::from([("progress".to_string(), Value::Bool(true))]),
+                    args: Some(vec![Value::Int(chunk)]),
+                    kwargs: None,
+                };
+                router.write(serializer.serialize(&result).unwrap()).unwrap();
+            }
+
+            let result = Result_ {
+                request_id,
+                details: Default::default(),
+                args: None,
+                kwargs: None,
+            };
+            router.write(serializer.serialize(&result).unwrap()).unwrap();
+            router
+        });
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let seen = threads.clone();
+        let request = CallRequest::new("io.xconn.progress").on_progress(move |_| {
+            thread::sleep(Duration::from_millis(10));
+            seen.lock().unwrap().push(thread::current().id());
+        });
+        session.call(request.with_timeout(Duration::from_secs(5))).unwrap();
+        let _router = callee.join().unwrap();
+
+        assert_eq!(*threads.lock().unwrap(), vec![thread::current().id(); 5]);
+    }
+
+    #[test]
+    fn publish_sends_its_payload_with_the_payload_serializer() {
+        let router = RouterStub::new();
+        let publisher = router.session();
+        let request = PublishRequest::new("io.xconn.topic")
+            .arg("hello")
+            .kwarg("key", 1)
+            .payload_serializer(PayloadSerializer::Json)
+            .acknowledge();
+        publisher.publish(request).unwrap();
+
+        let received = router.received();
+        let (_, publish) = received.iter().find(|(_, msg)| msg[0] == 16).unwrap();
+        assert_eq!(publish[2]["ppt_serializer"], "json");
+        assert_eq!(publish[2]["ppt_scheme"], "wamp");
+        // the encoded payload is the only argument, without kwargs
+        assert_eq!(publish.as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn manual_dispatch_runs_one_queued_handler_per_poll() {
+        let router = RouterStub::new();
+        let session = router.session_with(SessionOptions {
+            manual_dispatch: true,
+            ..Default::default()
+        });
+        let peer = Arc::new(router.session());
+
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let seen = handled.clone();
+        let on_event = move |event: XEvent| seen.lock().unwrap().push(event.args);
+        session
+            .subscribe(SubscribeRequest::new("io.xconn.topic", on_event))
+            .unwrap();
+        let seen = handled.clone();
+        let echo = RegisterRequest::new("io.xconn.echo", move |inv: XInvocation| {
+            seen.lock().unwrap().push(inv.args.clone());
+            XYield::new(inv.args, inv.kwargs)
+        });
+        session.register(echo).unwrap();
+
+        for n in 0..2 {
+            peer.publish(PublishRequest::new("io.xconn.topic").arg(n).acknowledge())
+                .unwrap();
+        }
+        let caller = {
+            let peer = peer.clone();
+            thread::spawn(move || {
+                peer.call(
+                    CallRequest::new("io.xconn.echo")
+                        .arg(2)
+                        .with_timeout(Duration::from_secs(5)),
+                )
+            })
+        };
+
+        for expected in 1..=3 {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !session.poll_once() {
+                assert!(Instant::now() < deadline, "handler {expected} was never queued");
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(handled.lock().unwrap().len(), expected);
+        }
+        assert!(!session.poll_once());
+
+        let response = caller.join().unwrap().unwrap();
+        assert_eq!(response.args, Some(vec![Value::Int(2)]));
+        assert_eq!(
+            *handled.lock().unwrap(),
+            vec![vec![Value::Int(0)], vec![Value::Int(1)], vec![Value::Int(2)]]
+        );
+    }
+
+    #[test]
+    fn leave_returns_when_the_router_closes_without_goodbye() {
+        let (client, router) = MemoryPeer::pair();
+        let details = SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+        let session = Arc::new(Session::new(details, client, Box::new(JSONSerializer {})));
+
+        let waiter = {
+            let session = session.clone();
+            thread::spawn(move || session.wait_disconnect_timeout(Duration::from_secs(5)))
+        };
+        let closer = thread::spawn(move || {
+            let msg = JSONSerializer {}.deserialize(router.read().unwrap()).unwrap();
+            assert_eq!(msg.message_type(), MESSAGE_TYPE_GOODBYE);
+            // closes the connection instead of answering
+            drop(router);
+        });
+
+        session.leave().unwrap();
+        closer.join().unwrap();
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn goodbye_arriving_while_the_session_is_dropped_does_not_panic_the_reader() {
+        let (client, router) = MemoryPeer::pair();
+        let details = SessionDetails::new(1, "realm1".to_string(), "test".to_string(), "anonymous".to_string());
+
+        // holds the GOODBYE on the reader until the session is being dropped
+        let (entered, reader_entered) = mpsc::channel();
+        let entered = Mutex::new(entered);
+        let hold_goodbye = move |msg: &dyn Message| {
+            if msg.message_type() == MESSAGE_TYPE_GOODBYE {
+                _ = entered.lock().unwrap().send(());
+                thread::sleep(Duration::from_millis(100));
+            }
+        };
+        let options = SessionOptions {
+            message_handler: Some(MessageFn(Arc::new(hold_goodbye))),
+            ..Default::default()
+        };
+        let session = Session::with_options(details, client, Box::new(JSONSerializer {}), options);
+        let state = session.state.clone();
+
+        let goodbye = Goodbye {
+            details: Default::default(),
+            reason: "wamp.close.system_shutdown".to_string(),
+        };
+        router.write(JSONSerializer {}.serialize(&goodbye).unwrap()).unwrap();
+        reader_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(session);
+
+        // only set once the reader got past the GOODBYE and wound down
+        assert!(state.closed.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn callee_sees_the_disclosed_caller() {
+        fn reply_caller(inv: XInvocation) -> XYield {
+            XYield::new(inv.caller().into_iter().map(Value::Int).collect(), Default::default())
+        }
+
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+        let disclosed = RegisterRequest::new("io.xconn.disclosed", reply_caller).disclose_caller(true);
+        callee.register(disclosed).unwrap();
+        callee
+            .register(RegisterRequest::new("io.xconn.plain", reply_caller))
+            .unwrap();
+
+        let caller_id = Some(vec![Value::Int(caller.details().id())]);
+        for (procedure, disclose_me) in [("io.xconn.disclosed", false), ("io.xconn.plain", true)] {
+            let request = CallRequest::new(procedure)
+                .disclose_me(disclose_me)
+                .with_timeout(Duration::from_secs(5));
+            assert_eq!(caller.call(request).unwrap().args, caller_id, "{procedure}");
+        }
+
+        let request = CallRequest::new("io.xconn.plain").with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).unwrap();
+        assert!(response.args.unwrap_or_default().is_empty());
+    }
+
+    #[test]
+    fn router_revocations_drop_the_handler_and_are_reported() {
+        let router = RouterStub::new();
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let session = router.session_with(SessionOptions {
+            revoked_handler: Some(RevokedFn(Arc::new(move |revocation| {
+                _ = sender.lock().unwrap().send(revocation);
+            }))),
+            ..Default::default()
+        });
+        let echo = RegisterRequest::new("io.xconn.echo", |inv: XInvocation| XYield::new(inv.args, inv.kwargs));
+        let registered = session.register(echo).unwrap();
+        let subscribed = session
+            .subscribe(SubscribeRequest::new("io.xconn.topic", |_: XEvent| {}))
+            .unwrap();
+
+        router.revoke(registered.registration_id, "wamp.error.testing");
+        match receiver.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Revocation::Registration {
+                registration_id,
+                reason,
+            } => {
+                assert_eq!(registration_id, registered.registration_id);
+                assert_eq!(reason.as_deref(), Some("wamp.error.testing"));
+            }
+            revocation => panic!("unexpected {revocation:?}"),
+        }
+        assert!(session.registrations().is_empty());
+
+        router.revoke(subscribed.subscription_id, "wamp.error.testing");
+        match receiver.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Revocation::Subscription { subscription_id, .. } => assert_eq!(subscription_id, subscribed.subscription_id),
+            revocation => panic!("unexpected {revocation:?}"),
+        }
+        assert!(session.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn calls_right_after_register_always_reach_the_handler() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+
+        for n in 0..100 {
+            let procedure = format!("io.xconn.stress.{n}");
+            let echo = RegisterRequest::new(procedure.clone(), |inv: XInvocation| XYield::new(inv.args, inv.kwargs));
+            callee.register(echo).unwrap();
+
+            let request = CallRequest::new(procedure).arg(n).with_timeout(Duration::from_secs(5));
+            let response = caller.call(request).unwrap();
+            assert_eq!(response.args, Some(vec![Value::Int(i64::from(n))]), "call {n}");
+        }
+    }
+
+    /// Fails to write the first YIELD, as if the connection hiccuped right then.
+    #[derive(Debug)]
+    struct FailingYield {
+        inner: Box<dyn Peer>,
+        failed: AtomicBool,
+    }
+
+    impl Peer for FailingYield {
+        fn kind(&self) -> TransportType {
+            self.inner.kind()
+        }
+
+        fn read(&self) -> Result<Vec<u8>, Error> {
+            self.inner.read()
+        }
+
+        fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+            self.inner.read_timeout(timeout)
+        }
+
+        fn write(&self, data: Vec<u8>) -> Result<(), Error> {
+            if data.starts_with(b"[70,") && !self.failed.swap(true, Ordering::SeqCst) {
+                return Err(Error::new("simulated write failure"));
+            }
+            self.inner.write(data)
+        }
+
+        fn close(&self) {
+            self.inner.close()
+        }
+    }
+
+    #[test]
+    fn yield_that_fails_to_send_is_answered_with_an_error() {
+        let router = RouterStub::new();
+        let (details, peer) = router.connect();
+        let peer = Box::new(FailingYield {
+            inner: peer,
+            failed: AtomicBool::new(false),
+        });
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let options = SessionOptions {
+            error_handler: Some(ErrorFn(Arc::new(move |e: Error| {
+                _ = sender.lock().unwrap().send(e.to_string());
+            }))),
+            ..Default::default()
+        };
+        let callee = Session::with_options(details, peer, Box::new(JSONSerializer {}), options);
+        let caller = router.session();
+        let echo = RegisterRequest::new("io.xconn.echo", |inv: XInvocation| XYield::new(inv.args, inv.kwargs));
+        callee.register(echo).unwrap();
+
+        let request = CallRequest::new("io.xconn.echo")
+            .arg("hello")
+            .with_timeout(Duration::from_secs(5));
+        let response = caller.call(request).unwrap();
+        assert_eq!(response.error.unwrap().uri, "wamp.error.runtime_error");
+        let reported = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(reported.contains("simulated write failure"), "{reported}");
+    }
+
+    #[test]
+    fn prefix_subscription_receives_events_for_sub_topics() {
+        let router = RouterStub::new();
+        let subscriber = router.session();
+        let publisher = router.session();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let on_event = move |event: XEvent| {
+            _ = sender.lock().unwrap().send(event.topic());
+        };
+        subscriber
+            .subscribe(SubscribeRequest::new("io.xconn.", on_event).match_prefix())
+            .unwrap();
+
+        for topic in ["io.other.topic", "io.xconn.a", "io.xconn.b.c"] {
+            publisher.publish(PublishRequest::new(topic).acknowledge()).unwrap();
+        }
+
+        let topics: Vec<_> = (0..2)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert_eq!(
+            topics,
+            vec![Some("io.xconn.a".to_string()), Some("io.xconn.b.c".to_string())]
+        );
+    }
+
+    #[test]
+    fn roundrobin_calls_alternate_between_shared_callees() {
+        let router = RouterStub::new();
+        let callees = [router.session(), router.session()];
+        for callee in &callees {
+            let id = callee.details().id();
+            let request = RegisterRequest::new("io.xconn.shared", move |_: XInvocation| {
+                XYield::new(vec![Value::Int(id)], Default::default())
+            })
+            .invoke_policy(InvokePolicy::RoundRobin);
+            callee.register(request).unwrap();
+        }
+
+        let caller = router.session();
+        let served: Vec<_> = (0..4)
+            .map(|_| {
+                let request = CallRequest::new("io.xconn.shared").with_timeout(Duration::from_secs(5));
+                caller.call(request).unwrap().args.unwrap()
+            })
+            .collect();
+        let [first, second] = callees.map(|callee| vec![Value::Int(callee.details().id())]);
+        assert_eq!(served, vec![first.clone(), second.clone(), first, second]);
+    }
+
+    #[test]
+    fn call_timeout_cancels_the_call_at_the_dealer() {
+        let router = RouterStub::new();
+        let callee = router.session();
+        let caller = router.session();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let wait_for_interrupt = move |inv: XInvocation| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !inv.is_cancelled() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            _ = sender.lock().unwrap().send(inv.is_cancelled());
+            XYield::new(vec![], Default::default())
+        };
+        callee
+            .register(RegisterRequest::new("io.xconn.slow", wait_for_interrupt))
+            .unwrap();
+
+        let request = CallRequest::new("io.xconn.slow").with_timeout(Duration::from_millis(100));
+        let err = caller.call(request).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        let received = router.received();
+        let (_, cancel) = received.iter().find(|(_, msg)| msg[0] == 49).unwrap();
+        assert_eq!(cancel[2]["mode"], "killnowait");
+        assert!(caller.state.call_requests.lock().unwrap().is_empty());
     }
 }
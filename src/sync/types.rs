@@ -1,21 +1,62 @@
+use crate::common::ppt::{PPT_CIPHER, PPT_KEYID, PPT_SCHEME, PPT_SERIALIZER};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
-pub type EventFn = fn(Event);
-pub type RegisterFn = fn(Invocation) -> Yield;
+type EventCallbackType = dyn Fn(Event) + Send + Sync;
+type RegisterCallbackType = dyn Fn(Invocation) -> Yield + Send + Sync;
+
+#[derive(Clone)]
+pub struct EventFn(pub Arc<EventCallbackType>);
+
+impl fmt::Debug for EventFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<EventFn>")
+    }
+}
+
+impl EventFn {
+    pub fn invoke(&self, event: Event) {
+        self.0(event)
+    }
+}
+
+#[derive(Clone)]
+pub struct RegisterFn(pub Arc<RegisterCallbackType>);
+
+impl fmt::Debug for RegisterFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<RegisterFn>")
+    }
+}
+
+impl RegisterFn {
+    pub fn invoke(&self, inv: Invocation) -> Yield {
+        self.0(inv)
+    }
+}
 
 #[derive(Debug)]
 pub struct SubscribeRequest {
     topic: String,
     options: HashMap<String, Value>,
     callback: EventFn,
+    ordered: bool,
+    filter: Option<EventFilterFn>,
 }
 
 impl SubscribeRequest {
-    pub fn new<S: Into<String>>(topic: S, callback: EventFn) -> Self {
+    pub fn new<S, F>(topic: S, callback: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(Event) + Send + Sync + 'static,
+    {
         Self {
             topic: topic.into(),
             options: Default::default(),
-            callback,
+            callback: EventFn(Arc::new(callback)),
+            ordered: false,
+            filter: None,
         }
     }
 
@@ -29,6 +70,45 @@ impl SubscribeRequest {
         self
     }
 
+    /// Subscribes to every topic starting with the given topic.
+    pub fn match_prefix(self) -> Self {
+        self.with_option("match", "prefix")
+    }
+
+    /// Treats empty URI components of the topic as wildcards, e.g.
+    /// `com..update` matches `com.user.update`.
+    pub fn match_wildcard(self) -> Self {
+        self.with_option("match", "wildcard")
+    }
+
+    /// Delivers events one at a time in the order they arrive instead of
+    /// running a handler per event concurrently. A slow handler then delays
+    /// all later events of this subscription, so only use it when order matters.
+    pub fn ordered(mut self) -> Self {
+        self.ordered = true;
+        self
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    /// Only hands events to the handler for which `filter` returns true, the
+    /// rest are dropped before a handler is dispatched for them. The filter
+    /// runs on the session's reader thread for every event of the subscription,
+    /// so it must be cheap and must not block.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Event) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(EventFilterFn(Arc::new(filter)));
+        self
+    }
+
+    pub fn event_filter(&self) -> Option<EventFilterFn> {
+        self.filter.clone()
+    }
+
     pub fn options(&self) -> &HashMap<String, Value> {
         &self.options
     }
@@ -38,7 +118,7 @@ impl SubscribeRequest {
     }
 
     pub fn callback(&self) -> EventFn {
-        self.callback
+        self.callback.clone()
     }
 }
 
@@ -46,16 +126,22 @@ impl SubscribeRequest {
 pub struct RegisterRequest {
     procedure: String,
     options: HashMap<String, Value>,
+    result_options: HashMap<String, Value>,
 
     callback: RegisterFn,
 }
 
 impl RegisterRequest {
-    pub fn new<S: Into<String>>(procedure: S, callback: RegisterFn) -> Self {
+    pub fn new<S, F>(procedure: S, callback: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(Invocation) -> Yield + Send + Sync + 'static,
+    {
         Self {
             procedure: procedure.into(),
             options: Default::default(),
-            callback,
+            result_options: Default::default(),
+            callback: RegisterFn(Arc::new(callback)),
         }
     }
 
@@ -69,16 +155,126 @@ impl RegisterRequest {
         self
     }
 
+    /// Asks the dealer to include the caller's identity in invocation details.
+    pub fn disclose_caller(self, disclose_caller: bool) -> Self {
+        self.with_option("disclose_caller", Value::Bool(disclose_caller))
+    }
+
+    /// Lets other sessions register the same procedure, with calls being
+    /// distributed among them according to `policy`.
+    pub fn invoke_policy(self, policy: InvokePolicy) -> Self {
+        self.with_option("invoke", policy.as_str())
+    }
+
+    /// Registers every procedure starting with the given procedure.
+    pub fn match_prefix(self) -> Self {
+        self.with_option("match", "prefix")
+    }
+
+    /// Treats empty URI components of the procedure as wildcards.
+    pub fn match_wildcard(self) -> Self {
+        self.with_option("match", "wildcard")
+    }
+
+    /// Marks the results of the procedure as passthrough (PPT) payloads, sent
+    /// to the caller untouched. Every result must then be exactly one argument
+    /// without kwargs; a result that isn't is answered with
+    /// `wamp.error.invalid_argument`. See `CallRequest::ppt_scheme` for the
+    /// accepted schemes. These options go on the YIELD, not the REGISTER.
+    pub fn ppt_scheme(mut self, scheme: &str) -> Self {
+        self.result_options.insert(PPT_SCHEME.to_string(), scheme.into());
+        self
+    }
+
+    /// The serializer the passthrough results are encoded with, e.g. `cbor`.
+    pub fn ppt_serializer(mut self, serializer: &str) -> Self {
+        self.result_options
+            .insert(PPT_SERIALIZER.to_string(), serializer.into());
+        self
+    }
+
+    /// The cipher the passthrough results are encrypted with. Requires
+    /// `ppt_serializer`.
+    pub fn ppt_cipher(mut self, cipher: &str) -> Self {
+        self.result_options.insert(PPT_CIPHER.to_string(), cipher.into());
+        self
+    }
+
+    /// Identifies the key the passthrough results are encrypted with.
+    /// Requires `ppt_serializer`.
+    pub fn ppt_keyid(mut self, keyid: &str) -> Self {
+        self.result_options.insert(PPT_KEYID.to_string(), keyid.into());
+        self
+    }
+
     pub fn options(&self) -> &HashMap<String, Value> {
         &self.options
     }
 
+    /// Options sent along with every result of the procedure.
+    pub fn result_options(&self) -> &HashMap<String, Value> {
+        &self.result_options
+    }
+
     pub fn procedure(&self) -> String {
         self.procedure.clone()
     }
 
     pub fn callback(&self) -> RegisterFn {
-        self.callback
+        self.callback.clone()
+    }
+}
+
+/// Subscriptions kept apart from any session, to subscribe them again on a
+/// new one after a manual rejoin. Take one from `Session::registry` and hand
+/// it to `Session::restore`; the router assigns fresh subscription ids, the
+/// handlers stay the same. Only the specs serialize, after loading them the
+/// handlers are supplied again through `add`.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionRegistry {
+    // the filter is only known for entries taken from a session
+    entries: Vec<(SubscriptionSpec, EventFn, Option<EventFilterFn>)>,
+}
+
+impl SubscriptionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<F>(&mut self, spec: SubscriptionSpec, callback: F)
+    where
+        F: Fn(Event) + Send + Sync + 'static,
+    {
+        self.entries.push((spec, EventFn(Arc::new(callback)), None));
+    }
+
+    pub(crate) fn insert(&mut self, spec: SubscriptionSpec, callback: EventFn, filter: Option<EventFilterFn>) {
+        self.entries.push((spec, callback, filter));
+    }
+
+    pub fn specs(&self) -> Vec<SubscriptionSpec> {
+        self.entries.iter().map(|(spec, ..)| spec.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn requests(&self) -> Vec<SubscribeRequest> {
+        self.entries
+            .iter()
+            .map(|(spec, callback, filter)| SubscribeRequest {
+                topic: spec.topic.clone(),
+                options: spec.options.clone(),
+                callback: callback.clone(),
+                ordered: spec.ordered,
+                filter: filter.clone(),
+            })
+            .collect()
     }
 }
 
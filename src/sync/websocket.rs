@@ -1,12 +1,16 @@
-use crate::common::types::{Error, TRANSPORT_WEB_SOCKET, TransportType};
+use crate::common::types::{Error, KeepAlive, TRANSPORT_WEB_SOCKET, TransportType};
 use crate::sync::peer::Peer;
-use mio::net::TcpStream as MioTcpStream;
+use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Token};
 use std::fmt::Debug;
-use std::net::TcpStream;
+use std::io::ErrorKind;
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
-use tungstenite::protocol::Role;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
 use tungstenite::{Bytes, Message, Utf8Bytes, WebSocket};
 
 const CLIENT: Token = Token(0);
@@ -17,6 +21,11 @@ pub struct WebSocketPeer {
     reader: Arc<Mutex<mpsc::Receiver<Message>>>,
     writer: Arc<mpsc::Sender<Message>>,
     binary: bool,
+    // shut down on close, which wakes up and ends the reader thread
+    stream: Arc<TcpStream>,
+    read_timeout: Arc<Mutex<Option<Duration>>>,
+    // why the background threads gave up, returned by read once they did
+    failure: Arc<Mutex<Option<String>>>,
 }
 
 impl Peer for WebSocketPeer {
@@ -25,9 +34,23 @@ impl Peer for WebSocketPeer {
     }
 
     fn read(&self) -> Result<Vec<u8>, Error> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        if let Some(timeout) = timeout {
+            return self.read_timeout(timeout);
+        }
+
         let reader = self.reader.lock().unwrap();
-        let msg = reader.recv().map_err(|e| Error::new(format!("read error: {e}")))?;
-        Ok(msg.into_data().to_vec())
+        let msg = reader.recv().map_err(|_| self.closed())?;
+        self.payload(msg)
+    }
+
+    fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let reader = self.reader.lock().unwrap();
+        let msg = reader.recv_timeout(timeout).map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => Error::new(format!("read timed out after {}ms", timeout.as_millis())),
+            mpsc::RecvTimeoutError::Disconnected => self.closed(),
+        })?;
+        self.payload(msg)
     }
 
     fn write(&self, data: Vec<u8>) -> Result<(), Error> {
@@ -44,17 +67,60 @@ impl Peer for WebSocketPeer {
             Ok(())
         }
     }
+
+    fn close(&self) {
+        _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+
+    // writes are queued for the writer thread and never block, so there is
+    // nothing to bound here; a stalled router is caught by the keepalive
 }
 
 impl WebSocketPeer {
-    pub fn try_new(stream: TcpStream, binary: bool) -> Result<Box<dyn Peer>, Error> {
-        let stream_copy = stream
+    /// The error for a read after the reader thread ended.
+    fn closed(&self) -> Error {
+        match self.failure.lock().unwrap().as_ref() {
+            Some(reason) => Error::new(format!("read error: {reason}")),
+            None => Error::new("read error: connection closed"),
+        }
+    }
+
+    /// Only accepts frames of the type the serializer writes, a router that
+    /// mixes them up would otherwise feed the deserializer garbage.
+    fn payload(&self, msg: Message) -> Result<Vec<u8>, Error> {
+        match msg {
+            Message::Text(text) if !self.binary => Ok(text.as_bytes().to_vec()),
+            Message::Binary(data) if self.binary => Ok(data.to_vec()),
+            Message::Text(_) => Err(Error::new("received a text frame on a binary session")),
+            _ => Err(Error::new("received a binary frame on a text session")),
+        }
+    }
+
+    pub fn try_new(
+        ws: WebSocket<MaybeTlsStream<TcpStream>>,
+        binary: bool,
+        keepalive: Option<KeepAlive>,
+    ) -> Result<Box<dyn Peer>, Error> {
+        let stream = tcp_stream(ws.get_ref())?;
+        let keepalive_stream = stream
+            .try_clone()
+            .map_err(|e| Error::new(format!("clone error: {e}")))?;
+        let close_stream = stream
             .try_clone()
             .map_err(|e| Error::new(format!("clone error: {e}")))?;
-        let mio_stream = MioTcpStream::from_std(stream_copy);
-        let mut mio_stream_b = MioTcpStream::from_std(stream);
+        let writer_stream = stream
+            .try_clone()
+            .map_err(|e| Error::new(format!("clone error: {e}")))?;
+        let raw_fd = extract_raw_fd(ws.get_ref())?;
+        stream
+            .set_nonblocking(true)
+            .map_err(|e| Error::new(format!("failed to set non-blocking: {e}")))?;
 
-        let ws = WebSocket::from_raw_socket(mio_stream, Role::Client, None);
         let ws_conn = Arc::new(Mutex::new(ws));
         let ws_writer = Arc::clone(&ws_conn);
         let ws_reader = Arc::clone(&ws_conn);
@@ -64,47 +130,148 @@ impl WebSocketPeer {
 
         let mut poll = Poll::new().map_err(|e| Error::new(format!("poll error: {e}")))?;
         poll.registry()
-            .register(&mut mio_stream_b, CLIENT, Interest::READABLE | Interest::WRITABLE)
+            .register(&mut SourceFd(&raw_fd), CLIENT, Interest::READABLE | Interest::WRITABLE)
             .map_err(|e| Error::new(format!("register error: {e}")))?;
         let mut events = Events::with_capacity(1024);
 
+        let missed_pongs = Arc::new(AtomicU32::new(0));
+        let reader_missed_pongs = missed_pongs.clone();
+
+        let failure: Arc<Mutex<Option<String>>> = Default::default();
+        let reader_failure = failure.clone();
+        let writer_failure = failure.clone();
+
         thread::spawn(move || {
-            loop {
-                poll.poll(&mut events, None).unwrap();
+            'reader: loop {
+                if let Err(e) = poll.poll(&mut events, None) {
+                    if e.kind() == ErrorKind::Interrupted {
+                        continue;
+                    }
+
+                    fail(&reader_failure, format!("poll error: {e}"));
+                    break;
+                }
+
                 for event in events.iter() {
-                    if event.token() == CLIENT && event.is_readable() {
+                    if event.token() != CLIENT {
+                        continue;
+                    }
+
+                    if event.is_writable() {
+                        // finish writes that previously hit WouldBlock
+                        match ws_reader.lock().unwrap().flush() {
+                            Ok(()) => {}
+                            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                            Err(e) => {
+                                fail(&reader_failure, format!("flush error: {e}"));
+                                break 'reader;
+                            }
+                        }
+                    }
+
+                    if !event.is_readable() {
+                        continue;
+                    }
+
+                    // readiness is edge-triggered, so drain everything that is
+                    // buffered before waiting for the next event.
+                    loop {
                         let msg_result = {
                             let mut sock = ws_reader.lock().unwrap();
                             sock.read()
                         };
 
                         match msg_result {
-                            Ok(msg) => background_writer.send(msg).unwrap(),
+                            Ok(msg @ (Message::Text(_) | Message::Binary(_))) => {
+                                if background_writer.send(msg).is_err() {
+                                    break 'reader;
+                                }
+                            }
+                            // tungstenite queues the pong itself and sends it with the next read/write
+                            Ok(Message::Ping(_)) => {}
+                            Ok(Message::Close(_)) => break 'reader,
+                            Ok(Message::Pong(_)) => reader_missed_pongs.store(0, Ordering::SeqCst),
+                            Ok(Message::Frame(_)) => {}
+                            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
                             Err(e) => {
-                                eprintln!("[Reader] Error: {e}");
-                                break;
+                                fail(&reader_failure, e.to_string());
+                                break 'reader;
                             }
                         }
                     }
                 }
             }
+
+            // dropping the sender closes the channel so that a pending
+            // `Peer::read` returns an error instead of blocking forever.
+            drop(background_writer);
         });
 
         thread::spawn(move || {
             for msg in background_reader {
                 let mut sock = ws_writer.lock().unwrap();
-                if let Err(e) = sock.send(msg) {
-                    eprintln!("[Writer] Error sending message: {e}");
-                    break;
+                match sock.send(msg) {
+                    Ok(()) => {}
+                    // the frame stays buffered and is flushed once the socket is writable
+                    Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => {
+                        // shutting down the socket ends the reader thread as well, so
+                        // the session learns about it instead of queueing writes forever
+                        fail(&writer_failure, format!("write error: {e}"));
+                        _ = writer_stream.shutdown(Shutdown::Both);
+                        break;
+                    }
                 }
             }
         });
 
+        if let Some(keepalive) = keepalive {
+            let ping_writer = front_writer.clone();
+            thread::spawn(move || {
+                loop {
+                    thread::sleep(keepalive.interval);
+                    if missed_pongs.fetch_add(1, Ordering::SeqCst) >= keepalive.max_missed_pongs {
+                        // shutting down the socket wakes up the reader thread, which
+                        // then closes the channel the session reads from.
+                        _ = keepalive_stream.shutdown(Shutdown::Both);
+                        break;
+                    }
+
+                    if ping_writer.send(Message::Ping(Bytes::new())).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
         Ok(Box::new(Self {
             kind: TRANSPORT_WEB_SOCKET,
             reader: Arc::new(Mutex::new(front_reader)),
             writer: Arc::new(front_writer),
             binary,
+            stream: Arc::new(close_stream),
+            read_timeout: Default::default(),
+            failure,
         }))
     }
 }
+
+/// Records why the connection ended, the first reason wins.
+fn fail(failure: &Mutex<Option<String>>, reason: String) {
+    failure.lock().unwrap().get_or_insert(reason);
+}
+
+/// Returns the tcp socket below the (possibly encrypted) stream.
+pub(crate) fn tcp_stream(stream: &MaybeTlsStream<TcpStream>) -> Result<&TcpStream, Error> {
+    match stream {
+        MaybeTlsStream::Plain(stream) => Ok(stream),
+        MaybeTlsStream::NativeTls(stream) => Ok(stream.get_ref()),
+        _ => Err(Error::new("unsupported websocket stream")),
+    }
+}
+
+/// Returns the fd of the tcp socket below the (possibly encrypted) stream so
+/// that it can be polled for readiness.
+fn extract_raw_fd(stream: &MaybeTlsStream<TcpStream>) -> Result<RawFd, Error> {
+    Ok(tcp_stream(stream)?.as_raw_fd())
+}